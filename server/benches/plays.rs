@@ -0,0 +1,112 @@
+// Play and search throughput through the router, alone and while other tasks
+// keep playing or searching, so lock contention between the two shows up.
+// Run with `cargo bench --bench plays`.
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use criterion::{Criterion, criterion_group, criterion_main};
+use music_types::Song;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::runtime::Runtime;
+use tower::ServiceExt;
+
+const SONGS: u64 = 10_000;
+const SEARCH: &str = "/songs/search?query=genre%3Arock%20AND%20plays%3E5&sort=plays";
+
+// A data directory with a synthetic songs.json
+fn data_dir() -> tempfile::TempDir {
+    const GENRES: [&str; 5] = ["Rock", "Pop", "Jazz", "Blues", "Folk"];
+    let songs: Vec<Song> = (1..=SONGS)
+        .map(|id| Song {
+            id,
+            uid: None,
+            title: format!("Song {}", id),
+            artist: format!("Artist {}", id % 97),
+            album: None,
+            genre: GENRES[id as usize % GENRES.len()].to_string(),
+            play_count: id % 20,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        })
+        .collect();
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("songs.json"),
+        serde_json::to_vec(&songs).unwrap(),
+    )
+    .unwrap();
+    dir
+}
+
+async fn get(app: &Router, uri: &str) {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+}
+
+// Keep `tasks` tasks sending requests (from `uri`) until the returned flag
+// is set
+fn load(runtime: &Runtime, app: &Router, tasks: usize, uri: fn(u64) -> String) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for n in 0..tasks {
+        let (app, stop) = (app.clone(), stop.clone());
+        runtime.spawn(async move {
+            let mut i = n as u64;
+            while !stop.load(Ordering::Relaxed) {
+                i += tasks as u64;
+                get(&app, &uri(i)).await;
+            }
+        });
+    }
+    stop
+}
+
+fn play_uri(i: u64) -> String {
+    format!("/songs/play/{}", i % SONGS + 1)
+}
+
+fn search_uri(_: u64) -> String {
+    SEARCH.to_string()
+}
+
+fn plays(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let dir = data_dir();
+    let app = runtime.block_on(async {
+        server::app(server::Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+    });
+    let background = std::thread::available_parallelism().map_or(4, |n| n.get()) / 2;
+    let next = AtomicU64::new(0);
+
+    let mut group = c.benchmark_group("plays");
+    group.bench_function("play", |b| {
+        b.iter(|| runtime.block_on(get(&app, &play_uri(next.fetch_add(1, Ordering::Relaxed)))))
+    });
+    group.bench_function("search", |b| b.iter(|| runtime.block_on(get(&app, SEARCH))));
+
+    let stop = load(&runtime, &app, background, search_uri);
+    group.bench_function("play while searching", |b| {
+        b.iter(|| runtime.block_on(get(&app, &play_uri(next.fetch_add(1, Ordering::Relaxed)))))
+    });
+    stop.store(true, Ordering::Relaxed);
+
+    let stop = load(&runtime, &app, background, play_uri);
+    group.bench_function("search while playing", |b| {
+        b.iter(|| runtime.block_on(get(&app, SEARCH)))
+    });
+    stop.store(true, Ordering::Relaxed);
+    group.finish();
+}
+
+criterion_group!(benches, plays);
+criterion_main!(benches);
@@ -0,0 +1,81 @@
+use crate::{Endpoint, Error, NewLibraryRequest, NewSongRequest, Song, SongSearchQuery, error};
+use serde::de::DeserializeOwned;
+
+// Blocking client with the same methods as `crate::Client`. Like
+// `reqwest::blocking`, it must not be used from within an async runtime.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::blocking::Client,
+    endpoint: Endpoint,
+}
+
+impl Client {
+    // A client for the server at `base_url`, e.g. `http://localhost:8080`
+    pub fn new(base_url: &str) -> Client {
+        Client {
+            http: reqwest::blocking::Client::new(),
+            endpoint: Endpoint::new(base_url),
+        }
+    }
+
+    // Record changes made through this client as made by `user`
+    pub fn with_user(mut self, user: &str) -> Client {
+        self.endpoint.user = Some(user.to_string());
+        self
+    }
+
+    // A client for the songs of a named library
+    pub fn library(&self, name: &str) -> Client {
+        Client {
+            http: self.http.clone(),
+            endpoint: self.endpoint.library(name),
+        }
+    }
+
+    fn send<T: DeserializeOwned>(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<T, Error> {
+        let mut request = request.header(reqwest::header::ACCEPT, "application/json");
+        if let Some(user) = &self.endpoint.user {
+            request = request.header(crate::USER_HEADER, user);
+        }
+
+        let response = request.send()?;
+        let status = response.status();
+        let body = response.bytes()?;
+        error::decode(status, &body)
+    }
+
+    // POST /songs/new
+    pub fn add_song(&self, song: &NewSongRequest) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "new"])?;
+        self.send(self.http.post(url).json(song))
+    }
+
+    // GET /songs/search
+    pub fn search(&self, query: &SongSearchQuery) -> Result<Vec<Song>, Error> {
+        let url = self.endpoint.url(&["songs", "search"])?;
+        self.send(self.http.get(url).query(query))
+    }
+
+    // GET /songs/play/ID: count a play and return the updated song
+    pub fn play(&self, id: u64) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "play", &id.to_string()])?;
+        self.send(self.http.get(url))
+    }
+
+    // GET /songs/uid/UID
+    pub fn song_by_uid(&self, uid: &str) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "uid", uid])?;
+        self.send(self.http.get(url))
+    }
+
+    // POST /libraries: create a named library
+    pub fn create_library(&self, name: &str) -> Result<(), Error> {
+        let url = self.endpoint.root_url(&["libraries"])?;
+        let _: serde_json::Value =
+            self.send(self.http.post(url).json(&NewLibraryRequest { name }))?;
+        Ok(())
+    }
+}
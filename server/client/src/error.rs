@@ -0,0 +1,104 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+// Everything a request to the server can fail with
+#[derive(Debug)]
+pub enum Error {
+    // No song or library with the requested id or name
+    NotFound(String),
+    // The search expression could not be parsed
+    InvalidQuery {
+        message: String,
+        // 0-based character offset into the query
+        position: usize,
+    },
+    // The server rejected the request, e.g. an invalid library name
+    BadRequest(String),
+    // The request conflicts with existing data, e.g. a library name in use
+    Conflict(String),
+    // Any other error status
+    Status {
+        status: u16,
+        message: String,
+    },
+    // The base URL given to the client is not a valid http(s) URL
+    InvalidUrl(String),
+    // The server could not be reached, or the connection failed
+    Http(reqwest::Error),
+    // The response body was not what the endpoint returns
+    Decode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(message) => write!(f, "not found: {}", message),
+            Error::InvalidQuery { message, position } => {
+                write!(f, "invalid query at position {}: {}", position, message)
+            }
+            Error::BadRequest(message) => write!(f, "bad request: {}", message),
+            Error::Conflict(message) => write!(f, "conflict: {}", message),
+            Error::Status { status, message } => write!(f, "status {}: {}", status, message),
+            Error::InvalidUrl(e) => write!(f, "invalid URL: {}", e),
+            Error::Http(e) => write!(f, "request failed: {}", e),
+            Error::Decode(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+// Error body of the server's responses; query errors also carry a position
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: String,
+    position: Option<usize>,
+}
+
+// Turn a response into the endpoint's value or a typed error. Shared by the
+// async and blocking clients.
+pub(crate) fn decode<T: DeserializeOwned>(status: StatusCode, body: &[u8]) -> Result<T, Error> {
+    if status.is_success() {
+        return serde_json::from_slice(body).map_err(|e| {
+            // GET /songs/play/ID answers an unknown id with 200 and an error
+            // body, for compatibility with older clients
+            match serde_json::from_slice::<ErrorBody>(body) {
+                Ok(body) => Error::NotFound(body.error),
+                Err(_) => Error::Decode(e.to_string()),
+            }
+        });
+    }
+
+    let (message, position) = match serde_json::from_slice::<ErrorBody>(body) {
+        Ok(body) => (body.error, body.position),
+        // Rejections by the framework, e.g. a malformed query string, are
+        // plain text
+        Err(_) => (String::from_utf8_lossy(body).into_owned(), None),
+    };
+
+    Err(match (status, position) {
+        (StatusCode::BAD_REQUEST, Some(position)) => Error::InvalidQuery { message, position },
+        (StatusCode::BAD_REQUEST, None) => Error::BadRequest(message),
+        (StatusCode::NOT_FOUND, _) => Error::NotFound(message),
+        (StatusCode::CONFLICT, _) => Error::Conflict(message),
+        _ => Error::Status {
+            status: status.as_u16(),
+            message,
+        },
+    })
+}
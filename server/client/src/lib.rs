@@ -0,0 +1,155 @@
+// Typed client for the music library server:
+//
+//   let client = Client::new("http://localhost:8080").with_user("alice");
+//   let song = client.add_song(&NewSongRequest { .. }).await?;
+//   let rock = client.search(&SongSearchQuery {
+//       query: Some("genre:rock AND plays>5".to_string()),
+//       ..Default::default()
+//   }).await?;
+//
+// `blocking::Client` has the same methods for code without an async runtime.
+// Requests and responses use the server's own types (see `music-types`), and
+// error responses are mapped to `Error`.
+
+pub mod blocking;
+mod error;
+
+pub use error::Error;
+pub use music_types::{AudioFile, NewSongRequest, Song, SongSearchQuery, SongSort, SortOrder};
+
+use serde::Serialize;
+
+// Header naming the user that changes are recorded for
+const USER_HEADER: &str = "x-user";
+
+// Where requests go and who they are made for; shared by both clients
+#[derive(Debug, Clone)]
+struct Endpoint {
+    base_url: String,
+    // Path segments of a named library's routes, empty for the default library
+    library: Vec<String>,
+    user: Option<String>,
+}
+
+impl Endpoint {
+    fn new(base_url: &str) -> Endpoint {
+        Endpoint {
+            base_url: base_url.to_string(),
+            library: Vec::new(),
+            user: None,
+        }
+    }
+
+    // URL of a route of this endpoint's library. Each segment is
+    // percent-encoded, so ids and names can hold any character.
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url, Error> {
+        let library = self.library.iter().map(String::as_str);
+        self.build(library.chain(segments.iter().copied()))
+    }
+
+    // URL of a route that is not scoped to a library
+    fn root_url(&self, segments: &[&str]) -> Result<reqwest::Url, Error> {
+        self.build(segments.iter().copied())
+    }
+
+    fn build<'a>(&self, segments: impl Iterator<Item = &'a str>) -> Result<reqwest::Url, Error> {
+        let mut url = reqwest::Url::parse(&self.base_url)
+            .map_err(|e| Error::InvalidUrl(format!("{}: {}", self.base_url, e)))?;
+        url.path_segments_mut()
+            .map_err(|()| Error::InvalidUrl(format!("{}: not a base URL", self.base_url)))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn library(&self, name: &str) -> Endpoint {
+        Endpoint {
+            library: vec!["libraries".to_string(), name.to_string()],
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NewLibraryRequest<'a> {
+    name: &'a str,
+}
+
+// Async client; cheap to clone
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    endpoint: Endpoint,
+}
+
+impl Client {
+    // A client for the server at `base_url`, e.g. `http://localhost:8080`
+    pub fn new(base_url: &str) -> Client {
+        Client {
+            http: reqwest::Client::new(),
+            endpoint: Endpoint::new(base_url),
+        }
+    }
+
+    // Record changes made through this client as made by `user`
+    pub fn with_user(mut self, user: &str) -> Client {
+        self.endpoint.user = Some(user.to_string());
+        self
+    }
+
+    // A client for the songs of a named library
+    pub fn library(&self, name: &str) -> Client {
+        Client {
+            http: self.http.clone(),
+            endpoint: self.endpoint.library(name),
+        }
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, Error> {
+        let mut request = request.header(reqwest::header::ACCEPT, "application/json");
+        if let Some(user) = &self.endpoint.user {
+            request = request.header(USER_HEADER, user);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        error::decode(status, &body)
+    }
+
+    // POST /songs/new
+    pub async fn add_song(&self, song: &NewSongRequest) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "new"])?;
+        self.send(self.http.post(url).json(song)).await
+    }
+
+    // GET /songs/search
+    pub async fn search(&self, query: &SongSearchQuery) -> Result<Vec<Song>, Error> {
+        let url = self.endpoint.url(&["songs", "search"])?;
+        self.send(self.http.get(url).query(query)).await
+    }
+
+    // GET /songs/play/ID: count a play and return the updated song
+    pub async fn play(&self, id: u64) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "play", &id.to_string()])?;
+        self.send(self.http.get(url)).await
+    }
+
+    // GET /songs/uid/UID
+    pub async fn song_by_uid(&self, uid: &str) -> Result<Song, Error> {
+        let url = self.endpoint.url(&["songs", "uid", uid])?;
+        self.send(self.http.get(url)).await
+    }
+
+    // POST /libraries: create a named library
+    pub async fn create_library(&self, name: &str) -> Result<(), Error> {
+        let url = self.endpoint.root_url(&["libraries"])?;
+        let _: serde_json::Value = self
+            .send(self.http.post(url).json(&NewLibraryRequest { name }))
+            .await?;
+        Ok(())
+    }
+}
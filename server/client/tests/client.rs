@@ -0,0 +1,220 @@
+use music_client::{Error, NewSongRequest, SongSearchQuery, SongSort, blocking};
+use std::thread::JoinHandle;
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+
+// The server's router running in-process on a free port, with its data files
+// in a temporary directory. Dropping it stops the server and removes the
+// directory.
+struct TestServer {
+    url: String,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    _dir: TempDir,
+}
+
+impl TestServer {
+    fn start() -> TestServer {
+        let dir = tempfile::tempdir().unwrap();
+        let config = server::Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+        let (stop, stopped) = oneshot::channel();
+        let thread = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async {
+                let app = server::app(config);
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                addr_tx.send(listener.local_addr().unwrap()).unwrap();
+                tokio::select! {
+                    result = axum::serve(listener, app) => result.unwrap(),
+                    _ = stopped => {}
+                }
+            });
+            // Dropping the runtime cancels the server's background tasks
+        });
+
+        TestServer {
+            url: format!("http://{}", addr_rx.recv().unwrap()),
+            stop: Some(stop),
+            thread: Some(thread),
+            _dir: dir,
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.stop.take().unwrap().send(());
+        let _ = self.thread.take().unwrap().join();
+    }
+}
+
+fn song(title: &str, artist: &str, genre: &str) -> NewSongRequest {
+    NewSongRequest {
+        title: title.to_string(),
+        artist: artist.to_string(),
+        genre: genre.to_string(),
+        album: None,
+    }
+}
+
+fn query(query: &str) -> SongSearchQuery {
+    SongSearchQuery {
+        query: Some(query.to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn async_add_search_and_play() {
+    let server = TestServer::start();
+    let client = music_client::Client::new(&server.url).with_user("alice");
+
+    let added = client
+        .add_song(&song("Async Anthem", "The Futures", "Rock"))
+        .await
+        .unwrap();
+    assert_eq!(added.title, "Async Anthem");
+    assert_eq!(added.play_count, 0);
+
+    let played = client.play(added.id).await.unwrap();
+    let played = client.play(played.id).await.unwrap();
+    assert_eq!(played.play_count, 2);
+
+    let found = client
+        .search(&SongSearchQuery {
+            artist: Some("the futures".to_string()),
+            sort: Some(SongSort::Plays),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(found, vec![played]);
+}
+
+#[tokio::test]
+async fn async_errors() {
+    let server = TestServer::start();
+    let client = music_client::Client::new(&server.url);
+
+    assert!(matches!(
+        client.play(999_999).await,
+        Err(Error::NotFound(_))
+    ));
+    assert!(matches!(
+        client.song_by_uid("01NOSUCHSONG").await,
+        Err(Error::NotFound(_))
+    ));
+
+    match client.search(&query("genre:rock AND (")).await {
+        Err(Error::InvalidQuery { position, .. }) => assert_eq!(position, 16),
+        other => panic!("expected an invalid query error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn async_named_library() {
+    let server = TestServer::start();
+    let client = music_client::Client::new(&server.url);
+    client.create_library("async-jazz").await.unwrap();
+    assert!(matches!(
+        client.create_library("async-jazz").await,
+        Err(Error::Conflict(_))
+    ));
+    assert!(matches!(
+        client.create_library("Not Valid!").await,
+        Err(Error::BadRequest(_))
+    ));
+
+    let jazz = client.library("async-jazz");
+    let added = jazz
+        .add_song(&song("Blue Await", "Tokio Trio", "Jazz"))
+        .await
+        .unwrap();
+    assert_eq!(jazz.play(added.id).await.unwrap().play_count, 1);
+
+    let found = jazz.search(&query("title:\"blue await\"")).await.unwrap();
+    assert_eq!(found.len(), 1);
+    // Songs of a named library are not in the default one
+    assert!(
+        client
+            .search(&query("title:\"blue await\""))
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    assert!(matches!(
+        client.library("no-such-library").search(&query("")).await,
+        Err(Error::NotFound(_))
+    ));
+}
+
+#[test]
+fn blocking_add_search_and_play() {
+    let server = TestServer::start();
+    let client = blocking::Client::new(&server.url).with_user("bob");
+
+    let added = client
+        .add_song(&song("Blocking Blues", "Sync Sisters", "Blues"))
+        .unwrap();
+    let played = client.play(added.id).unwrap();
+    assert_eq!(played.play_count, 1);
+
+    let found = client.search(&query("artist:\"sync sisters\"")).unwrap();
+    assert_eq!(found, vec![played]);
+}
+
+#[test]
+fn blocking_errors() {
+    let server = TestServer::start();
+    let client = blocking::Client::new(&server.url);
+
+    assert!(matches!(client.play(999_999), Err(Error::NotFound(_))));
+    match client.search(&query("plays>many")) {
+        Err(Error::InvalidQuery { message, position }) => {
+            assert_eq!(message, "expected a number, found 'many'");
+            assert_eq!(position, 6);
+        }
+        other => panic!("expected an invalid query error, got {:?}", other),
+    }
+}
+
+#[test]
+fn blocking_named_library() {
+    let server = TestServer::start();
+    let client = blocking::Client::new(&server.url);
+    client.create_library("blocking-folk").unwrap();
+
+    let folk = client.library("blocking-folk");
+    let added = folk
+        .add_song(&song("Threadbare", "Mutex Minstrels", "Folk"))
+        .unwrap();
+    assert_eq!(folk.play(added.id).unwrap().play_count, 1);
+    assert!(matches!(folk.play(999_999), Err(Error::NotFound(_))));
+}
+
+#[test]
+fn path_segments_are_encoded() {
+    let server = TestServer::start();
+    let client = blocking::Client::new(&server.url);
+
+    // Reaches GET /songs/uid/UID rather than another route or a framework 404
+    match client.song_by_uid("a/b?c=1&d#e") {
+        Err(Error::NotFound(message)) => assert_eq!(message, "Song not found"),
+        other => panic!("expected the song lookup to miss, got {:?}", other),
+    }
+    match client.library("a/../b?c").search(&query("")) {
+        Err(Error::NotFound(message)) => assert_eq!(message, "Library not found"),
+        other => panic!("expected an unknown library, got {:?}", other),
+    }
+
+    assert!(matches!(
+        blocking::Client::new("not a url").play(1),
+        Err(Error::InvalidUrl(_))
+    ));
+}
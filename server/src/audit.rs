@@ -0,0 +1,757 @@
+use crate::health::Persistence;
+use crate::libraries::{self, DEFAULT_LIBRARY};
+use crate::library::{self, Library};
+use crate::negotiate::{Format, Negotiated};
+use crate::playlists::Playlist;
+use crate::ratings::{RATINGS_FILE, UserRating, of_songs, refresh_song_stats, restore};
+use crate::webhooks::Event;
+use crate::{AppState, ErrorMessage, Song, load_json, save_json, save_songs};
+use axum::{
+    Json, async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{StatusCode, request::Parts},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+pub const AUDIT_FILE: &str = "audit.json";
+
+// Who is making a change, taken from the `X-User` request header
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .headers
+            .get("x-user")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .unwrap_or("anonymous");
+        Ok(Actor(user.to_string()))
+    }
+}
+
+// One song's value before and after a change (`None` when it did not exist)
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SongChange {
+    song_id: u64,
+    #[serde(serialize_with = "library::serialize_stored_option")]
+    before: Option<Song>,
+    #[serde(serialize_with = "library::serialize_stored_option")]
+    after: Option<Song>,
+}
+
+// A playlist before and after a change (`None` when it did not exist)
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlaylistChange {
+    playlist_id: u64,
+    #[schema(value_type = Option<Object>)]
+    before: Option<Playlist>,
+    #[schema(value_type = Option<Object>)]
+    after: Option<Playlist>,
+}
+
+// A recorded change to songs, ratings or a playlist. Plays are not recorded.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    id: u64,
+    at: String,
+    user: String,
+    action: String,
+    // Named library that was changed (absent for the default library)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    library: Option<String>,
+    changes: Vec<SongChange>,
+    // Ratings of the changed songs before a merge or rating, put back when it
+    // is undone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ratings: Option<Vec<UserRating>>,
+    // Set for playlist changes, which change no songs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    playlist: Option<PlaylistChange>,
+    // Entry that this one undid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    undo_of: Option<u64>,
+    // Entry that undid this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    undone_by: Option<u64>,
+}
+
+// Structure for receiving audit log filters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Only entries touching this song
+    song_id: Option<u64>,
+    /// Only entries made by this user
+    user: Option<String>,
+    /// Only entries for this library (`default` for the default library)
+    library: Option<String>,
+    /// Maximum number of entries (default 50)
+    limit: Option<usize>,
+    /// Number of entries to skip
+    offset: Option<usize>,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Changes between two versions of the song list, in id order
+pub fn diff(before: &[Song], after: &[Song]) -> Vec<SongChange> {
+    let old: HashMap<u64, &Song> = before.iter().map(|s| (s.id, s)).collect();
+    let new: HashMap<u64, &Song> = after.iter().map(|s| (s.id, s)).collect();
+    let ids: BTreeSet<u64> = old.keys().chain(new.keys()).copied().collect();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let old = old.get(&id).copied();
+            let new = new.get(&id).copied();
+            (old != new).then(|| SongChange {
+                song_id: id,
+                before: old.cloned(),
+                after: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+// A single song that was created, or changed from `before`
+pub fn change(before: Option<&Song>, after: Option<&Song>) -> Vec<SongChange> {
+    let song_id = before.or(after).map_or(0, |s| s.id);
+    vec![SongChange {
+        song_id,
+        before: before.cloned(),
+        after: after.cloned(),
+    }]
+}
+
+impl AuditEntry {
+    // An entry that is not in a log yet; `append` assigns its id
+    fn new(library: Option<&str>, user: &str, action: &str, changes: Vec<SongChange>) -> Self {
+        AuditEntry {
+            id: 0,
+            at: Utc::now().to_rfc3339(),
+            user: user.to_string(),
+            action: action.to_string(),
+            library: library.map(str::to_string),
+            changes,
+            ratings: None,
+            playlist: None,
+            undo_of: None,
+            undone_by: None,
+        }
+    }
+
+    // The entry as the API shows it. The log keeps where each song's file
+    // was so an undo can link it again, but that is a path on the server.
+    fn without_files(mut self) -> Self {
+        for change in &mut self.changes {
+            for song in [&mut change.before, &mut change.after]
+                .into_iter()
+                .flatten()
+            {
+                song.file = None;
+            }
+        }
+        self
+    }
+}
+
+// Append an entry to an audit log, marking the entry it undoes. Nothing is
+// recorded for an empty change set.
+fn append(log: &mut Vec<AuditEntry>, mut entry: AuditEntry) -> Option<u64> {
+    if entry.changes.is_empty() && entry.playlist.is_none() {
+        return None;
+    }
+
+    let id = log.last().map_or(1, |e| e.id + 1);
+    entry.id = id;
+    if let Some(undone) = entry.undo_of
+        && let Some(undone) = log.iter_mut().find(|e| e.id == undone)
+    {
+        undone.undone_by = Some(id);
+    }
+    log.push(entry);
+    Some(id)
+}
+
+// Record a change to the default library in the running server's audit log
+pub fn record(state: &AppState, user: &str, action: &str, changes: Vec<SongChange>) {
+    commit(state, AuditEntry::new(None, user, action, changes));
+}
+
+// Record a change to a library in the running server's audit log
+pub fn record_in(
+    state: &AppState,
+    library: Option<&str>,
+    user: &str,
+    action: &str,
+    changes: Vec<SongChange>,
+) {
+    commit(state, AuditEntry::new(library, user, action, changes));
+}
+
+// Record a change along with the ratings of the changed songs before it, so
+// undoing the change, e.g. a merge, can put them back
+pub fn record_with_ratings(
+    state: &AppState,
+    user: &str,
+    action: &str,
+    changes: Vec<SongChange>,
+    ratings: Vec<UserRating>,
+) {
+    let mut entry = AuditEntry::new(None, user, action, changes);
+    entry.ratings = Some(ratings);
+    commit(state, entry);
+}
+
+// Record a change to a playlist of the default library
+pub fn record_playlist(
+    state: &AppState,
+    user: &str,
+    action: &str,
+    playlist_id: u64,
+    before: Option<Playlist>,
+    after: Option<Playlist>,
+) {
+    commit(
+        state,
+        playlist_entry(user, action, playlist_id, before, after),
+    );
+}
+
+fn playlist_entry(
+    user: &str,
+    action: &str,
+    playlist_id: u64,
+    before: Option<Playlist>,
+    after: Option<Playlist>,
+) -> AuditEntry {
+    let mut entry = AuditEntry::new(None, user, action, Vec::new());
+    entry.playlist = Some(PlaylistChange {
+        playlist_id,
+        before,
+        after,
+    });
+    entry
+}
+
+// Send an entry's webhooks and add it to the running server's audit log
+fn commit(state: &AppState, entry: AuditEntry) -> Option<AuditEntry> {
+    // Every catalogue addition and removal, including those made by an undo,
+    // is recorded here, so this is where their webhooks fire
+    for change in &entry.changes {
+        let event = match (&change.before, &change.after) {
+            (None, Some(song)) => (Event::Added, song),
+            (Some(song), None) => (Event::Deleted, song),
+            _ => continue,
+        };
+        state
+            .webhooks
+            .emit_in(entry.library.as_deref(), event.0, event.1);
+    }
+
+    let mut log = state.audit.write();
+    append(&mut log, entry)?;
+    save_json(&state.persistence, AUDIT_FILE, &*log);
+    log.last().cloned()
+}
+
+// Drop audit entries older than `max_age`, returning how many were removed.
+// The newest entry is always kept so entry ids keep increasing.
+pub fn compact(state: &AppState, max_age: chrono::Duration) -> usize {
+    let cutoff = Utc::now() - max_age;
+    let mut log = state.audit.write();
+
+    let keep_from = log
+        .iter()
+        .position(|e| chrono::DateTime::parse_from_rfc3339(&e.at).map_or(true, |at| at >= cutoff))
+        .unwrap_or(log.len())
+        .min(log.len().saturating_sub(1));
+
+    if keep_from > 0 {
+        log.drain(..keep_from);
+        save_json(&state.persistence, AUDIT_FILE, &*log);
+    }
+    keep_from
+}
+
+// Record a change made by a CLI command directly in the audit file
+pub fn record_to_file(
+    persistence: &Persistence,
+    library: Option<&str>,
+    user: &str,
+    action: &str,
+    changes: Vec<SongChange>,
+) {
+    let mut log: Vec<AuditEntry> = load_json(persistence, AUDIT_FILE);
+    if append(&mut log, AuditEntry::new(library, user, action, changes)).is_some() {
+        save_json(persistence, AUDIT_FILE, &log);
+    }
+}
+
+// Revert the changes of an entry. Created songs are removed, removed songs
+// are restored, and updated songs get their catalogue fields back. Play
+// counts are shifted by the recorded difference rather than overwritten, so
+// plays made since the change are kept.
+fn revert(songs: &mut Library, changes: &[SongChange]) {
+    for change in changes.iter().rev() {
+        match (&change.before, &change.after) {
+            (None, Some(after)) => {
+                songs.remove(after.id);
+            }
+            (Some(before), None) => {
+                if !songs.contains(before.id) {
+                    songs.push(before.clone());
+                }
+            }
+            (Some(before), Some(after)) => {
+                if songs.contains(before.id)
+                    && let Some(song) = songs.get_mut(before.id)
+                {
+                    song.title = before.title.clone();
+                    song.artist = before.artist.clone();
+                    song.album = before.album.clone();
+                    song.genre = before.genre.clone();
+                    song.file = before.file.clone();
+                    song.set_plays(
+                        (song.plays() + before.play_count).saturating_sub(after.play_count),
+                    );
+                }
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+// Browse the audit log, newest first
+#[utoipa::path(
+    get,
+    path = "/audit",
+    tag = "audit",
+    params(AuditQuery),
+    responses((status = 200, description = "Audit entries, newest first", body = [AuditEntry], content_type = ["application/json", "text/csv", "application/msgpack"]))
+)]
+pub async fn handle_audit_list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+    format: Format,
+) -> Negotiated<Vec<AuditEntry>> {
+    let log = state.audit.read();
+
+    let entries = log
+        .iter()
+        .rev()
+        .filter(|e| query.user.as_ref().is_none_or(|u| &e.user == u))
+        .filter(|e| {
+            query
+                .library
+                .as_deref()
+                .is_none_or(|lib| e.library.as_deref().unwrap_or(DEFAULT_LIBRARY) == lib)
+        })
+        .filter(|e| {
+            query
+                .song_id
+                .is_none_or(|id| e.changes.iter().any(|c| c.song_id == id))
+        })
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(50))
+        .map(|e| e.clone().without_files())
+        .collect();
+
+    Negotiated(format, entries)
+}
+
+// Undo a recorded change. The undo is itself recorded as a new entry, and
+// sends webhooks for the songs it adds back or removes. Undoing a merge or a
+// rating also puts back the ratings it changed, and undoing a playlist change
+// puts the playlist back as it was.
+#[utoipa::path(
+    post,
+    path = "/audit/{id}/undo",
+    tag = "audit",
+    params(("id" = u64, Path, description = "Audit entry id")),
+    responses(
+        (status = 200, description = "The audit entry recording the undo", body = AuditEntry),
+        (status = 404, description = "No audit entry with this id", body = ErrorMessage),
+        (status = 409, description = "The entry was already undone, or undoing it would change nothing", body = ErrorMessage),
+    )
+)]
+pub async fn handle_audit_undo(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Actor(user): Actor,
+) -> Result<Json<AuditEntry>, ApiError> {
+    let Some(entry) = state.audit.read().iter().find(|e| e.id == id).cloned() else {
+        return Err(error(StatusCode::NOT_FOUND, "Audit entry not found"));
+    };
+    if let Some(change) = &entry.playlist {
+        return undo_playlist(&state, &user, id, change);
+    }
+    let target = match &entry.library {
+        Some(name) => libraries::library(&state, name)?,
+        None => state.songs.clone(),
+    };
+
+    // Lock order: ratings, songs, then the audit log (taken by `commit`)
+    let mut ratings = state.ratings.write();
+    let mut songs = target.write();
+
+    // Checked under the songs lock, so two undos of one entry cannot both pass
+    if state
+        .audit
+        .read()
+        .iter()
+        .any(|e| e.id == id && e.undone_by.is_some())
+    {
+        return Err(error(StatusCode::CONFLICT, "Change was already undone"));
+    }
+
+    let before = songs.to_vec();
+    let song_ids: Vec<u64> = entry.changes.iter().map(|c| c.song_id).collect();
+    let ratings_before = of_songs(&ratings, &song_ids);
+    revert(&mut songs, &entry.changes);
+    // Ratings only cover the default library
+    if entry.library.is_none() {
+        if let Some(before) = &entry.ratings {
+            let since = chrono::DateTime::parse_from_rfc3339(&entry.at)
+                .map_or(0, |at| at.timestamp().max(0) as u64);
+            restore(&mut ratings, &song_ids, before, since);
+            save_json(&state.persistence, RATINGS_FILE, &*ratings);
+        }
+        for change in &entry.changes {
+            if let Some(song) = songs.get_mut(change.song_id) {
+                refresh_song_stats(song, &ratings);
+            }
+        }
+    }
+    let after = songs.to_vec();
+    let mut changes = diff(&before, &after);
+    // A review or a rating that left the averages as they were changes no
+    // song, but is still a change to undo
+    let ratings_changed = of_songs(&ratings, &song_ids) != ratings_before;
+    if changes.is_empty() && ratings_changed {
+        changes = after
+            .iter()
+            .filter(|s| song_ids.contains(&s.id))
+            .flat_map(|s| change(Some(s), Some(s)))
+            .collect();
+    }
+    save_songs(&state.persistence, &mut songs);
+
+    let mut undo = AuditEntry::new(entry.library.as_deref(), &user, "undo", changes);
+    undo.undo_of = Some(id);
+    if ratings_changed {
+        undo.ratings = Some(ratings_before);
+    }
+    match commit(&state, undo) {
+        Some(undo) => Ok(Json(undo.without_files())),
+        // Nothing changed, e.g. the created song was already deleted
+        None => Err(error(StatusCode::CONFLICT, "Nothing left to undo")),
+    }
+}
+
+// Put a playlist back as it was before a recorded change
+fn undo_playlist(
+    state: &AppState,
+    user: &str,
+    id: u64,
+    change: &PlaylistChange,
+) -> Result<Json<AuditEntry>, ApiError> {
+    // Lock order: playlists, then the audit log (taken by `commit`)
+    let mut playlists = state.playlists.write();
+    if state
+        .audit
+        .read()
+        .iter()
+        .any(|e| e.id == id && e.undone_by.is_some())
+    {
+        return Err(error(StatusCode::CONFLICT, "Change was already undone"));
+    }
+
+    let current = playlists.find(change.playlist_id).cloned();
+    if current == change.before {
+        return Err(error(StatusCode::CONFLICT, "Nothing left to undo"));
+    }
+    playlists.put(
+        change.playlist_id,
+        change.before.clone(),
+        &state.persistence,
+    );
+
+    let mut undo = playlist_entry(
+        user,
+        "undo",
+        change.playlist_id,
+        current,
+        change.before.clone(),
+    );
+    undo.undo_of = Some(id);
+    let undo = commit(state, undo).expect("a playlist change is always recorded");
+    Ok(Json(undo))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicates::handle_songs_merge;
+    use crate::ratings::handle_songs_rate;
+    use crate::webhooks::{self, handle_webhooks_create};
+    use crate::{AudioFile, Config, NewSongRequest, add_song, load_state};
+    use serde_json::{Value, json};
+
+    fn song(id: u64, title: &str, plays: u64) -> Song {
+        Song {
+            id,
+            uid: None,
+            title: title.to_string(),
+            artist: "Auditor".to_string(),
+            album: None,
+            genre: "Pop".to_string(),
+            play_count: plays,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        }
+    }
+
+    fn setup(dir: &std::path::Path) -> (Arc<AppState>, webhooks::Receiver) {
+        load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        })
+    }
+
+    fn add(state: &AppState, title: &str) -> Song {
+        let song = NewSongRequest {
+            title: title.to_string(),
+            artist: "Auditor".to_string(),
+            genre: "Pop".to_string(),
+            album: None,
+        };
+        add_song(state, "alice", song)
+    }
+
+    async fn rate(state: &Arc<AppState>, id: u64, user: &str, rating: u8) {
+        let request = serde_json::from_value(json!({"rating": rating})).unwrap();
+        let actor = Actor(user.to_string());
+        let _ = handle_songs_rate(State(state.clone()), Path(id), actor, Json(request))
+            .await
+            .unwrap();
+    }
+
+    async fn undo(state: &Arc<AppState>, id: u64) -> Result<Json<AuditEntry>, ApiError> {
+        handle_audit_undo(State(state.clone()), Path(id), Actor("bob".to_string())).await
+    }
+
+    #[test]
+    fn diff_lists_added_removed_and_changed_songs_in_id_order() {
+        let before = [song(3, "C", 0), song(1, "A", 0), song(2, "B", 0)];
+        let after = [song(4, "D", 0), song(2, "B", 0), song(1, "A2", 0)];
+
+        let changes = diff(&before, &after);
+        let summary: Vec<(u64, bool, bool)> = changes
+            .iter()
+            .map(|c| (c.song_id, c.before.is_some(), c.after.is_some()))
+            .collect();
+        assert_eq!(
+            summary,
+            [(1, true, true), (3, true, false), (4, false, true)]
+        );
+        assert_eq!(changes[0].after.as_ref().unwrap().title, "A2");
+    }
+
+    #[test]
+    fn revert_undoes_each_kind_of_change_and_keeps_later_plays() {
+        let mut songs = Library::default();
+        songs.push(song(1, "Created", 0));
+        songs.push(song(2, "Renamed", 7));
+
+        let changes = vec![
+            SongChange {
+                song_id: 1,
+                before: None,
+                after: Some(song(1, "Created", 0)),
+            },
+            SongChange {
+                song_id: 2,
+                before: Some(song(2, "Original", 1)),
+                after: Some(song(2, "Renamed", 4)),
+            },
+            SongChange {
+                song_id: 3,
+                before: Some(song(3, "Deleted", 2)),
+                after: None,
+            },
+        ];
+        revert(&mut songs, &changes);
+
+        assert!(!songs.contains(1));
+        let renamed = songs.get(2).unwrap();
+        assert_eq!(renamed.title, "Original");
+        // 3 plays were added by the change and 3 more since: only the first
+        // 3 are taken back
+        assert_eq!(renamed.plays(), 4);
+        assert_eq!(songs.get(3).unwrap().title, "Deleted");
+    }
+
+    #[tokio::test]
+    async fn undo_is_recorded_once_and_sends_webhooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = setup(dir.path());
+        let hook = serde_json::from_value(json!({"url": "http://127.0.0.1:9/hook"})).unwrap();
+        let _ = handle_webhooks_create(State(state.clone()), Json(hook))
+            .await
+            .unwrap();
+
+        let created = add(&state, "Oops");
+        receiver.try_recv().unwrap();
+        let id = state.audit.read().last().unwrap().id;
+
+        let Json(entry) = undo(&state, id).await.unwrap();
+        assert_eq!(entry.undo_of, Some(id));
+        assert!(!state.songs.read().contains(created.id));
+        let delivery = serde_json::to_value(receiver.try_recv().unwrap()).unwrap();
+        assert_eq!(delivery["payload"]["event"], "song.deleted");
+        assert_eq!(delivery["payload"]["song"]["title"], "Oops");
+
+        let log = state.audit.read().clone();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].undone_by, Some(entry.id));
+
+        let (status, _) = undo(&state, id).await.unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = undo(&state, 99).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Undoing the undo brings the song back, with an added event
+        let _ = undo(&state, entry.id).await.unwrap();
+        assert!(state.songs.read().contains(created.id));
+        let delivery = serde_json::to_value(receiver.try_recv().unwrap()).unwrap();
+        assert_eq!(delivery["payload"]["event"], "song.added");
+    }
+
+    #[tokio::test]
+    async fn undoing_a_merge_puts_the_ratings_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = setup(dir.path());
+        let kept = add(&state, "Song");
+        let merged = add(&state, "Song (Live)");
+        rate(&state, kept.id, "alice", 5).await;
+        rate(&state, merged.id, "alice", 1).await;
+        rate(&state, merged.id, "bob", 3).await;
+
+        let request =
+            serde_json::from_value(json!({"target": kept.id, "sources": [merged.id]})).unwrap();
+        let _ = handle_songs_merge(
+            State(state.clone()),
+            Actor("alice".to_string()),
+            Json(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(state.songs.read().get(kept.id).unwrap().rating_count, 2);
+
+        let merge = state.audit.read().last().unwrap().clone();
+        assert_eq!(merge.action, "merge");
+        let _ = undo(&state, merge.id).await.unwrap();
+
+        let songs = state.songs.read();
+        let kept = songs.get(kept.id).unwrap();
+        assert_eq!((kept.average_rating, kept.rating_count), (Some(5.0), 1));
+        let merged = songs.get(merged.id).unwrap();
+        assert_eq!((merged.average_rating, merged.rating_count), (Some(2.0), 2));
+        assert_eq!(state.ratings.read().len(), 3);
+    }
+
+    #[test]
+    fn compact_drops_old_entries_but_keeps_the_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = setup(dir.path());
+        for title in ["A", "B", "C"] {
+            add(&state, title);
+        }
+        let old = (Utc::now() - chrono::Duration::days(10)).to_rfc3339();
+        for entry in state.audit.write().iter_mut().take(2) {
+            entry.at = old.clone();
+        }
+
+        assert_eq!(compact(&state, chrono::Duration::days(5)), 2);
+        let ids: Vec<u64> = state.audit.read().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [3]);
+
+        // Even when every entry is old, the newest is kept so ids keep
+        // increasing
+        state.audit.write()[0].at = old;
+        assert_eq!(compact(&state, chrono::Duration::days(5)), 0);
+        add(&state, "D");
+        let ids: Vec<u64> = state.audit.read().iter().map(|e| e.id).collect();
+        assert_eq!(ids, [3, 4]);
+
+        let saved: Vec<Value> = serde_json::from_str(
+            &std::fs::read_to_string(state.persistence.path(AUDIT_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_paths_are_kept_in_the_log_but_not_shown() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = setup(dir.path());
+        let scanned = add(&state, "Scanned");
+
+        // The song is linked to its file, then deleted
+        let before = state.songs.read().to_vec();
+        state.songs.write().get_mut(scanned.id).unwrap().file = Some(AudioFile {
+            path: "/srv/music/scanned.mp3".to_string(),
+            size: 1024,
+            modified: 1,
+        });
+        record(
+            &state,
+            "cli",
+            "scan",
+            diff(&before, &state.songs.read().to_vec()),
+        );
+        let before = state.songs.read().to_vec();
+        state.songs.write().remove(scanned.id);
+        record(
+            &state,
+            "alice",
+            "delete",
+            diff(&before, &state.songs.read().to_vec()),
+        );
+
+        // After a restart, undoing the delete links the file again
+        *state.audit.write() = load_json(&state.persistence, AUDIT_FILE);
+        let delete = state.audit.read().last().unwrap().id;
+        let Json(entry) = undo(&state, delete).await.unwrap();
+        let song = state.songs.read().get(scanned.id).unwrap().song();
+        assert_eq!(song.file.unwrap().path, "/srv/music/scanned.mp3");
+
+        let query = serde_json::from_value(json!({})).unwrap();
+        let entries = handle_audit_list(State(state.clone()), Query(query), Format::Json).await;
+        for shown in [
+            serde_json::to_string(&entries.1).unwrap(),
+            serde_json::to_string(&entry).unwrap(),
+        ] {
+            assert!(!shown.contains("/srv/music"), "{}", shown);
+        }
+        let saved = std::fs::read_to_string(state.persistence.path(AUDIT_FILE)).unwrap();
+        assert!(saved.contains("/srv/music/scanned.mp3"));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::library::LibrarySong;
+use crate::negotiate::{Format, Negotiated};
+use crate::{AppState, ErrorMessage, Song};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+// Artists and albums derived from the songs' `artist` and `album` fields.
+// They are computed from the current songs on every request, so edits,
+// plays, merges, scans and reloads are reflected immediately. Artists are
+// matched case-insensitively; an album is identified by its artist and title.
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GenreBreakdown {
+    genre: String,
+    songs: usize,
+    plays: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlbumSummary {
+    id: String,
+    title: String,
+    song_count: usize,
+    play_count: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Artist {
+    name: String,
+    song_count: usize,
+    play_count: u64,
+    genres: Vec<GenreBreakdown>,
+    albums: Vec<AlbumSummary>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Album {
+    id: String,
+    title: String,
+    artist: String,
+    song_count: usize,
+    play_count: u64,
+    genres: Vec<GenreBreakdown>,
+    songs: Vec<Song>,
+}
+
+// Fields that the artist list can be sorted by
+#[derive(Debug, Deserialize, Clone, Copy, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtistSort {
+    Name,
+    Plays,
+    Songs,
+}
+
+// Structure for receiving artist list parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArtistsQuery {
+    /// Sort by name (default), or by plays or songs, most first
+    sort: Option<ArtistSort>,
+}
+
+fn artist_key(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+// Lowercase letters and digits, with everything else collapsed into `-`
+fn slug(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.trim().to_lowercase().chars() {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// Stable id of an album, e.g. `queen--a-night-at-the-opera--5d41402a`. The
+// slugs keep ids readable; the suffix, a hash of the artist and title as
+// they are matched, keeps apart names that slug alike ("AC/DC" and "AC DC")
+// and names with no letters or digits, whose slug is empty.
+fn album_id(artist: &str, album: &str) -> String {
+    let key = format!("{}\0{}", artist_key(artist), album.trim().to_lowercase());
+    let hash = hex::encode(&Sha256::digest(key.as_bytes())[..4]);
+
+    let mut id = String::new();
+    for part in [slug(artist), slug(album)] {
+        if !part.is_empty() {
+            id.push_str(&part);
+            id.push_str("--");
+        }
+    }
+    id + &hash
+}
+
+fn play_count(songs: &[&LibrarySong]) -> u64 {
+    songs.iter().map(|s| s.plays()).sum()
+}
+
+// Songs and plays per genre, most played first
+fn genres(songs: &[&LibrarySong]) -> Vec<GenreBreakdown> {
+    let mut genres: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+    for song in songs {
+        let entry = genres.entry(&song.genre).or_default();
+        entry.0 += 1;
+        entry.1 += song.plays();
+    }
+
+    let mut genres: Vec<GenreBreakdown> = genres
+        .into_iter()
+        .map(|(genre, (songs, plays))| GenreBreakdown {
+            genre: genre.to_string(),
+            songs,
+            plays,
+        })
+        .collect();
+    genres.sort_by_key(|g| std::cmp::Reverse(g.plays));
+    genres
+}
+
+// Albums of one artist's songs, in title order
+fn albums(songs: &[&LibrarySong]) -> Vec<AlbumSummary> {
+    let mut albums: BTreeMap<String, Vec<&LibrarySong>> = BTreeMap::new();
+    for &song in songs {
+        if let Some(album) = &song.album {
+            albums
+                .entry(album_id(&song.artist, album))
+                .or_default()
+                .push(song);
+        }
+    }
+
+    let mut albums: Vec<AlbumSummary> = albums
+        .into_iter()
+        .map(|(id, songs)| AlbumSummary {
+            id,
+            title: songs[0].album.clone().unwrap_or_default(),
+            song_count: songs.len(),
+            play_count: play_count(&songs),
+        })
+        .collect();
+    albums.sort_by_key(|a| a.title.to_lowercase());
+    albums
+}
+
+// List every artist with play counts, genres and albums
+#[utoipa::path(
+    get,
+    path = "/artists",
+    tag = "artists",
+    params(ArtistsQuery),
+    responses((status = 200, description = "Every artist", body = [Artist], content_type = ["application/json", "text/csv", "application/msgpack"]))
+)]
+pub async fn handle_artists_list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ArtistsQuery>,
+    format: Format,
+) -> Negotiated<Vec<Artist>> {
+    let songs = state.songs.read();
+
+    let mut by_artist: BTreeMap<String, Vec<&LibrarySong>> = BTreeMap::new();
+    for song in songs.iter() {
+        by_artist
+            .entry(artist_key(&song.artist))
+            .or_default()
+            .push(song);
+    }
+
+    let mut artists: Vec<Artist> = by_artist
+        .into_values()
+        .map(|songs| Artist {
+            // The spelling of the artist's first song
+            name: songs[0].artist.trim().to_string(),
+            song_count: songs.len(),
+            play_count: play_count(&songs),
+            genres: genres(&songs),
+            albums: albums(&songs),
+        })
+        .collect();
+
+    match query.sort.unwrap_or(ArtistSort::Name) {
+        ArtistSort::Name => {}
+        ArtistSort::Plays => artists.sort_by_key(|a| std::cmp::Reverse(a.play_count)),
+        ArtistSort::Songs => artists.sort_by_key(|a| std::cmp::Reverse(a.song_count)),
+    }
+
+    Negotiated(format, artists)
+}
+
+// Songs by one artist
+#[utoipa::path(
+    get,
+    path = "/artists/{name}/songs",
+    tag = "artists",
+    params(("name" = String, Path, description = "Artist name (case-insensitive)")),
+    responses(
+        (status = 200, description = "The artist's songs", body = [Song], content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 404, description = "No songs by this artist", body = ErrorMessage),
+    )
+)]
+pub async fn handle_artist_songs(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    format: Format,
+) -> Result<Negotiated<Vec<Song>>, (StatusCode, Json<ErrorMessage>)> {
+    let key = artist_key(&name);
+    let songs: Vec<Song> = state
+        .songs
+        .read()
+        .iter()
+        .filter(|song| artist_key(&song.artist) == key)
+        .map(LibrarySong::song)
+        .collect();
+
+    if songs.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorMessage {
+                error: "Artist not found",
+            }),
+        ));
+    }
+    Ok(Negotiated(format, songs))
+}
+
+// An album with its songs
+#[utoipa::path(
+    get,
+    path = "/albums/{id}",
+    tag = "artists",
+    params(("id" = String, Path, description = "Album id, as listed under GET /artists")),
+    responses(
+        (status = 200, description = "The album", body = Album),
+        (status = 404, description = "No album with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_album_get(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Album>, (StatusCode, Json<ErrorMessage>)> {
+    let library = state.songs.read();
+    let songs: Vec<&LibrarySong> = library
+        .iter()
+        .filter(|song| {
+            song.album
+                .as_ref()
+                .is_some_and(|album| album_id(&song.artist, album) == id)
+        })
+        .collect();
+
+    let Some(first) = songs.first() else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorMessage {
+                error: "Album not found",
+            }),
+        ));
+    };
+
+    Ok(Json(Album {
+        id: id.clone(),
+        title: first.album.clone().unwrap_or_default(),
+        artist: first.artist.trim().to_string(),
+        song_count: songs.len(),
+        play_count: play_count(&songs),
+        genres: genres(&songs),
+        songs: songs.into_iter().map(LibrarySong::song).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, NewSongRequest, add_song, load_state, record_play};
+
+    // Queen (spelled three ways) on two albums, and Abba without one
+    fn catalog(dir: &std::path::Path) -> (Arc<AppState>, Vec<Song>) {
+        let state = load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        })
+        .0;
+        let songs = [
+            (
+                "Bohemian Rhapsody",
+                "Queen",
+                "Rock",
+                Some("A Night at the Opera"),
+            ),
+            (
+                "Love of My Life",
+                "queen ",
+                "Ballad",
+                Some("a night at the opera"),
+            ),
+            ("Radio Ga Ga", "QUEEN", "Pop", Some("The Works")),
+            ("Waterloo", "Abba", "Pop", None),
+        ]
+        .map(|(title, artist, genre, album)| {
+            let song = NewSongRequest {
+                title: title.to_string(),
+                artist: artist.to_string(),
+                genre: genre.to_string(),
+                album: album.map(str::to_string),
+            };
+            add_song(&state, "alice", song)
+        });
+        for (song, plays) in songs.iter().zip([3, 0, 1, 5]) {
+            for _ in 0..plays {
+                record_play(&state, song.id).unwrap();
+            }
+        }
+        (state, songs.to_vec())
+    }
+
+    async fn artists(state: &Arc<AppState>, sort: Option<ArtistSort>) -> Vec<Artist> {
+        handle_artists_list(
+            State(state.clone()),
+            Query(ArtistsQuery { sort }),
+            Format::Json,
+        )
+        .await
+        .1
+    }
+
+    async fn album(state: &Arc<AppState>, id: String) -> Result<Album, StatusCode> {
+        handle_album_get(State(state.clone()), Path(id))
+            .await
+            .map(|Json(album)| album)
+            .map_err(|(status, _)| status)
+    }
+
+    #[test]
+    fn slugs_keep_letters_and_digits() {
+        assert_eq!(slug("  A Night at the Opera! "), "a-night-at-the-opera");
+        assert_eq!(slug("Sigur Rós"), "sigur-rós");
+        assert_eq!(slug("AC/DC"), "ac-dc");
+        assert_eq!(slug("?!"), "");
+    }
+
+    #[test]
+    fn album_ids_are_stable_and_readable() {
+        let id = album_id("Queen", "A Night at the Opera");
+        assert!(id.starts_with("queen--a-night-at-the-opera--"), "{}", id);
+        assert_eq!(id.len(), "queen--a-night-at-the-opera--".len() + 8);
+        // Matched like artists: case and surrounding spaces do not matter
+        assert_eq!(album_id(" queen", "a night at the opera "), id);
+    }
+
+    #[test]
+    fn names_that_slug_alike_get_different_ids() {
+        assert_ne!(
+            album_id("AC/DC", "Back in Black"),
+            album_id("AC DC", "Back in Black")
+        );
+        assert_ne!(album_id("Prince", "?"), album_id("Prince", "!"));
+        assert_ne!(album_id("?", "Album"), album_id("!", "Album"));
+
+        // Punctuation-only names leave no empty segments
+        let id = album_id("!!!", "???");
+        assert_eq!(id.len(), 8, "{}", id);
+        assert!(!album_id("Prince", "?").contains("----"));
+    }
+
+    #[tokio::test]
+    async fn artists_are_grouped_case_insensitively_with_genres_and_albums() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = catalog(dir.path());
+
+        let artists = artists(&state, None).await;
+        let names: Vec<&str> = artists.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, ["Abba", "Queen"]);
+
+        let queen = &artists[1];
+        assert_eq!((queen.song_count, queen.play_count), (3, 4));
+        let genres: Vec<(&str, usize, u64)> = queen
+            .genres
+            .iter()
+            .map(|g| (g.genre.as_str(), g.songs, g.plays))
+            .collect();
+        assert_eq!(genres, [("Rock", 1, 3), ("Pop", 1, 1), ("Ballad", 1, 0)]);
+
+        // Album titles are matched like artists; the first spelling is shown
+        let albums: Vec<(&str, usize, u64)> = queen
+            .albums
+            .iter()
+            .map(|a| (a.title.as_str(), a.song_count, a.play_count))
+            .collect();
+        assert_eq!(
+            albums,
+            [("A Night at the Opera", 2, 3), ("The Works", 1, 1)]
+        );
+        assert!(artists[0].albums.is_empty());
+    }
+
+    #[tokio::test]
+    async fn artists_sort_by_plays_and_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = catalog(dir.path());
+
+        let order =
+            |artists: Vec<Artist>| -> Vec<String> { artists.into_iter().map(|a| a.name).collect() };
+        assert_eq!(
+            order(artists(&state, Some(ArtistSort::Plays)).await),
+            ["Abba", "Queen"]
+        );
+        assert_eq!(
+            order(artists(&state, Some(ArtistSort::Songs)).await),
+            ["Queen", "Abba"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_artists_and_albums_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = catalog(dir.path());
+
+        let songs = |name: &str| {
+            handle_artist_songs(State(state.clone()), Path(name.to_string()), Format::Json)
+        };
+        let Negotiated(_, queen) = songs(" QUEEN").await.unwrap();
+        assert_eq!(queen.len(), 3);
+        let Err((status, _)) = songs("Nobody").await else {
+            panic!("an unknown artist was found");
+        };
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let id = album_id("Queen", "The Works");
+        assert_eq!(album(&state, id).await.unwrap().songs.len(), 1);
+        let missing = album_id("Queen", "Innuendo");
+        assert_eq!(
+            album(&state, missing).await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_follow_plays() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, songs) = catalog(dir.path());
+        let id = album_id("Queen", "A Night at the Opera");
+
+        let before = album(&state, id.clone()).await.unwrap();
+        assert_eq!(before.play_count, 3);
+        assert_eq!(before.artist, "Queen");
+
+        record_play(&state, songs[1].id).unwrap();
+        let after = album(&state, id).await.unwrap();
+        assert_eq!(after.play_count, 4);
+        let played = after.songs.iter().find(|s| s.id == songs[1].id).unwrap();
+        assert_eq!(played.play_count, 1);
+        let ballad = after.genres.iter().find(|g| g.genre == "Ballad").unwrap();
+        assert_eq!(ballad.plays, 1);
+
+        let queen = artists(&state, None).await.remove(1);
+        assert_eq!(queen.play_count, 5);
+    }
+}
@@ -0,0 +1,292 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Server settings, read from environment variables at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    // Fraction of a song that must be streamed before it counts as a play
+    pub play_portion: f64,
+    // Log filter directive, e.g. `info` or `server=debug`
+    pub log_level: String,
+    // Directory holding songs.json and the other data files
+    pub data_dir: PathBuf,
+    // Directory holding timestamped state snapshots (relative to `data_dir`
+    // unless absolute)
+    pub snapshot_dir: String,
+    // Number of snapshots kept when pruning
+    pub snapshot_retention: usize,
+    // Folders that POST /admin/scan may scan (subfolders included); the
+    // endpoint refuses every folder when none are configured
+    pub music_dirs: Vec<PathBuf>,
+    // Assign opaque ULID public ids to songs
+    pub public_ids: bool,
+    // Address both listeners bind to; `0.0.0.0` exposes the server on the LAN
+    pub bind_address: String,
+    // PEM certificate chain and private key for the HTTPS listener
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    // Serve HTTPS with a generated self-signed certificate
+    pub tls_dev: bool,
+    // Port of the HTTPS listener (plain HTTP stays on 8080 and redirects)
+    pub https_port: u16,
+    // How long a client gets to complete the TLS handshake before the
+    // connection is dropped
+    pub tls_handshake_timeout: Duration,
+    // Seconds between checks of songs.json for external edits (0 disables)
+    pub reload_interval: u64,
+    // Attempts per webhook delivery before it is moved to the dead letters
+    pub webhook_max_attempts: u32,
+    // Wait before the first webhook retry; doubled for each further retry
+    pub webhook_backoff: Duration,
+    // How often play counts, which are counted in memory, are saved
+    pub play_checkpoint_interval: Duration,
+    // How often webhook dead letters, which are collected in memory, are saved
+    pub dead_letter_save_interval: Duration,
+    // How often the scheduled maintenance jobs run (zero disables a job; see
+    // `scheduler.rs`)
+    pub history_compact_interval: Duration,
+    pub snapshot_prune_interval: Duration,
+    pub trending_interval: Duration,
+    pub queue_expire_interval: Duration,
+    // Audit entries older than this are dropped when history is compacted
+    pub history_retention_days: u64,
+    // Play queues unused for this long are dropped
+    pub queue_ttl: Duration,
+    // Most play queues kept at once; creating another drops the least
+    // recently used one
+    pub max_queues: usize,
+    // Origins allowed to call the API from a browser (`*` for any); CORS is
+    // off when empty
+    pub cors_origins: Vec<String>,
+    // Methods and request headers allowed in cross-origin requests
+    pub cors_methods: Vec<String>,
+    pub cors_headers: Vec<String>,
+    // Compress responses of at least `compression_min_size` bytes with gzip
+    // or brotli, as the client accepts
+    pub compression: bool,
+    pub compression_min_size: u16,
+    // Add nosniff, frame, referrer (and with TLS, HSTS) headers to responses
+    pub security_headers: bool,
+    // Content-Security-Policy header value, if any
+    pub content_security_policy: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            play_portion: 0.5,
+            log_level: "info".to_string(),
+            data_dir: PathBuf::from("."),
+            snapshot_dir: "snapshots".to_string(),
+            snapshot_retention: 10,
+            music_dirs: Vec::new(),
+            public_ids: false,
+            bind_address: "127.0.0.1".to_string(),
+            tls_cert: None,
+            tls_key: None,
+            tls_dev: false,
+            https_port: 8443,
+            tls_handshake_timeout: Duration::from_secs(10),
+            reload_interval: 2,
+            webhook_max_attempts: 5,
+            webhook_backoff: Duration::from_secs(1),
+            play_checkpoint_interval: Duration::from_secs(5),
+            dead_letter_save_interval: Duration::from_secs(5),
+            history_compact_interval: Duration::from_secs(3600),
+            snapshot_prune_interval: Duration::from_secs(3600),
+            trending_interval: Duration::from_secs(300),
+            queue_expire_interval: Duration::from_secs(300),
+            history_retention_days: 90,
+            queue_ttl: Duration::from_secs(24 * 3600),
+            max_queues: 1000,
+            cors_origins: Vec::new(),
+            cors_methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+            cors_headers: vec!["content-type".to_string(), "x-user".to_string()],
+            compression: true,
+            compression_min_size: 1024,
+            security_headers: true,
+            content_security_policy: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let mut config = Config::default();
+
+        if let Some(portion) = env_parse::<f64>("STREAM_PLAY_PORTION")
+            && portion > 0.0
+            && portion <= 1.0
+        {
+            config.play_portion = portion;
+        }
+
+        if let Ok(level) = env::var("LOG_LEVEL") {
+            config.log_level = level;
+        }
+
+        if let Ok(dir) = env::var("DATA_DIR")
+            && !dir.trim().is_empty()
+        {
+            config.data_dir = PathBuf::from(dir.trim());
+        }
+
+        if let Ok(dir) = env::var("SNAPSHOT_DIR") {
+            config.snapshot_dir = dir;
+        }
+
+        if let Some(keep) = env_parse::<usize>("SNAPSHOT_RETENTION")
+            && keep > 0
+        {
+            config.snapshot_retention = keep;
+        }
+
+        if let Some(dirs) = env_list("MUSIC_DIRS") {
+            config.music_dirs = dirs.into_iter().map(PathBuf::from).collect();
+        }
+
+        if let Ok(value) = env::var("PUBLIC_IDS") {
+            config.public_ids = matches!(value.trim(), "1" | "true" | "yes");
+        }
+
+        if let Ok(address) = env::var("BIND_ADDRESS") {
+            config.bind_address = address.trim().to_string();
+        }
+
+        config.tls_cert = env::var("TLS_CERT").ok().filter(|v| !v.is_empty());
+        config.tls_key = env::var("TLS_KEY").ok().filter(|v| !v.is_empty());
+
+        if let Ok(value) = env::var("TLS_DEV") {
+            config.tls_dev = matches!(value.trim(), "1" | "true" | "yes");
+        }
+
+        if let Some(port) = env_parse::<u16>("HTTPS_PORT") {
+            config.https_port = port;
+        }
+
+        if let Some(secs) = env_parse::<u64>("TLS_HANDSHAKE_TIMEOUT")
+            && secs > 0
+        {
+            config.tls_handshake_timeout = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("RELOAD_INTERVAL") {
+            config.reload_interval = secs;
+        }
+
+        if let Some(attempts) = env_parse::<u32>("WEBHOOK_MAX_ATTEMPTS")
+            && attempts > 0
+        {
+            config.webhook_max_attempts = attempts;
+        }
+
+        if let Some(ms) = env_parse::<u64>("WEBHOOK_BACKOFF_MS") {
+            config.webhook_backoff = Duration::from_millis(ms);
+        }
+
+        if let Some(secs) = env_parse::<u64>("PLAY_CHECKPOINT_INTERVAL")
+            && secs > 0
+        {
+            config.play_checkpoint_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("DEAD_LETTER_SAVE_INTERVAL")
+            && secs > 0
+        {
+            config.dead_letter_save_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("HISTORY_COMPACT_INTERVAL") {
+            config.history_compact_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("SNAPSHOT_PRUNE_INTERVAL") {
+            config.snapshot_prune_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("TRENDING_INTERVAL") {
+            config.trending_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = env_parse::<u64>("QUEUE_EXPIRE_INTERVAL") {
+            config.queue_expire_interval = Duration::from_secs(secs);
+        }
+
+        if let Some(days) = env_parse::<u64>("HISTORY_RETENTION_DAYS")
+            && days > 0
+        {
+            config.history_retention_days = days;
+        }
+
+        if let Some(secs) = env_parse::<u64>("QUEUE_TTL")
+            && secs > 0
+        {
+            config.queue_ttl = Duration::from_secs(secs);
+        }
+
+        if let Some(max) = env_parse::<usize>("MAX_QUEUES")
+            && max > 0
+        {
+            config.max_queues = max;
+        }
+
+        if let Some(origins) = env_list("CORS_ALLOWED_ORIGINS") {
+            config.cors_origins = origins;
+        }
+
+        if let Some(methods) = env_list("CORS_ALLOWED_METHODS") {
+            config.cors_methods = methods;
+        }
+
+        if let Some(headers) = env_list("CORS_ALLOWED_HEADERS") {
+            config.cors_headers = headers;
+        }
+
+        if let Ok(value) = env::var("COMPRESSION") {
+            config.compression = matches!(value.trim(), "1" | "true" | "yes");
+        }
+
+        if let Some(size) = env_parse::<u16>("COMPRESSION_MIN_SIZE") {
+            config.compression_min_size = size;
+        }
+
+        if let Ok(value) = env::var("SECURITY_HEADERS") {
+            config.security_headers = matches!(value.trim(), "1" | "true" | "yes");
+        }
+
+        config.content_security_policy = env::var("CONTENT_SECURITY_POLICY")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+
+        config
+    }
+
+    // Where snapshots are written
+    pub fn snapshot_path(&self) -> PathBuf {
+        self.data_dir.join(&self.snapshot_dir)
+    }
+
+    // Whether the HTTPS listener is enabled
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_dev || self.tls_cert.is_some() || self.tls_key.is_some()
+    }
+}
+
+// Split a comma-separated environment variable, dropping empty entries
+fn env_list(name: &str) -> Option<Vec<String>> {
+    let value = env::var(name).ok()?;
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+// Parse an environment variable, ignoring it if unset or invalid
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,480 @@
+use crate::audit::{self, Actor};
+use crate::ratings::{RATINGS_FILE, of_songs, reassign, refresh_song_stats};
+use crate::{AppState, ErrorMessage, Song, save_json, save_songs};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::info;
+use utoipa::{IntoParams, ToSchema};
+
+// Words in brackets or after a dash that only describe a release, e.g.
+// "(Remastered 2011)", "[Live]" or "- Single Version"
+const VERSION_WORDS: &[&str] = &[
+    "remaster",
+    "remastered",
+    "live",
+    "mono",
+    "stereo",
+    "version",
+    "edit",
+    "mix",
+    "remix",
+    "single",
+    "radio",
+    "demo",
+    "acoustic",
+    "deluxe",
+    "bonus",
+    "explicit",
+    "clean",
+];
+
+// Structure for receiving duplicate search parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DuplicatesQuery {
+    /// Minimum similarity (0-1) of both normalized title and artist
+    threshold: Option<f64>,
+}
+
+// A group of songs that look like the same recording
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicateCluster {
+    title: String,
+    artist: String,
+    songs: Vec<Song>,
+}
+
+// Structure for receiving a merge request from POST JSON
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MergeRequest {
+    // Song that is kept
+    target: u64,
+    // Songs folded into the target and removed
+    sources: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MergeResponse {
+    song: Song,
+    // Ids that now redirect to `song`
+    merged_ids: Vec<u64>,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Lowercase, drop bracketed or dash-separated version notes, strip
+// punctuation and collapse whitespace
+fn normalize(value: &str) -> String {
+    let mut text = value.to_lowercase();
+
+    // "(...)" and "[...]" groups that only describe the release
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        while let Some(start) = text.find(open) {
+            let Some(len) = text[start..].find(close) else {
+                break;
+            };
+            let inner = &text[start + 1..start + len];
+            let keep = if is_version_note(inner) { "" } else { inner };
+            text = format!("{} {} {}", &text[..start], keep, &text[start + len + 1..]);
+        }
+    }
+
+    // "Title - 2011 Remaster"
+    if let Some(idx) = text.rfind(" - ")
+        && is_version_note(&text[idx + 3..])
+    {
+        text.truncate(idx);
+    }
+
+    let cleaned: String = text
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    let words: Vec<&str> = cleaned.split_whitespace().collect();
+
+    match words.as_slice() {
+        ["the", rest @ ..] if !rest.is_empty() => rest.join(" "),
+        _ => words.join(" "),
+    }
+}
+
+fn is_version_note(text: &str) -> bool {
+    text.split(|c: char| !c.is_alphanumeric())
+        .any(|word| VERSION_WORDS.contains(&word))
+}
+
+// Normalized Levenshtein similarity in [0, 1]
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    1.0 - prev[b.len()] as f64 / longest as f64
+}
+
+// Whether `similarity(a, b)` reaches `threshold`. The edit distance is at
+// least the difference in length, so most pairs are ruled out without
+// computing it.
+fn close_enough(a: &str, b: &str, threshold: f64) -> bool {
+    let (a_len, b_len) = (a.chars().count(), b.chars().count());
+    let longest = a_len.max(b_len);
+    if longest > 0 && 1.0 - a_len.abs_diff(b_len) as f64 / (longest as f64) < threshold {
+        return false;
+    }
+    similarity(a, b) >= threshold
+}
+
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// Cluster songs whose normalized titles and artists are both at least
+// `threshold` similar. Clusters are returned in id order of their first song.
+fn clusters(songs: &[Song], threshold: f64) -> Vec<DuplicateCluster> {
+    let keys: Vec<(String, String)> = songs
+        .iter()
+        .map(|s| (normalize(&s.title), normalize(&s.artist)))
+        .collect();
+
+    let mut parent: Vec<usize> = (0..songs.len()).collect();
+    for i in 0..songs.len() {
+        for j in i + 1..songs.len() {
+            if close_enough(&keys[i].1, &keys[j].1, threshold)
+                && close_enough(&keys[i].0, &keys[j].0, threshold)
+            {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                parent[b] = a;
+            }
+        }
+    }
+
+    let mut groups: Vec<(usize, Vec<usize>)> = Vec::new();
+    for i in 0..songs.len() {
+        let root = find(&mut parent, i);
+        match groups.iter_mut().find(|(r, _)| *r == root) {
+            Some((_, members)) => members.push(i),
+            None => groups.push((root, vec![i])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(_, members)| DuplicateCluster {
+            title: keys[members[0]].0.clone(),
+            artist: keys[members[0]].1.clone(),
+            songs: members.iter().map(|&i| songs[i].clone()).collect(),
+        })
+        .collect()
+}
+
+// Find groups of likely duplicate songs. Clustering compares every pair of
+// songs, so it runs on a copy of the songs off the async workers.
+#[utoipa::path(
+    get,
+    path = "/songs/duplicates",
+    tag = "songs",
+    params(DuplicatesQuery),
+    responses((status = 200, description = "Clusters of likely duplicates", body = [DuplicateCluster]))
+)]
+pub async fn handle_songs_duplicates(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DuplicatesQuery>,
+) -> Json<Vec<DuplicateCluster>> {
+    let songs = state.songs.read().to_vec();
+    Json(find_duplicates(songs, &query).await)
+}
+
+// Clusters of likely duplicates in a song list, computed on a blocking thread
+pub async fn find_duplicates(songs: Vec<Song>, query: &DuplicatesQuery) -> Vec<DuplicateCluster> {
+    let threshold = query.threshold.unwrap_or(0.9).clamp(0.0, 1.0);
+    tokio::task::spawn_blocking(move || clusters(&songs, threshold))
+        .await
+        .unwrap()
+}
+
+// Fold duplicate songs into one: play counts are summed, ratings move over,
+// and the merged ids keep resolving to the target song
+#[utoipa::path(
+    post,
+    path = "/songs/merge",
+    tag = "songs",
+    request_body = MergeRequest,
+    responses(
+        (status = 200, description = "The merged song", body = MergeResponse),
+        (status = 400, description = "Invalid merge request", body = ErrorMessage),
+        (status = 404, description = "A song does not exist", body = ErrorMessage),
+    )
+)]
+pub async fn handle_songs_merge(
+    State(state): State<Arc<AppState>>,
+    Actor(user): Actor,
+    Json(payload): Json<MergeRequest>,
+) -> Result<Json<MergeResponse>, ApiError> {
+    // Lock order: ratings before songs
+    let mut ratings = state.ratings.write();
+    let mut songs = state.songs.write();
+
+    let Some(target) = songs.get(payload.target).map(|s| s.id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Song not found"));
+    };
+
+    let mut sources = Vec::new();
+    for &id in &payload.sources {
+        let Some(source) = songs.get(id).map(|s| s.id) else {
+            return Err(error(StatusCode::NOT_FOUND, "Song not found"));
+        };
+        if source == target {
+            return Err(error(
+                StatusCode::BAD_REQUEST,
+                "A song cannot be merged into itself",
+            ));
+        }
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    if sources.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "No songs to merge"));
+    }
+
+    let before = songs.to_vec();
+    let merged_ids: Vec<u64> = sources.iter().copied().chain([target]).collect();
+    let ratings_before = of_songs(&ratings, &merged_ids);
+    for &source in &sources {
+        let merged = songs.remove(source).unwrap();
+        songs.redirect(source, target);
+        reassign(&mut ratings, source, target);
+
+        let song = songs.get_mut(target).unwrap();
+        song.add_plays(merged.play_count);
+        if song.file.is_none() {
+            song.file = merged.file;
+        }
+        if song.album.is_none() {
+            song.album = merged.album;
+        }
+    }
+
+    let song = songs.get_mut(target).unwrap();
+    refresh_song_stats(song, &ratings);
+    let song = song.song();
+
+    save_json(&state.persistence, RATINGS_FILE, &*ratings);
+    save_songs(&state.persistence, &mut songs);
+    audit::record_with_ratings(
+        &state,
+        &user,
+        "merge",
+        audit::diff(&before, &songs.to_vec()),
+        ratings_before,
+    );
+
+    info!(target, merged = ?sources, "songs merged");
+    Ok(Json(MergeResponse {
+        song,
+        merged_ids: sources,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, NewSongRequest, add_song, load_state};
+    use serde_json::json;
+
+    fn song(id: u64, title: &str, artist: &str) -> Song {
+        Song {
+            id,
+            uid: None,
+            title: title.to_string(),
+            artist: artist.to_string(),
+            album: None,
+            genre: "Rock".to_string(),
+            play_count: 0,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        }
+    }
+
+    fn ids(clusters: &[DuplicateCluster]) -> Vec<Vec<u64>> {
+        clusters
+            .iter()
+            .map(|c| c.songs.iter().map(|s| s.id).collect())
+            .collect()
+    }
+
+    async fn merge(
+        state: &Arc<AppState>,
+        target: u64,
+        sources: &[u64],
+    ) -> Result<Json<MergeResponse>, ApiError> {
+        let request =
+            serde_json::from_value(json!({"target": target, "sources": sources})).unwrap();
+        handle_songs_merge(
+            State(state.clone()),
+            Actor("alice".to_string()),
+            Json(request),
+        )
+        .await
+    }
+
+    #[test]
+    fn normalize_drops_version_notes_punctuation_and_a_leading_the() {
+        assert_eq!(
+            normalize("Bohemian Rhapsody (Remastered)"),
+            "bohemian rhapsody"
+        );
+        assert_eq!(normalize("Help! [Live]  - 2009 Remaster"), "help");
+        assert_eq!(normalize("Hey Jude - Single Version"), "hey jude");
+        assert_eq!(normalize("The Beatles"), "beatles");
+        // Other notes and titles that are only "The" are kept
+        assert_eq!(normalize("Song (feat. Someone)"), "song feat someone");
+        assert_eq!(normalize("The"), "the");
+        assert_eq!(normalize("Rock - Roll"), "rock roll");
+    }
+
+    #[test]
+    fn similarity_is_normalized_edit_distance() {
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abc", "abc"), 1.0);
+        assert_eq!(similarity("abc", ""), 0.0);
+        assert_eq!(similarity("home", "hope"), 0.75);
+        assert_eq!(similarity("kitten", "sitting"), 1.0 - 3.0 / 7.0);
+    }
+
+    #[test]
+    fn the_threshold_is_inclusive() {
+        let songs = [song(1, "Home", "Band"), song(2, "Hope", "Band")];
+        assert_eq!(ids(&clusters(&songs, 0.75)), [[1, 2]]);
+        assert!(clusters(&songs, 0.76).is_empty());
+
+        // Both the title and the artist must be close enough
+        let songs = [song(1, "Home", "Band"), song(2, "Home", "Other")];
+        assert!(clusters(&songs, 0.75).is_empty());
+    }
+
+    #[test]
+    fn clusters_group_versions_of_the_same_recording() {
+        let songs = [
+            song(1, "Bohemian Rhapsody", "Queen"),
+            song(2, "Under Pressure", "Queen"),
+            song(3, "Bohemian Rhapsody (Remastered)", "Queen"),
+            song(4, "Under Pressure - Live", "Queen"),
+            song(5, "Bohemian Rhapsody", "The Queen"),
+            song(6, "Somebody to Love", "Queen"),
+        ];
+
+        let found = clusters(&songs, 0.9);
+        assert_eq!(ids(&found), [vec![1, 3, 5], vec![2, 4]]);
+        assert_eq!(
+            (found[0].title.as_str(), found[0].artist.as_str()),
+            ("bohemian rhapsody", "queen")
+        );
+    }
+
+    #[tokio::test]
+    async fn the_endpoint_finds_duplicates_in_the_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        for title in [
+            "Bohemian Rhapsody",
+            "Bohemian Rhapsody (Remastered)",
+            "Other",
+        ] {
+            let request = NewSongRequest {
+                title: title.to_string(),
+                artist: "Queen".to_string(),
+                genre: "Rock".to_string(),
+                album: None,
+            };
+            add_song(&state, "alice", request);
+        }
+
+        let query = serde_json::from_value(json!({})).unwrap();
+        let Json(found) = handle_songs_duplicates(State(state), Query(query)).await;
+        assert_eq!(ids(&found), [[1, 2]]);
+    }
+
+    #[tokio::test]
+    async fn merge_folds_sources_into_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let mut kept = song(1, "Song", "Band");
+        kept.play_count = 3;
+        let mut live = song(2, "Song (Live)", "Band");
+        live.play_count = 4;
+        live.album = Some("Live".to_string());
+        {
+            let mut songs = state.songs.write();
+            songs.push(kept);
+            songs.push(live);
+            songs.push(song(3, "Other", "Band"));
+        }
+
+        assert_eq!(
+            merge(&state, 1, &[]).await.unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            merge(&state, 1, &[1]).await.unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            merge(&state, 1, &[9]).await.unwrap_err().0,
+            StatusCode::NOT_FOUND
+        );
+
+        let Json(merged) = merge(&state, 1, &[2, 2]).await.unwrap();
+        assert_eq!(merged.merged_ids, [2]);
+        assert_eq!(merged.song.play_count, 7);
+        assert_eq!(merged.song.album.as_deref(), Some("Live"));
+
+        // The merged id keeps resolving to the target
+        let songs = state.songs.read();
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs.get(2).unwrap().id, 1);
+        drop(songs);
+        let entry = serde_json::to_value(state.audit.read().last().unwrap()).unwrap();
+        assert_eq!(entry["action"], "merge");
+    }
+}
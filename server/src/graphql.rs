@@ -0,0 +1,354 @@
+use crate::audit::Actor;
+use crate::playlists::{self, PlaylistKind, PlaylistSummary};
+use crate::query::QueryError;
+use crate::{
+    AppState, NewSongRequest, Song, SongSearchQuery, SongSort, SortOrder, add_song, record_play,
+    search_songs,
+};
+use async_graphql::{
+    Context, EmptySubscription, Enum, Error, ErrorExtensions, InputObject, Object, Request,
+    Response, Result, Schema, SimpleObject, http::GraphiQLSource,
+};
+use axum::{Json, extract::State, response::Html};
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock};
+
+// GraphQL API over the same state and helpers as the REST handlers:
+//
+//   { search(query: "genre:rock", sort: PLAYS) { id title playCount }
+//     stats { songCount totalPlays } }
+//
+// The application state and the calling user are attached to each request,
+// so one schema serves every request.
+pub type LibrarySchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+fn schema() -> &'static LibrarySchema {
+    static SCHEMA: OnceLock<LibrarySchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::new(QueryRoot, MutationRoot, EmptySubscription))
+}
+
+fn app_state<'a>(ctx: &Context<'a>) -> &'a Arc<AppState> {
+    ctx.data_unchecked::<Arc<AppState>>()
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "music_types::SongSort")]
+enum SortField {
+    Id,
+    Title,
+    Plays,
+    Rating,
+    Favourites,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "music_types::SortOrder")]
+enum Order {
+    Asc,
+    Desc,
+}
+
+// The shared song types live in the `music-types` crate, so the GraphQL
+// objects wrap them
+struct SongObject(Song);
+
+#[Object(name = "Song")]
+impl SongObject {
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    // Opaque public id, only assigned when PUBLIC_IDS is enabled
+    async fn uid(&self) -> Option<&str> {
+        self.0.uid.as_deref()
+    }
+
+    async fn title(&self) -> &str {
+        &self.0.title
+    }
+
+    async fn artist(&self) -> &str {
+        &self.0.artist
+    }
+
+    async fn album(&self) -> Option<&str> {
+        self.0.album.as_deref()
+    }
+
+    async fn genre(&self) -> &str {
+        &self.0.genre
+    }
+
+    async fn play_count(&self) -> u64 {
+        self.0.play_count
+    }
+
+    async fn average_rating(&self) -> Option<f64> {
+        self.0.average_rating
+    }
+
+    async fn rating_count(&self) -> u64 {
+        self.0.rating_count
+    }
+
+    async fn favourite_count(&self) -> u64 {
+        self.0.favourite_count
+    }
+}
+
+// Case-insensitive substring filters, as on GET /songs/search
+#[derive(InputObject, Default)]
+struct SongFilter {
+    title: Option<String>,
+    artist: Option<String>,
+    genre: Option<String>,
+}
+
+fn query_error(e: &QueryError) -> Error {
+    let position = e.position();
+    Error::new(e.message()).extend_with(|_, ext| ext.set("position", position))
+}
+
+// A playlist as of now; a smart playlist's songs match its rule
+struct PlaylistObject(PlaylistSummary, std::result::Result<Vec<Song>, QueryError>);
+
+#[Object(name = "Playlist")]
+impl PlaylistObject {
+    async fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn smart(&self) -> bool {
+        self.0.kind == PlaylistKind::Smart
+    }
+
+    async fn created_by(&self) -> &str {
+        &self.0.created_by
+    }
+
+    async fn created_at(&self) -> &str {
+        &self.0.created_at
+    }
+
+    async fn song_count(&self) -> usize {
+        self.0.song_count
+    }
+
+    // Fails with the parse error when a smart playlist's rule no longer parses
+    async fn songs(&self) -> Result<Vec<SongObject>> {
+        match &self.1 {
+            Ok(songs) => Ok(songs.iter().cloned().map(SongObject).collect()),
+            Err(e) => Err(query_error(e)),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct GenreStats {
+    genre: String,
+    songs: usize,
+    plays: u64,
+}
+
+#[derive(SimpleObject)]
+struct LibraryStats {
+    song_count: usize,
+    total_plays: u64,
+    rated_songs: usize,
+    // Genres by number of plays
+    genres: Vec<GenreStats>,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    // Same filters and query language as GET /songs/search
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: Option<String>,
+        #[graphql(default)] filter: SongFilter,
+        sort: Option<SortField>,
+        order: Option<Order>,
+    ) -> Result<Vec<SongObject>> {
+        let search = SongSearchQuery {
+            query,
+            title: filter.title,
+            artist: filter.artist,
+            genre: filter.genre,
+            sort: sort.map(SongSort::from),
+            order: order.map(SortOrder::from),
+        };
+
+        let songs = app_state(ctx).songs.read();
+        search_songs(&songs, &search)
+            .map(|songs| songs.into_iter().map(SongObject).collect())
+            .map_err(|e| query_error(&e))
+    }
+
+    // A song by id (merged ids resolve to the song they were merged into)
+    async fn song(&self, ctx: &Context<'_>, id: u64) -> Option<SongObject> {
+        app_state(ctx)
+            .songs
+            .read()
+            .get(id)
+            .map(|song| SongObject(song.song()))
+    }
+
+    async fn song_by_uid(&self, ctx: &Context<'_>, uid: String) -> Option<SongObject> {
+        app_state(ctx)
+            .songs
+            .read()
+            .get_by_uid(&uid)
+            .map(|song| SongObject(song.song()))
+    }
+
+    // Every playlist, like GET /playlists
+    async fn playlists(&self, ctx: &Context<'_>) -> Vec<PlaylistObject> {
+        playlists::evaluate_all(app_state(ctx))
+            .into_iter()
+            .map(|(summary, songs)| PlaylistObject(summary, songs))
+            .collect()
+    }
+
+    async fn playlist(&self, ctx: &Context<'_>, id: u64) -> Option<PlaylistObject> {
+        playlists::evaluate_all(app_state(ctx))
+            .into_iter()
+            .find(|(summary, _)| summary.id == id)
+            .map(|(summary, songs)| PlaylistObject(summary, songs))
+    }
+
+    async fn stats(&self, ctx: &Context<'_>) -> LibraryStats {
+        let songs = app_state(ctx).songs.read();
+
+        let mut genres: BTreeMap<&str, (usize, u64)> = BTreeMap::new();
+        for song in songs.iter() {
+            let entry = genres.entry(&song.genre).or_default();
+            entry.0 += 1;
+            entry.1 += song.plays();
+        }
+        let mut genres: Vec<GenreStats> = genres
+            .into_iter()
+            .map(|(genre, (songs, plays))| GenreStats {
+                genre: genre.to_string(),
+                songs,
+                plays,
+            })
+            .collect();
+        genres.sort_by_key(|g| std::cmp::Reverse(g.plays));
+
+        LibraryStats {
+            song_count: songs.len(),
+            total_plays: songs.iter().map(|s| s.plays()).sum(),
+            rated_songs: songs.iter().filter(|s| s.rating_count > 0).count(),
+            genres,
+        }
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    // Count a play, like GET /songs/play/:id
+    async fn play(&self, ctx: &Context<'_>, id: u64) -> Result<SongObject> {
+        record_play(app_state(ctx), id)
+            .map(SongObject)
+            .ok_or_else(|| Error::new("Song not found"))
+    }
+
+    // Add a song, like POST /songs/new
+    async fn add_song(
+        &self,
+        ctx: &Context<'_>,
+        title: String,
+        artist: String,
+        genre: String,
+        album: Option<String>,
+    ) -> SongObject {
+        let Actor(user) = ctx.data_unchecked::<Actor>();
+        let payload = NewSongRequest {
+            title,
+            artist,
+            genre,
+            album,
+        };
+        SongObject(add_song(app_state(ctx), user, payload))
+    }
+}
+
+// Execute a GraphQL request
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "GraphQL request: `query`, and optionally `operationName` and `variables`"),
+    responses(
+        (status = 200, description = "GraphQL response with `data` and/or `errors`", body = Object),
+    )
+)]
+pub async fn handle_graphql(
+    State(state): State<Arc<AppState>>,
+    actor: Actor,
+    Json(request): Json<Request>,
+) -> Json<Response> {
+    let request = request.data(state).data(actor);
+    Json(schema().execute(request).await)
+}
+
+// In-browser GraphQL editor
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses((status = 200, description = "GraphiQL editor page", content_type = "text/html"))
+)]
+pub async fn handle_graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, load_state};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn playlists_are_queryable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let song = NewSongRequest {
+            title: "A".to_string(),
+            artist: "Grapher".to_string(),
+            genre: "Rock".to_string(),
+            album: None,
+        };
+        add_song(&state, "alice", song);
+        let payload =
+            serde_json::from_value(json!({"name": "Rock", "rule": {"query": "genre:rock"}}));
+        crate::playlists::handle_playlists_create(
+            State(state.clone()),
+            Actor("alice".to_string()),
+            Json(payload.unwrap()),
+        )
+        .await;
+
+        let request = Request::new("{ playlists { id name smart songCount songs { title } } }")
+            .data(state)
+            .data(Actor("alice".to_string()));
+        let response = schema().execute(request).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            json!({"playlists": [{"id": 1, "name": "Rock", "smart": true, "songCount": 1, "songs": [{"title": "A"}]}]})
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use crate::audit::AUDIT_FILE;
+use crate::library::SEQUENCE_FILE;
+use crate::playlists::{PLAYLIST_SEQUENCE_FILE, PLAYLISTS_FILE};
+use crate::ratings::RATINGS_FILE;
+use crate::webhooks::{DEAD_LETTERS_FILE, WEBHOOK_SEQUENCE_FILE, WEBHOOKS_FILE};
+use crate::{AppState, SONGS_FILE};
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use utoipa::ToSchema;
+
+// A data file that could not be read or written
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PersistenceError {
+    path: String,
+    error: String,
+    at: String,
+}
+
+// The data directory, and the outcome of the data file reads and writes
+// reported by every caller of `load_json`/`save_json`. The server keeps one
+// in `AppState`; CLI commands use their own.
+#[derive(Debug)]
+pub struct Persistence {
+    dir: PathBuf,
+    status: Mutex<PersistenceStatus>,
+}
+
+#[derive(Debug, Default)]
+struct PersistenceStatus {
+    last_saved_at: Option<String>,
+    last_error: Option<PersistenceError>,
+    // Files whose most recent write failed, with the error
+    failing: BTreeMap<String, String>,
+    // Data files whose most recent read failed; cleared once the file is
+    // read or written successfully
+    load_errors: BTreeMap<String, PersistenceError>,
+    // Data files with changes waiting for an outside edit of the file to be
+    // reloaded (see `save_songs`); cleared once the file is read or written
+    deferred: BTreeMap<String, String>,
+}
+
+fn persistence_error(path: &str, error: &str) -> PersistenceError {
+    PersistenceError {
+        path: path.to_string(),
+        error: error.to_string(),
+        at: Utc::now().to_rfc3339(),
+    }
+}
+
+impl Persistence {
+    pub fn new(dir: impl Into<PathBuf>) -> Persistence {
+        Persistence {
+            dir: dir.into(),
+            status: Mutex::default(),
+        }
+    }
+
+    // Location of a data file, given relative to the data directory
+    pub fn path(&self, file: impl AsRef<Path>) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    // Note a successful write of a data file
+    pub fn saved(&self, path: &str) {
+        let mut status = self.status.lock();
+        status.last_saved_at = Some(Utc::now().to_rfc3339());
+        status.failing.remove(path);
+        status.load_errors.remove(path);
+        status.deferred.remove(path);
+    }
+
+    // Note a failed write of a data file
+    pub fn save_failed(&self, path: &str, error: &str) {
+        let mut status = self.status.lock();
+        status.last_error = Some(persistence_error(path, error));
+        status.failing.insert(path.to_string(), error.to_string());
+    }
+
+    // Note a data file that was read (or is absent) without error
+    pub fn loaded(&self, path: &str) {
+        let mut status = self.status.lock();
+        status.load_errors.remove(path);
+        status.deferred.remove(path);
+    }
+
+    // Note a write of a data file that was put off because the file was
+    // edited outside the server
+    pub fn save_deferred(&self, path: &str) {
+        self.status
+            .lock()
+            .deferred
+            .entry(path.to_string())
+            .or_insert_with(|| Utc::now().to_rfc3339());
+    }
+
+    // Note a data file that exists but could not be read or parsed
+    pub fn load_failed(&self, path: &str, error: &str) {
+        self.status
+            .lock()
+            .load_errors
+            .insert(path.to_string(), persistence_error(path, error));
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    ready: bool,
+    // The last read of every data file succeeded
+    data_loaded: bool,
+    // The data files and their directories accept writes
+    data_writable: bool,
+    // The most recent write of every data file succeeded
+    last_save_ok: bool,
+    // No changes are waiting for an edited data file to be reloaded
+    saves_current: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ServerInfo {
+    version: &'static str,
+    started_at: String,
+    uptime_secs: u64,
+    song_count: usize,
+    visit_count: usize,
+    last_saved_at: Option<String>,
+    last_save_error: Option<PersistenceError>,
+    load_errors: Vec<PersistenceError>,
+}
+
+// Data files of the running server, and the directories they live in
+fn data_files(state: &AppState) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let persistence = &state.persistence;
+    let mut files: Vec<PathBuf> = [
+        SONGS_FILE,
+        SEQUENCE_FILE,
+        RATINGS_FILE,
+        AUDIT_FILE,
+        PLAYLISTS_FILE,
+        PLAYLIST_SEQUENCE_FILE,
+        WEBHOOKS_FILE,
+        WEBHOOK_SEQUENCE_FILE,
+        DEAD_LETTERS_FILE,
+    ]
+    .map(|file| persistence.path(file))
+    .to_vec();
+    let mut dirs = vec![persistence.path("")];
+
+    // The snapshot directory is created by the first snapshot; until then
+    // the directory it will be created in must be writable
+    let snapshots = state.config.snapshot_path();
+    match snapshots.parent() {
+        Some(parent) if !snapshots.exists() => dirs.push(parent.to_path_buf()),
+        _ => dirs.push(snapshots),
+    }
+
+    for library in state.libraries.read().values() {
+        let library = library.read();
+        let library_files = library.files();
+        let songs = persistence.path(&library_files.songs);
+        files.push(persistence.path(&library_files.sequence));
+        if let Some(dir) = songs.parent() {
+            dirs.push(dir.to_path_buf());
+        }
+        files.push(songs);
+    }
+    dirs.sort();
+    dirs.dedup();
+    (files, dirs)
+}
+
+// Check that the data files that exist can be opened for writing and that
+// new files can be created in their directories. Existing files are left
+// unchanged; each check creates and removes its own probe file, so
+// concurrent checks do not interfere.
+fn check_writable(files: &[PathBuf], dirs: &[PathBuf]) -> Result<(), Vec<String>> {
+    let mut errors = Vec::new();
+
+    for file in files {
+        if file.exists()
+            && let Err(e) = OpenOptions::new().append(true).open(file)
+        {
+            errors.push(format!("{} is not writable: {}", file.display(), e));
+        }
+    }
+
+    // Saves write a temporary file and rename it into place
+    static PROBES: AtomicU64 = AtomicU64::new(0);
+    for dir in dirs {
+        let probe = dir.join(format!(
+            ".readyz-{}-{}",
+            std::process::id(),
+            PROBES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let created = OpenOptions::new().write(true).create_new(true).open(&probe);
+        if let Err(e) = created.and_then(|_| fs::remove_file(&probe)) {
+            errors.push(format!("{} is not writable: {}", dir.display(), e));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// The process is up and serving requests
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "admin",
+    responses((status = 200, description = "The server is alive", body = String))
+)]
+pub async fn handle_healthz() -> &'static str {
+    "ok"
+}
+
+// Whether the server can serve and persist data
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Ready", body = Readiness),
+        (status = 503, description = "Not ready, with the failing checks", body = Readiness),
+    )
+)]
+pub async fn handle_readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let mut errors = Vec::new();
+
+    let (files, dirs) = data_files(&state);
+    let check = tokio::task::spawn_blocking(move || check_writable(&files, &dirs));
+    let data_writable = match check.await {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            errors.extend(e);
+            false
+        }
+        Err(e) => {
+            errors.push(e.to_string());
+            false
+        }
+    };
+
+    let (data_loaded, last_save_ok, saves_current) = {
+        let persistence = state.persistence.status.lock();
+        for load_error in persistence.load_errors.values() {
+            errors.push(format!("{}: {}", load_error.path, load_error.error));
+        }
+        for (path, error) in &persistence.failing {
+            errors.push(format!("{}: {}", path, error));
+        }
+        for (path, since) in &persistence.deferred {
+            errors.push(format!(
+                "{}: saves deferred since {} until the edited file is reloaded",
+                path, since
+            ));
+        }
+        (
+            persistence.load_errors.is_empty(),
+            persistence.failing.is_empty(),
+            persistence.deferred.is_empty(),
+        )
+    };
+
+    let ready = data_loaded && data_writable && last_save_ok && saves_current;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(Readiness {
+            ready,
+            data_loaded,
+            data_writable,
+            last_save_ok,
+            saves_current,
+            errors,
+        }),
+    )
+}
+
+// Uptime, library size and persistence status
+#[utoipa::path(
+    get,
+    path = "/admin/info",
+    tag = "admin",
+    responses((status = 200, description = "Server status", body = ServerInfo))
+)]
+pub async fn handle_admin_info(State(state): State<Arc<AppState>>) -> Json<ServerInfo> {
+    let song_count = state.songs.read().len();
+    let persistence = state.persistence.status.lock();
+
+    Json(ServerInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        started_at: state.started_at.to_rfc3339(),
+        uptime_secs: (Utc::now() - state.started_at).num_seconds().max(0) as u64,
+        song_count,
+        visit_count: state.visit_count.load(Ordering::SeqCst),
+        last_saved_at: persistence.last_saved_at.clone(),
+        last_save_error: persistence.last_error.clone(),
+        load_errors: persistence.load_errors.values().cloned().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, load_state, save_json};
+    use tempfile::TempDir;
+
+    fn state(dir: &TempDir) -> Arc<AppState> {
+        load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .0
+    }
+
+    async fn readiness(state: &Arc<AppState>) -> (StatusCode, Readiness) {
+        let (status, Json(readiness)) = handle_readyz(State(state.clone())).await;
+        (status, readiness)
+    }
+
+    #[test]
+    fn load_and_save_errors_clear_on_success() {
+        let persistence = Persistence::new(".");
+        persistence.load_failed("a.json", "invalid data file");
+        persistence.save_failed("b.json", "disk full");
+        {
+            let status = persistence.status.lock();
+            assert_eq!(status.load_errors.len(), 1);
+            assert_eq!(status.failing.len(), 1);
+            assert_eq!(status.last_error.as_ref().unwrap().path, "b.json");
+        }
+
+        // A later successful read or write of the same file clears its error
+        persistence.loaded("a.json");
+        persistence.saved("b.json");
+        let status = persistence.status.lock();
+        assert!(status.load_errors.is_empty());
+        assert!(status.failing.is_empty());
+        assert!(status.last_saved_at.is_some());
+    }
+
+    #[test]
+    fn probes_every_data_file_and_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let (files, dirs) = data_files(&state(&dir));
+
+        for file in [DEAD_LETTERS_FILE, WEBHOOK_SEQUENCE_FILE, RATINGS_FILE] {
+            assert!(files.contains(&dir.path().join(file)), "{}", file);
+        }
+        // The snapshot directory does not exist yet, so its parent is probed
+        assert_eq!(dirs, vec![dir.path().join("")]);
+
+        fs::create_dir(dir.path().join("snapshots")).unwrap();
+        let (_, dirs) = data_files(&state(&dir));
+        assert!(dirs.contains(&dir.path().join("snapshots")));
+    }
+
+    #[test]
+    fn concurrent_checks_do_not_interfere() {
+        let dir = tempfile::tempdir().unwrap();
+        let dirs = vec![dir.path().to_path_buf()];
+
+        std::thread::scope(|scope| {
+            let checks: Vec<_> = (0..16)
+                .map(|_| scope.spawn(|| (0..50).all(|_| check_writable(&[], &dirs).is_ok())))
+                .collect();
+            for check in checks {
+                assert!(check.join().unwrap());
+            }
+        });
+        // No probe files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let missing = vec![dir.path().join("missing")];
+        assert_eq!(check_writable(&[], &missing).unwrap_err().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn not_ready_until_an_unreadable_file_is_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, ready) = readiness(&state(&dir)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(ready.ready && ready.errors.is_empty());
+
+        fs::write(dir.path().join(RATINGS_FILE), "not json").unwrap();
+        let state = state(&dir);
+        let (status, ready) = readiness(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!ready.data_loaded);
+        assert!(ready.data_writable && ready.last_save_ok);
+        assert!(ready.errors[0].starts_with("ratings.json: invalid data file"));
+
+        save_json(&state.persistence, RATINGS_FILE, &Vec::<()>::new());
+        let (status, _) = readiness(&state).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
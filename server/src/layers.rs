@@ -0,0 +1,294 @@
+use crate::AppState;
+use crate::config::Config;
+use axum::{
+    Router,
+    http::{HeaderName, HeaderValue, Method, header},
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{NotForContentType, Predicate, SizeAbove},
+};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tracing::warn;
+
+// Cross-origin, compression and security header middleware, configured from
+// the environment (see `config.rs`)
+pub fn apply(router: Router<Arc<AppState>>, config: &Config) -> Router<Arc<AppState>> {
+    let mut router = router;
+
+    if config.security_headers {
+        router = security_headers(router, config);
+    }
+
+    if config.compression {
+        // Audio is already compressed, and compressing a byte range would
+        // break Range requests
+        let predicate = SizeAbove::new(config.compression_min_size)
+            .and(NotForContentType::const_new("audio/"))
+            .and(NotForContentType::IMAGES)
+            .and(NotForContentType::SSE);
+        router = router.layer(
+            CompressionLayer::new()
+                .gzip(true)
+                .br(true)
+                .compress_when(predicate),
+        );
+    }
+
+    // Outermost, so preflight requests are answered before anything else
+    if let Some(cors) = cors(config) {
+        router = router.layer(cors);
+    }
+
+    router
+}
+
+fn cors(config: &Config) -> Option<CorsLayer> {
+    if config.cors_origins.is_empty() {
+        return None;
+    }
+
+    let origin = if config.cors_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.cors_origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .inspect_err(|_| warn!(origin, "ignoring invalid CORS origin"))
+                .ok()
+        }))
+    };
+
+    let methods: Vec<Method> = config
+        .cors_methods
+        .iter()
+        .filter_map(|method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .inspect_err(|_| warn!(method, "ignoring invalid CORS method"))
+                .ok()
+        })
+        .collect();
+
+    let cors = CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(methods)
+        .expose_headers([HeaderName::from_static("x-request-id")])
+        .max_age(Duration::from_secs(600));
+
+    let cors = if config.cors_headers.iter().any(|h| h == "*") {
+        cors.allow_headers(Any)
+    } else {
+        cors.allow_headers(
+            config
+                .cors_headers
+                .iter()
+                .filter_map(|name| {
+                    HeaderName::from_bytes(name.to_lowercase().as_bytes())
+                        .inspect_err(|_| warn!(name, "ignoring invalid CORS header"))
+                        .ok()
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    Some(cors)
+}
+
+// Standard hardening headers; handlers that set one themselves keep theirs
+fn security_headers(router: Router<Arc<AppState>>, config: &Config) -> Router<Arc<AppState>> {
+    let mut headers = vec![
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ),
+        (header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY")),
+        (
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        ),
+    ];
+
+    // Browsers ignore HSTS over plain HTTP, so it is only sent with TLS
+    if config.tls_enabled() {
+        headers.push((
+            header::STRICT_TRANSPORT_SECURITY,
+            HeaderValue::from_static("max-age=31536000"),
+        ));
+    }
+
+    if let Some(policy) = &config.content_security_policy {
+        match HeaderValue::from_str(policy) {
+            Ok(value) => headers.push((header::CONTENT_SECURITY_POLICY, value)),
+            Err(_) => warn!("ignoring invalid CONTENT_SECURITY_POLICY"),
+        }
+    }
+
+    headers.into_iter().fold(router, |router, (name, value)| {
+        router.layer(SetResponseHeaderLayer::if_not_present(name, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::response::Response;
+    use serde_json::json;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    // A data directory with enough songs for a compressible search response,
+    // the first linked to an audio file
+    fn data_dir() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let audio = dir.path().join("song.mp3");
+        std::fs::write(&audio, vec![0u8; 4096]).unwrap();
+
+        let songs: Vec<_> = (1..=50)
+            .map(|id| {
+                json!({
+                    "id": id,
+                    "title": format!("Song {}", id),
+                    "artist": "Layered",
+                    "genre": "Pop",
+                    "play_count": 0,
+                    "file": {"path": audio.display().to_string(), "size": 4096, "modified": 0},
+                })
+            })
+            .collect();
+        std::fs::write(dir.path().join("songs.json"), json!(songs).to_string()).unwrap();
+        dir
+    }
+
+    async fn send(config: Config, request: Request<Body>) -> Response {
+        crate::app(config).oneshot(request).await.unwrap()
+    }
+
+    fn config(dir: &TempDir) -> Config {
+        Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    fn encoding(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn preflight_requests_from_allowed_origins_are_answered() {
+        let dir = data_dir();
+        let cors = Config {
+            cors_origins: vec!["https://app.example".to_string()],
+            ..config(&dir)
+        };
+        let preflight = |origin: &str| {
+            Request::options("/songs/search")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(cors.clone(), preflight("https://app.example")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+                .to_str()
+                .unwrap()
+                .contains("POST")
+        );
+
+        let response = send(cors, preflight("https://evil.example")).await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+
+        // Without CORS_ORIGINS no CORS headers are sent at all
+        let response = send(config(&dir), preflight("https://app.example")).await;
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn large_responses_are_compressed_but_audio_is_not() {
+        let dir = data_dir();
+        let get = |uri: &str, encoding: &str| {
+            Request::get(uri)
+                .header(header::ACCEPT_ENCODING, encoding)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = send(config(&dir), get("/songs/search", "gzip")).await;
+        assert_eq!(encoding(&response), Some("gzip"));
+        let response = send(config(&dir), get("/songs/search", "br")).await;
+        assert_eq!(encoding(&response), Some("br"));
+
+        // Small responses are sent as they are
+        let response = send(config(&dir), get("/songs/search?title=Song%2050", "gzip")).await;
+        assert_eq!(encoding(&response), None);
+
+        let response = send(config(&dir), get("/songs/1/stream", "gzip, br")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "audio/mpeg");
+        assert_eq!(encoding(&response), None);
+
+        let mut request = get("/songs/1/stream", "gzip, br");
+        request
+            .headers_mut()
+            .insert(header::RANGE, HeaderValue::from_static("bytes=0-2047"));
+        let response = send(config(&dir), request).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(encoding(&response), None);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 2048);
+    }
+
+    #[tokio::test]
+    async fn hsts_is_only_sent_with_tls() {
+        let dir = data_dir();
+        let get = || Request::get("/count").body(Body::empty()).unwrap();
+
+        let response = send(config(&dir), get()).await;
+        let headers = response.headers();
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+        let tls = Config {
+            tls_dev: true,
+            ..config(&dir)
+        };
+        let response = send(tls, get()).await;
+        assert_eq!(
+            response.headers()[header::STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000"
+        );
+
+        let off = Config {
+            security_headers: false,
+            ..config(&dir)
+        };
+        let response = send(off, get()).await;
+        assert!(!response.headers().contains_key(header::X_FRAME_OPTIONS));
+    }
+}
@@ -0,0 +1,859 @@
+mod audit;
+mod catalog;
+mod config;
+mod duplicates;
+mod graphql;
+mod health;
+mod layers;
+mod libraries;
+mod library;
+mod logging;
+mod negotiate;
+mod openapi;
+mod playlists;
+mod plays;
+mod query;
+mod queue;
+mod ratings;
+mod reload;
+mod scanner;
+mod scheduler;
+mod snapshot;
+mod stream;
+mod tls;
+mod trending;
+mod webhooks;
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware,
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::audit::{Actor, AuditEntry};
+pub use crate::config::Config;
+use crate::health::Persistence;
+use crate::library::{FileStamp, Library, LibraryFiles, LibrarySong, StoredSongs};
+use crate::negotiate::{Format, Negotiated};
+use crate::playlists::Playlists;
+use crate::query::QueryError;
+use crate::queue::PlayQueue;
+use crate::ratings::UserRating;
+use crate::reload::ReloadStatus;
+use crate::scanner::ScanSummary;
+use crate::scheduler::Scheduler;
+use crate::trending::Trending;
+use crate::webhooks::Webhooks;
+use music_types::{AudioFile, NewSongRequest, Song, SongSearchQuery, SongSort, SortOrder};
+
+// Used when returning an error message as JSON
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorMessage {
+    error: &'static str,
+}
+
+// Structure for receiving a music folder scan request
+#[derive(Debug, Deserialize, ToSchema)]
+struct ScanRequest {
+    #[schema(value_type = String)]
+    dir: PathBuf,
+}
+
+// Global shared application state
+#[derive(Debug)]
+struct AppState {
+    config: Config,
+    started_at: DateTime<Utc>,
+    visit_count: AtomicUsize,
+    // The default library, served by the unscoped /songs routes
+    songs: Arc<RwLock<Library>>,
+    // Named libraries served under /libraries/NAME (see `libraries.rs`)
+    libraries: RwLock<BTreeMap<String, Arc<RwLock<Library>>>>,
+    ratings: RwLock<Vec<UserRating>>,
+    audit: RwLock<Vec<AuditEntry>>,
+    // In-memory play queues by session id
+    queues: Mutex<HashMap<String, PlayQueue>>,
+    // Outcome of the last reload of an externally edited songs.json
+    reload: Mutex<ReloadStatus>,
+    webhooks: Webhooks,
+    // Status of the periodic maintenance jobs
+    jobs: Scheduler,
+    trending: Mutex<Trending>,
+    // Regular and smart playlists of the default library. Lock order: songs
+    // before playlists, playlists before the audit log.
+    playlists: RwLock<Playlists>,
+    // Outcome of the data file reads and writes, for /readyz
+    persistence: Persistence,
+}
+
+const SONGS_FILE: &str = "songs.json";
+
+// Load a JSON file from disk (default value if the file is missing or
+// invalid). `path` is relative to the data directory.
+fn load_json<T: serde::de::DeserializeOwned + Default>(persistence: &Persistence, path: &str) -> T {
+    let file = persistence.path(path);
+    if !file.exists() {
+        persistence.loaded(path);
+        return T::default();
+    }
+
+    let result = fs::read_to_string(&file)
+        .map_err(|e| format!("failed to read data file: {}", e))
+        .and_then(|data| {
+            serde_json::from_str(&data).map_err(|e| format!("invalid data file: {}", e))
+        });
+    match result {
+        Ok(value) => {
+            persistence.loaded(path);
+            value
+        }
+        Err(e) => {
+            warn!(path, error = %e, "ignoring data file");
+            persistence.load_failed(path, &e);
+            T::default()
+        }
+    }
+}
+
+// Save a value to disk as JSON. The data is written to a temporary file and
+// renamed into place, so readers never see a partially written file.
+// Returns whether the file was written. `path` is relative to the data
+// directory.
+fn save_json<T: Serialize + ?Sized>(persistence: &Persistence, path: &str, value: &T) -> bool {
+    let file = persistence.path(path);
+    let tmp = persistence.path(format!("{}.tmp", path));
+    let result = serde_json::to_string(value)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&tmp, json).map_err(|e| e.to_string()))
+        .and_then(|()| fs::rename(&tmp, &file).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        error!(path, error = %e, "failed to save data file");
+        persistence.save_failed(path, &e);
+        return false;
+    }
+    persistence.saved(path);
+    true
+}
+
+// Load songs and the id sequence from disk (if the files exist)
+fn load_library(persistence: &Persistence, config: &Config, files: LibraryFiles) -> Library {
+    let songs = load_json(persistence, &files.songs);
+    let sequence: library::Sequence = load_json(persistence, &files.sequence);
+    let stamp = FileStamp::of(persistence.path(&files.songs));
+    let mut library = Library::new(songs, sequence, config.public_ids, files);
+    library.mark_synced(stamp);
+    library
+}
+
+// Save the song list and id sequence to disk. If a watched songs file
+// (songs.json) was edited since it was last loaded or saved, the save is left
+// to the reload (see `reload.rs`), which merges these changes with the edit.
+fn save_songs(persistence: &Persistence, songs: &mut Library) {
+    let files = songs.files().clone();
+    if save_deferred(persistence, songs) {
+        warn!(
+            path = files.songs,
+            "songs file changed on disk; saving after it is reloaded"
+        );
+        persistence.save_deferred(&files.songs);
+        return;
+    }
+
+    if save_json(persistence, &files.songs, &StoredSongs(&songs.to_vec())) {
+        save_json(persistence, &files.sequence, &songs.sequence());
+        songs.mark_synced(FileStamp::of(persistence.path(&files.songs)));
+    }
+}
+
+// Whether saves of a library wait for its songs file to be reloaded
+fn save_deferred(persistence: &Persistence, songs: &Library) -> bool {
+    let files = songs.files();
+    files.watched && FileStamp::of(persistence.path(&files.songs)) != songs.stamp()
+}
+
+// Save what is still in memory only when the server stops. Songs whose save
+// is deferred, e.g. because songs.json was edited into an invalid file, are
+// written next to it instead, so the changes are not lost. Returns the side
+// file written, if any.
+fn save_on_exit(state: &AppState) -> Option<PathBuf> {
+    plays::checkpoint(state);
+    state.webhooks.save_dead_letters(&state.persistence);
+
+    let mut songs = state.songs.write();
+    if !save_deferred(&state.persistence, &songs) {
+        return None;
+    }
+    // Let a reload that is still due merge the edit first
+    drop(songs);
+    reload::reload(state, false);
+    songs = state.songs.write();
+    save_songs(&state.persistence, &mut songs);
+    if !save_deferred(&state.persistence, &songs) {
+        return None;
+    }
+
+    let side = format!("{}.unsaved", songs.files().songs);
+    if !save_json(&state.persistence, &side, &StoredSongs(&songs.to_vec())) {
+        return None;
+    }
+    let path = state.persistence.path(&side);
+    error!(
+        path = %path.display(),
+        "songs.json was edited and could not be reloaded; wrote the unsaved songs next to it"
+    );
+    Some(path)
+}
+
+// Current time as seconds since the Unix epoch
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Basic welcome page, or the web UI when a browser asks for HTML
+async fn handle_root(headers: HeaderMap) -> Response {
+    let wants_html = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+
+    if wants_html {
+        return handle_ui().await.into_response();
+    }
+    "Welcome to the Rust-powered web server!".into_response()
+}
+
+// Single-page web UI for browsing, adding and playing songs, embedded in the binary
+async fn handle_ui() -> Html<&'static str> {
+    Html(include_str!("../static/ui.html"))
+}
+
+// Increments and returns the global visit counter
+async fn handle_count(State(state): State<Arc<AppState>>) -> String {
+    let prev = state.visit_count.fetch_add(1, Ordering::SeqCst);
+    let current = prev + 1;
+    format!("Visit count: {}", current)
+}
+
+// Add a new song to the library
+#[utoipa::path(
+    post,
+    path = "/songs/new",
+    tag = "songs",
+    request_body = NewSongRequest,
+    responses((status = 200, description = "The song that was added", body = Song))
+)]
+async fn handle_songs_new(
+    State(state): State<Arc<AppState>>,
+    Actor(user): Actor,
+    Json(payload): Json<NewSongRequest>,
+) -> (StatusCode, Json<Song>) {
+    (StatusCode::OK, Json(add_song(&state, &user, payload)))
+}
+
+// Add a song to the default library and record it in the audit log
+fn add_song(state: &AppState, user: &str, payload: NewSongRequest) -> Song {
+    let mut songs = state.songs.write();
+    let new_song = insert_song(&state.persistence, &mut songs, payload);
+    audit::record(state, user, "create", audit::change(None, Some(&new_song)));
+
+    new_song
+}
+
+// Create a song with the next id in a library and persist it
+fn insert_song(persistence: &Persistence, songs: &mut Library, payload: NewSongRequest) -> Song {
+    let new_song = Song {
+        id: songs.allocate_id(),
+        uid: None,
+        title: payload.title,
+        artist: payload.artist,
+        album: payload.album.filter(|a| !a.trim().is_empty()),
+        genre: payload.genre,
+        play_count: 0,
+        average_rating: None,
+        rating_count: 0,
+        favourite_count: 0,
+        file: None,
+    };
+
+    let new_song = songs.push(new_song).song();
+    save_songs(persistence, songs);
+
+    new_song
+}
+
+// Search for songs by title/artist/genre
+#[utoipa::path(
+    get,
+    path = "/songs/search",
+    tag = "songs",
+    params(SongSearchQuery),
+    responses(
+        (status = 200, description = "Songs matching every given filter", body = [Song], content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 400, description = "The query expression could not be parsed", body = QueryError),
+    )
+)]
+async fn handle_songs_search(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SongSearchQuery>,
+    format: Format,
+) -> Result<Negotiated<Vec<Song>>, (StatusCode, Json<QueryError>)> {
+    let songs = state.songs.read();
+
+    search_songs(&songs, &query)
+        .map(|results| Negotiated(format, results))
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(e)))
+}
+
+// Apply search filters, the query expression and sorting to a song list
+fn search_songs(songs: &[LibrarySong], query: &SongSearchQuery) -> Result<Vec<Song>, QueryError> {
+    let expr = match query.query.as_deref().map(str::trim) {
+        Some(q) if !q.is_empty() => Some(query::parse(q)?),
+        _ => None,
+    };
+
+    let title_filter = query.title.as_ref().map(|s| s.to_lowercase());
+    let artist_filter = query.artist.as_ref().map(|s| s.to_lowercase());
+    let genre_filter = query.genre.as_ref().map(|s| s.to_lowercase());
+
+    let mut results: Vec<Song> = songs
+        .iter()
+        .filter(|song| {
+            let title = song.title.to_lowercase();
+            let artist = song.artist.to_lowercase();
+            let genre = song.genre.to_lowercase();
+
+            // Apply title filter if provided
+            if let Some(ref filter) = title_filter
+                && !title.contains(filter)
+            {
+                return false;
+            }
+
+            // Apply artist filter if provided
+            if let Some(ref filter) = artist_filter
+                && !artist.contains(filter)
+            {
+                return false;
+            }
+
+            // Apply genre filter if provided
+            if let Some(ref filter) = genre_filter
+                && !genre.contains(filter)
+            {
+                return false;
+            }
+
+            // Apply the query expression if provided
+            if let Some(ref expr) = expr {
+                return expr.matches(song);
+            }
+
+            true
+        })
+        .map(LibrarySong::song)
+        .collect();
+
+    if let Some(sort) = query.sort {
+        sort_songs(&mut results, sort, query.order);
+    }
+
+    Ok(results)
+}
+
+// Sort songs by a field. Numeric fields default to descending order; songs
+// without a rating always come last.
+fn sort_songs(songs: &mut [Song], sort: SongSort, order: Option<SortOrder>) {
+    let order = order.unwrap_or(match sort {
+        SongSort::Id | SongSort::Title => SortOrder::Asc,
+        SongSort::Plays | SongSort::Rating | SongSort::Favourites => SortOrder::Desc,
+    });
+
+    songs.sort_by(|a, b| {
+        let ordering = match sort {
+            SongSort::Id => a.id.cmp(&b.id),
+            SongSort::Title => a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+            SongSort::Plays => a.play_count.cmp(&b.play_count),
+            SongSort::Favourites => a.favourite_count.cmp(&b.favourite_count),
+            SongSort::Rating => match (a.average_rating, b.average_rating) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (Some(_), None) => return std::cmp::Ordering::Less,
+                (None, Some(_)) => return std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            },
+        };
+        if order == SortOrder::Desc {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+// Count a play of a song in the default library and notify webhooks
+fn record_play(state: &AppState, id: u64) -> Option<Song> {
+    let song = play_song(&state.songs.read(), id)?;
+    state.webhooks.emit(webhooks::Event::Played, &song);
+
+    Some(song)
+}
+
+// Increment a song's play count, returning the updated song. Plays are
+// written to disk by the next checkpoint (see `plays.rs`).
+fn play_song(songs: &Library, id: u64) -> Option<Song> {
+    songs.play(id).map(LibrarySong::song)
+}
+
+// Look up a song by its opaque public id
+#[utoipa::path(
+    get,
+    path = "/songs/uid/{uid}",
+    tag = "songs",
+    params(("uid" = String, Path, description = "Public song id (ULID)")),
+    responses(
+        (status = 200, description = "The song with this public id", body = Song),
+        (status = 404, description = "No song with this public id", body = ErrorMessage),
+    )
+)]
+async fn handle_songs_by_uid(
+    State(state): State<Arc<AppState>>,
+    Path(uid): Path<String>,
+) -> Result<Json<Song>, (StatusCode, Json<ErrorMessage>)> {
+    match state.songs.read().get_by_uid(&uid) {
+        Some(song) => Ok(Json(song.song())),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorMessage {
+                error: "Song not found",
+            }),
+        )),
+    }
+}
+
+// Play a song by ID
+#[utoipa::path(
+    get,
+    path = "/songs/play/{id}",
+    tag = "songs",
+    params(("id" = u64, Path, description = "Song id")),
+    responses(
+        (status = 200, description = "The song with its play count incremented, or `{\"error\": \"Song not found\"}` if no song has this id (kept for older clients; GET /libraries/default/songs/play/{id} answers 404 instead)", body = Song),
+    )
+)]
+async fn handle_songs_play(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Song>, (StatusCode, Json<ErrorMessage>)> {
+    if let Some(song) = record_play(&state, id) {
+        return Ok(Json(song));
+    }
+
+    Err((
+        StatusCode::OK,
+        Json(ErrorMessage {
+            error: "Song not found",
+        }),
+    ))
+}
+
+// Scan a music folder and upsert the songs found in it. Only folders under
+// MUSIC_DIRS can be scanned; `server scan DIR` has no such limit.
+#[utoipa::path(
+    post,
+    path = "/admin/scan",
+    tag = "admin",
+    request_body = ScanRequest,
+    responses(
+        (status = 200, description = "What the scan added, updated and could not read", body = ScanSummary),
+        (status = 400, description = "The music directory could not be read, with the reason", body = ScanError),
+        (status = 403, description = "The folder does not exist or is not under a configured music directory", body = ErrorMessage),
+    )
+)]
+async fn handle_admin_scan(
+    State(state): State<Arc<AppState>>,
+    Actor(user): Actor,
+    Json(payload): Json<ScanRequest>,
+) -> Result<Json<ScanSummary>, Response> {
+    if !scanner::allowed(&state.config.music_dirs, &payload.dir) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorMessage {
+                error: "Only folders under MUSIC_DIRS can be scanned",
+            }),
+        )
+            .into_response());
+    }
+    let snapshot = state.songs.read().to_vec();
+
+    // Walking the folder and reading tags is blocking I/O
+    let result = tokio::task::spawn_blocking(move || scanner::walk(&payload.dir, &snapshot))
+        .await
+        .unwrap()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(e)).into_response())?;
+
+    let mut songs = state.songs.write();
+    let before = songs.to_vec();
+    let summary = scanner::apply(&mut songs, result);
+    save_songs(&state.persistence, &mut songs);
+    audit::record(&state, &user, "scan", audit::diff(&before, &songs.to_vec()));
+
+    Ok(Json(summary))
+}
+
+// `server scan <dir>`: scan a music folder into songs.json and exit
+fn run_scan(config: &Config, dir: &str) {
+    let persistence = Persistence::new(&config.data_dir);
+    let mut songs = load_library(&persistence, config, LibraryFiles::default());
+
+    match scanner::walk(FsPath::new(dir), &songs.to_vec()) {
+        Ok(result) => {
+            let before = songs.to_vec();
+            let summary = scanner::apply(&mut songs, result);
+            save_songs(&persistence, &mut songs);
+            audit::record_to_file(
+                &persistence,
+                None,
+                "cli",
+                "scan",
+                audit::diff(&before, &songs.to_vec()),
+            );
+            println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Run the command line: start the server, or run a subcommand
+pub async fn run() {
+    let config = Config::from_env();
+    logging::init(&config.log_level);
+
+    let args: Vec<String> = std::env::args().collect();
+    let snapshot_dir = config.snapshot_path();
+    let result = match args.get(1).map(String::as_str) {
+        Some("scan") => {
+            let Some(dir) = args.get(2) else {
+                eprintln!("Usage: server scan <dir>");
+                std::process::exit(1);
+            };
+            return run_scan(&config, dir);
+        }
+        Some("snapshot") => Some(snapshot::run_create(&config, &snapshot_dir)),
+        Some("snapshots") => return snapshot::run_list(&snapshot_dir),
+        Some("restore") => {
+            let Some(name) = args.get(2) else {
+                eprintln!("Usage: server restore <snapshot>");
+                std::process::exit(1);
+            };
+            Some(snapshot::run_restore(&config, &snapshot_dir, name))
+        }
+        Some(other) => {
+            eprintln!("Unknown command: {}", other);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    // Subcommands run to completion instead of starting the server
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Load the certificate before binding anything, so a bad TLS setup
+    // stops the server instead of silently falling back to plain HTTP
+    let tls = if config.tls_enabled() {
+        match tls::server_config(&config) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let bind_address = config.bind_address.clone();
+    let https_port = config.https_port;
+    let handshake_timeout = config.tls_handshake_timeout;
+
+    let state = start(config);
+    let app = router(state.clone());
+
+    // On Ctrl-C or SIGTERM, stop accepting connections and let the requests
+    // in flight finish
+    let shutdown = CancellationToken::new();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            plays::shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    // Bind the server to port 8080 (localhost unless BIND_ADDRESS is set)
+    let listener = TcpListener::bind((bind_address.as_str(), 8080))
+        .await
+        .unwrap();
+
+    let Some(tls) = tls else {
+        info!(
+            "The server is currently listening on {}:8080.",
+            bind_address
+        );
+
+        // Start the Axum server
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await
+            .unwrap();
+        save_on_shutdown(state).await;
+        return;
+    };
+
+    // With TLS, the app is served over HTTPS and port 8080 only redirects
+    let tls_listener = TcpListener::bind((bind_address.as_str(), https_port))
+        .await
+        .unwrap();
+    info!(
+        "The server is currently listening on {}:{} (HTTPS), redirecting from port 8080.",
+        bind_address, https_port
+    );
+
+    let redirect =
+        tls::redirect_router(https_port).layer(middleware::from_fn(logging::trace_requests));
+    let stopped = shutdown.clone().cancelled_owned();
+    let redirect = tokio::spawn(async move {
+        axum::serve(listener, redirect)
+            .with_graceful_shutdown(stopped)
+            .await
+            .unwrap()
+    });
+    tls::serve(
+        tls_listener,
+        tls,
+        app,
+        handshake_timeout,
+        shutdown.cancelled_owned(),
+    )
+    .await;
+    redirect.await.unwrap();
+    save_on_shutdown(state).await;
+}
+
+// Save the plays counted since the last checkpoint, the dead letters and any
+// changes whose save was deferred, once the server has stopped
+async fn save_on_shutdown(state: Arc<AppState>) {
+    tokio::task::spawn_blocking(move || save_on_exit(&state))
+        .await
+        .unwrap();
+}
+
+// Load the state from disk. The receiver delivers queued webhook events (see
+// `webhooks::run`).
+fn load_state(config: Config) -> (Arc<AppState>, webhooks::Receiver) {
+    let persistence = Persistence::new(&config.data_dir);
+
+    // Load songs from disk (if file exists)
+    let songs = load_library(&persistence, &config, LibraryFiles::default());
+    let named = libraries::load_all(&persistence, &config);
+
+    let (webhooks, deliveries) = Webhooks::load(&persistence);
+    let jobs = Scheduler::new(&config);
+
+    // Build shared global state for handlers
+    let state = Arc::new(AppState {
+        config,
+        started_at: Utc::now(),
+        visit_count: AtomicUsize::new(0),
+        songs: Arc::new(RwLock::new(songs)),
+        libraries: RwLock::new(named),
+        ratings: RwLock::new(load_json(&persistence, ratings::RATINGS_FILE)),
+        audit: RwLock::new(load_json(&persistence, audit::AUDIT_FILE)),
+        queues: Mutex::new(HashMap::new()),
+        reload: Mutex::new(ReloadStatus::default()),
+        webhooks,
+        jobs,
+        trending: Mutex::new(Trending::default()),
+        playlists: RwLock::new(playlists::load(&persistence)),
+        persistence,
+    });
+    (state, deliveries)
+}
+
+// Load the state from disk and start the background tasks. Must be called
+// from within a Tokio runtime.
+fn start(config: Config) -> Arc<AppState> {
+    let (state, deliveries) = load_state(config);
+
+    // Send webhook deliveries in the background
+    tokio::spawn(webhooks::run(state.clone(), deliveries));
+
+    // Pick up hand edits to songs.json while running
+    tokio::spawn(reload::watch(state.clone()));
+
+    // Maintenance jobs, including saving play counts periodically
+    scheduler::start(&state);
+
+    state
+}
+
+// The application with its state and the data files in
+// `config.data_dir`, ready to be served. Used by `run` and by in-process
+// tests.
+pub fn app(config: Config) -> Router {
+    router(start(config))
+}
+
+fn router(state: Arc<AppState>) -> Router {
+    // Define all routes in the application
+    let app = Router::new()
+        .route("/", get(handle_root)) // GET /
+        .route("/ui", get(handle_ui)) // GET /ui
+        .route("/count", get(handle_count)) // GET /count
+        .route("/healthz", get(health::handle_healthz)) // GET /healthz
+        .route("/readyz", get(health::handle_readyz)) // GET /readyz
+        .route("/songs/new", post(handle_songs_new)) // POST /songs/new
+        .route("/songs/search", get(handle_songs_search)) // GET /songs/search
+        .route("/songs/play/:id", get(handle_songs_play)) // GET /songs/play/ID
+        .route(
+            "/songs/duplicates",
+            get(duplicates::handle_songs_duplicates),
+        ) // GET /songs/duplicates
+        .route("/songs/merge", post(duplicates::handle_songs_merge)) // POST /songs/merge
+        .route("/songs/uid/:uid", get(handle_songs_by_uid)) // GET /songs/uid/UID
+        .route("/songs/trending", get(trending::handle_songs_trending)) // GET /songs/trending
+        .route("/artists", get(catalog::handle_artists_list)) // GET /artists
+        .route("/artists/:name/songs", get(catalog::handle_artist_songs)) // GET /artists/NAME/songs
+        .route("/albums/:id", get(catalog::handle_album_get)) // GET /albums/ID
+        .route(
+            "/playlists",
+            get(playlists::handle_playlists_list).post(playlists::handle_playlists_create),
+        ) // GET, POST /playlists
+        .route(
+            "/playlists/:id",
+            get(playlists::handle_playlists_get).delete(playlists::handle_playlists_delete),
+        ) // GET, DELETE /playlists/ID
+        .route(
+            "/playlists/:id/songs",
+            post(playlists::handle_playlists_add_songs),
+        ) // POST /playlists/ID/songs
+        .route(
+            "/libraries",
+            get(libraries::handle_libraries_list).post(libraries::handle_libraries_create),
+        ) // GET, POST /libraries
+        .route(
+            "/libraries/:lib/songs/new",
+            post(libraries::handle_library_songs_new),
+        ) // POST /libraries/LIB/songs/new
+        .route(
+            "/libraries/:lib/songs/search",
+            get(libraries::handle_library_songs_search),
+        ) // GET /libraries/LIB/songs/search
+        .route(
+            "/libraries/:lib/songs/play/:id",
+            get(libraries::handle_library_songs_play),
+        ) // GET /libraries/LIB/songs/play/ID
+        .route(
+            "/libraries/:lib/songs/uid/:uid",
+            get(libraries::handle_library_songs_by_uid),
+        ) // GET /libraries/LIB/songs/uid/UID
+        .route(
+            "/libraries/:lib/songs/:id/stream",
+            get(libraries::handle_library_songs_stream),
+        ) // GET /libraries/LIB/songs/ID/stream
+        .route(
+            "/libraries/:lib/songs/duplicates",
+            get(libraries::handle_library_songs_duplicates),
+        ) // GET /libraries/LIB/songs/duplicates
+        .route("/songs/:id/stream", get(stream::handle_songs_stream)) // GET /songs/ID/stream
+        .route("/songs/:id/rating", post(ratings::handle_songs_rate)) // POST /songs/ID/rating
+        .route(
+            "/songs/:id/favourite",
+            post(ratings::handle_songs_favourite),
+        ) // POST /songs/ID/favourite
+        .route("/songs/:id/reviews", get(ratings::handle_songs_reviews)) // GET /songs/ID/reviews
+        .route(
+            "/users/:user/favourites",
+            get(ratings::handle_user_favourites),
+        ) // GET /users/USER/favourites
+        .route("/queue", post(queue::handle_queue_create)) // POST /queue
+        .route(
+            "/queue/:id",
+            get(queue::handle_queue_get).delete(queue::handle_queue_delete),
+        ) // GET, DELETE /queue/ID
+        .route("/queue/:id/enqueue", post(queue::handle_queue_enqueue)) // POST /queue/ID/enqueue
+        .route("/queue/:id/next", post(queue::handle_queue_next)) // POST /queue/ID/next
+        .route("/queue/:id/skip", post(queue::handle_queue_skip)) // POST /queue/ID/skip
+        .route("/queue/:id/previous", post(queue::handle_queue_previous)) // POST /queue/ID/previous
+        .route("/queue/:id/mode", post(queue::handle_queue_mode)) // POST /queue/ID/mode
+        .route(
+            "/graphql",
+            get(graphql::handle_graphiql).post(graphql::handle_graphql),
+        ) // GET, POST /graphql
+        .route("/openapi.json", get(openapi::handle_openapi_json)) // GET /openapi.json
+        .route("/docs", get(openapi::handle_docs)) // GET /docs
+        .route("/audit", get(audit::handle_audit_list)) // GET /audit
+        .route("/audit/:id/undo", post(audit::handle_audit_undo)) // POST /audit/ID/undo
+        .route("/admin/info", get(health::handle_admin_info)) // GET /admin/info
+        .route("/admin/scan", post(handle_admin_scan)) // POST /admin/scan
+        .route("/admin/jobs", get(scheduler::handle_jobs_list)) // GET /admin/jobs
+        .route("/admin/jobs/:name/run", post(scheduler::handle_jobs_run)) // POST /admin/jobs/NAME/run
+        .route(
+            "/admin/webhooks",
+            get(webhooks::handle_webhooks_list).post(webhooks::handle_webhooks_create),
+        ) // GET, POST /admin/webhooks
+        .route(
+            "/admin/webhooks/:id",
+            delete(webhooks::handle_webhooks_delete),
+        ) // DELETE /admin/webhooks/ID
+        .route(
+            "/admin/webhooks/dead-letters",
+            get(webhooks::handle_dead_letters_list),
+        ) // GET /admin/webhooks/dead-letters
+        .route(
+            "/admin/webhooks/dead-letters/:id/retry",
+            post(webhooks::handle_dead_letters_retry),
+        ) // POST /admin/webhooks/dead-letters/ID/retry
+        .route(
+            "/admin/reload",
+            get(reload::handle_reload_status).post(reload::handle_reload),
+        ) // GET, POST /admin/reload
+        .route(
+            "/admin/snapshots",
+            get(snapshot::handle_snapshot_list).post(snapshot::handle_snapshot_create),
+        ) // GET, POST /admin/snapshots
+        .route(
+            "/admin/snapshots/:name/restore",
+            post(snapshot::handle_snapshot_restore),
+        ); // POST /admin/snapshots/NAME/restore
+
+    // CORS, compression and security headers, inside the request log
+    layers::apply(app, &state.config)
+        .layer(middleware::from_fn(logging::trace_requests))
+        .with_state(state)
+}
@@ -0,0 +1,508 @@
+use crate::audit::{self, Actor};
+use crate::config::Config;
+use crate::duplicates::{DuplicateCluster, DuplicatesQuery, find_duplicates};
+use crate::health::Persistence;
+use crate::library::{Library, LibraryFiles, SEQUENCE_FILE};
+use crate::negotiate::{Format, Negotiated};
+use crate::stream;
+use crate::{
+    AppState, ErrorMessage, NewSongRequest, SONGS_FILE, Song, SongSearchQuery, add_song,
+    insert_song, load_library, play_song, record_play, save_songs, search_songs, webhooks,
+};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+// Named libraries, e.g. one per household, each with its own songs, id
+// sequence and files under `libraries/NAME/`. The default library keeps
+// songs.json at the top of the data directory and is also reachable as
+// `/libraries/default`.
+//
+// Scoped under /libraries/LIB/songs: new, search, play, uid, stream and
+// duplicates. Changes are recorded in the audit log and sent to webhooks
+// with the library's name. Everything else (ratings, favourites, reviews,
+// merging, trending, playlists, queues, the catalogue, scans and
+// hot-reloading) only covers the default library. Unknown libraries and
+// songs are always 404 here; only the legacy GET /songs/play/ID answers an
+// unknown id with 200.
+pub const LIBRARIES_DIR: &str = "libraries";
+pub const DEFAULT_LIBRARY: &str = "default";
+
+// Structure for receiving a new library request from POST JSON
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewLibraryRequest {
+    name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LibraryInfo {
+    name: String,
+    song_count: usize,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Library names become directory names, so only lowercase letters, digits,
+// `-` and `_` are allowed
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+}
+
+fn files(name: &str) -> LibraryFiles {
+    let dir = format!("{}/{}", LIBRARIES_DIR, name);
+    LibraryFiles {
+        songs: format!("{}/{}", dir, SONGS_FILE),
+        sequence: format!("{}/{}", dir, SEQUENCE_FILE),
+        watched: false,
+    }
+}
+
+// Load every named library found under the libraries directory
+pub fn load_all(
+    persistence: &Persistence,
+    config: &Config,
+) -> BTreeMap<String, Arc<RwLock<Library>>> {
+    let mut libraries = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(persistence.path(LIBRARIES_DIR)) else {
+        return libraries;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.path().is_dir() {
+            continue;
+        }
+        if !valid_name(&name) || name == DEFAULT_LIBRARY {
+            warn!(name, "ignoring library directory with an invalid name");
+            continue;
+        }
+        let library = load_library(persistence, config, files(&name));
+        libraries.insert(name, Arc::new(RwLock::new(library)));
+    }
+
+    info!(count = libraries.len(), "loaded named libraries");
+    libraries
+}
+
+// Create the directory of a new, empty library. The empty song list is
+// written right away so the library is found on restart.
+pub fn create(persistence: &Persistence, config: &Config, name: &str) -> io::Result<Library> {
+    fs::create_dir_all(persistence.path(LIBRARIES_DIR).join(name))?;
+    let mut library = load_library(persistence, config, files(name));
+    save_songs(persistence, &mut library);
+    Ok(library)
+}
+
+// Look up a library by name
+pub fn library(state: &AppState, name: &str) -> Result<Arc<RwLock<Library>>, ApiError> {
+    if name == DEFAULT_LIBRARY {
+        return Ok(state.songs.clone());
+    }
+    state
+        .libraries
+        .read()
+        .get(name)
+        .cloned()
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Library not found"))
+}
+
+// List the libraries, starting with the default one
+#[utoipa::path(
+    get,
+    path = "/libraries",
+    tag = "libraries",
+    responses((status = 200, description = "Every library", body = [LibraryInfo]))
+)]
+pub async fn handle_libraries_list(State(state): State<Arc<AppState>>) -> Json<Vec<LibraryInfo>> {
+    let mut list = vec![LibraryInfo {
+        name: DEFAULT_LIBRARY.to_string(),
+        song_count: state.songs.read().len(),
+    }];
+    for (name, library) in state.libraries.read().iter() {
+        list.push(LibraryInfo {
+            name: name.clone(),
+            song_count: library.read().len(),
+        });
+    }
+    Json(list)
+}
+
+// Create an empty named library
+#[utoipa::path(
+    post,
+    path = "/libraries",
+    tag = "libraries",
+    request_body = NewLibraryRequest,
+    responses(
+        (status = 201, description = "The library was created", body = LibraryInfo),
+        (status = 400, description = "Invalid library name", body = ErrorMessage),
+        (status = 409, description = "A library with this name exists", body = ErrorMessage),
+        (status = 500, description = "The library files could not be created", body = ErrorMessage),
+    )
+)]
+pub async fn handle_libraries_create(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<NewLibraryRequest>,
+) -> Result<(StatusCode, Json<LibraryInfo>), ApiError> {
+    if !valid_name(&payload.name) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "Library names may only contain a-z, 0-9, - and _",
+        ));
+    }
+
+    let mut libraries = state.libraries.write();
+    if payload.name == DEFAULT_LIBRARY || libraries.contains_key(&payload.name) {
+        return Err(error(StatusCode::CONFLICT, "Library already exists"));
+    }
+
+    let library = create(&state.persistence, &state.config, &payload.name).map_err(|e| {
+        warn!(name = payload.name, error = %e, "failed to create library directory");
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not create the library",
+        )
+    })?;
+    libraries.insert(payload.name.clone(), Arc::new(RwLock::new(library)));
+
+    info!(name = payload.name, "library created");
+    Ok((
+        StatusCode::CREATED,
+        Json(LibraryInfo {
+            name: payload.name,
+            song_count: 0,
+        }),
+    ))
+}
+
+// Add a new song to a library
+#[utoipa::path(
+    post,
+    path = "/libraries/{lib}/songs/new",
+    tag = "libraries",
+    params(("lib" = String, Path, description = "Library name")),
+    request_body = NewSongRequest,
+    responses(
+        (status = 200, description = "The song that was added", body = Song),
+        (status = 404, description = "No library with this name", body = ErrorMessage),
+    )
+)]
+pub async fn handle_library_songs_new(
+    State(state): State<Arc<AppState>>,
+    Path(lib): Path<String>,
+    Actor(user): Actor,
+    Json(payload): Json<NewSongRequest>,
+) -> Result<Json<Song>, ApiError> {
+    if lib == DEFAULT_LIBRARY {
+        // Same as POST /songs/new, including the audit log and webhooks
+        return Ok(Json(add_song(&state, &user, payload)));
+    }
+    let library = library(&state, &lib)?;
+    let mut songs = library.write();
+    let song = insert_song(&state.persistence, &mut songs, payload);
+    audit::record_in(
+        &state,
+        Some(&lib),
+        &user,
+        "create",
+        audit::change(None, Some(&song)),
+    );
+    Ok(Json(song))
+}
+
+// Count a play of a song in a library and notify webhooks. `Ok(None)` when
+// the library has no such song.
+pub fn record_play_in(state: &AppState, lib: &str, id: u64) -> Result<Option<Song>, ApiError> {
+    if lib == DEFAULT_LIBRARY {
+        return Ok(record_play(state, id));
+    }
+    let Some(song) = play_song(&library(state, lib)?.read(), id) else {
+        return Ok(None);
+    };
+    state
+        .webhooks
+        .emit_in(Some(lib), webhooks::Event::Played, &song);
+    Ok(Some(song))
+}
+
+// Search for songs in a library
+#[utoipa::path(
+    get,
+    path = "/libraries/{lib}/songs/search",
+    tag = "libraries",
+    params(("lib" = String, Path, description = "Library name"), SongSearchQuery),
+    responses(
+        (status = 200, description = "Songs matching every given filter", body = [Song], content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 400, description = "The query expression could not be parsed", body = crate::query::QueryError),
+        (status = 404, description = "No library with this name", body = ErrorMessage),
+    )
+)]
+pub async fn handle_library_songs_search(
+    State(state): State<Arc<AppState>>,
+    Path(lib): Path<String>,
+    Query(query): Query<SongSearchQuery>,
+    format: Format,
+) -> Response {
+    let library = match library(&state, &lib) {
+        Ok(library) => library,
+        Err(e) => return e.into_response(),
+    };
+    let songs = library.read();
+
+    match search_songs(&songs, &query) {
+        Ok(results) => Negotiated(format, results).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(e)).into_response(),
+    }
+}
+
+// Play a song in a library by ID
+#[utoipa::path(
+    get,
+    path = "/libraries/{lib}/songs/play/{id}",
+    tag = "libraries",
+    params(
+        ("lib" = String, Path, description = "Library name"),
+        ("id" = u64, Path, description = "Song id"),
+    ),
+    responses(
+        (status = 200, description = "The song with its play count incremented", body = Song),
+        (status = 404, description = "No such library or song", body = ErrorMessage),
+    )
+)]
+pub async fn handle_library_songs_play(
+    State(state): State<Arc<AppState>>,
+    Path((lib, id)): Path<(String, u64)>,
+) -> Result<Json<Song>, ApiError> {
+    record_play_in(&state, &lib, id)?
+        .map(Json)
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Song not found"))
+}
+
+// Stream a song's audio file from a library, like GET /songs/ID/stream
+#[utoipa::path(
+    get,
+    path = "/libraries/{lib}/songs/{id}/stream",
+    tag = "libraries",
+    params(
+        ("lib" = String, Path, description = "Library name"),
+        ("id" = u64, Path, description = "Song id"),
+        ("Range" = Option<String>, Header, description = "Single byte range, e.g. `bytes=0-1023`"),
+    ),
+    responses(
+        (status = 200, description = "The whole audio file"),
+        (status = 206, description = "The requested byte range"),
+        (status = 404, description = "No such library or song, or the song has no audio file", body = ErrorMessage),
+        (status = 416, description = "Range outside the file"),
+    )
+)]
+pub async fn handle_library_songs_stream(
+    State(state): State<Arc<AppState>>,
+    Path((lib, id)): Path<(String, u64)>,
+    headers: HeaderMap,
+) -> Response {
+    stream::stream(state, lib, id, headers).await
+}
+
+// Find groups of likely duplicate songs in a library
+#[utoipa::path(
+    get,
+    path = "/libraries/{lib}/songs/duplicates",
+    tag = "libraries",
+    params(("lib" = String, Path, description = "Library name"), DuplicatesQuery),
+    responses(
+        (status = 200, description = "Clusters of likely duplicates", body = [DuplicateCluster]),
+        (status = 404, description = "No library with this name", body = ErrorMessage),
+    )
+)]
+pub async fn handle_library_songs_duplicates(
+    State(state): State<Arc<AppState>>,
+    Path(lib): Path<String>,
+    Query(query): Query<DuplicatesQuery>,
+) -> Result<Json<Vec<DuplicateCluster>>, ApiError> {
+    let songs = library(&state, &lib)?.read().to_vec();
+    Ok(Json(find_duplicates(songs, &query).await))
+}
+
+// Look up a song in a library by its opaque public id
+#[utoipa::path(
+    get,
+    path = "/libraries/{lib}/songs/uid/{uid}",
+    tag = "libraries",
+    params(
+        ("lib" = String, Path, description = "Library name"),
+        ("uid" = String, Path, description = "Public song id (ULID)"),
+    ),
+    responses(
+        (status = 200, description = "The song with this public id", body = Song),
+        (status = 404, description = "No such library or song", body = ErrorMessage),
+    )
+)]
+pub async fn handle_library_songs_by_uid(
+    State(state): State<Arc<AppState>>,
+    Path((lib, uid)): Path<(String, String)>,
+) -> Result<Json<Song>, ApiError> {
+    let library = library(&state, &lib)?;
+    let songs = library.read();
+    songs
+        .get_by_uid(&uid)
+        .map(|song| Json(song.song()))
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Song not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditQuery, handle_audit_list, handle_audit_undo};
+    use crate::webhooks::{self, handle_webhooks_create};
+    use serde_json::{Value, json};
+
+    fn new_song(title: &str) -> NewSongRequest {
+        serde_json::from_value(json!({"title": title, "artist": "Nest", "genre": "Folk"})).unwrap()
+    }
+
+    async fn setup(dir: &std::path::Path) -> (Arc<AppState>, webhooks::Receiver) {
+        let (state, receiver) = crate::load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        });
+        let request = serde_json::from_value(json!({"name": "home"})).unwrap();
+        let _ = handle_libraries_create(State(state.clone()), Json(request))
+            .await
+            .unwrap();
+        let hook = serde_json::from_value(json!({"url": "http://127.0.0.1:9/hook"})).unwrap();
+        let _ = handle_webhooks_create(State(state.clone()), Json(hook))
+            .await
+            .unwrap();
+        (state, receiver)
+    }
+
+    async fn audit(state: &Arc<AppState>, library: &str) -> Vec<Value> {
+        let query: AuditQuery = serde_json::from_value(json!({"library": library})).unwrap();
+        let entries = handle_audit_list(State(state.clone()), Query(query), Format::Json).await;
+        entries
+            .1
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn named_library_writes_are_audited_and_sent_to_webhooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = setup(dir.path()).await;
+
+        let Json(song) = handle_library_songs_new(
+            State(state.clone()),
+            Path("home".to_string()),
+            Actor("alice".to_string()),
+            Json(new_song("Hearth")),
+        )
+        .await
+        .unwrap();
+        assert!(state.songs.read().is_empty());
+
+        let delivery = serde_json::to_value(receiver.try_recv().unwrap()).unwrap();
+        assert_eq!(delivery["payload"]["event"], "song.added");
+        assert_eq!(delivery["payload"]["library"], "home");
+
+        let entries = audit(&state, "home").await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["library"], "home");
+        assert!(audit(&state, DEFAULT_LIBRARY).await.is_empty());
+
+        let Json(played) =
+            handle_library_songs_play(State(state.clone()), Path(("home".to_string(), song.id)))
+                .await
+                .unwrap();
+        assert_eq!(played.play_count, 1);
+        let delivery = serde_json::to_value(receiver.try_recv().unwrap()).unwrap();
+        assert_eq!(delivery["payload"]["event"], "song.played");
+        assert_eq!(delivery["payload"]["library"], "home");
+
+        // Undo removes the song from the named library, not the default one
+        let id = entries[0]["id"].as_u64().unwrap();
+        let _ = handle_audit_undo(State(state.clone()), Path(id), Actor("bob".to_string()))
+            .await
+            .unwrap();
+        assert!(library(&state, "home").unwrap().read().is_empty());
+        let delivery = serde_json::to_value(receiver.try_recv().unwrap()).unwrap();
+        assert_eq!(delivery["payload"]["event"], "song.deleted");
+        assert_eq!(delivery["payload"]["library"], "home");
+    }
+
+    #[tokio::test]
+    async fn unknown_songs_and_libraries_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = setup(dir.path()).await;
+
+        for lib in [DEFAULT_LIBRARY, "home", "nowhere"] {
+            let (status, _) =
+                handle_library_songs_play(State(state.clone()), Path((lib.to_string(), 42)))
+                    .await
+                    .unwrap_err();
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", lib);
+
+            let response = handle_library_songs_stream(
+                State(state.clone()),
+                Path((lib.to_string(), 42)),
+                HeaderMap::new(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", lib);
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_found_per_library() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = setup(dir.path()).await;
+        for title in ["Hearth", "Hearth (Live)"] {
+            let _ = handle_library_songs_new(
+                State(state.clone()),
+                Path("home".to_string()),
+                Actor("alice".to_string()),
+                Json(new_song(title)),
+            )
+            .await
+            .unwrap();
+        }
+        add_song(&state, "alice", new_song("Hearth"));
+
+        let query = || serde_json::from_value(json!({})).unwrap();
+        let Json(home) = handle_library_songs_duplicates(
+            State(state.clone()),
+            Path("home".to_string()),
+            Query(query()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(home.len(), 1);
+        let Json(default) = handle_library_songs_duplicates(
+            State(state.clone()),
+            Path(DEFAULT_LIBRARY.to_string()),
+            Query(query()),
+        )
+        .await
+        .unwrap();
+        assert!(default.is_empty());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::plays::Unsaved;
+use crate::{AudioFile, Song};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use ulid::Ulid;
+
+pub const SEQUENCE_FILE: &str = "sequence.json";
+
+// Where a library's songs and id sequence are persisted
+#[derive(Debug, Clone)]
+pub struct LibraryFiles {
+    pub songs: String,
+    pub sequence: String,
+    // Whether the songs file is watched for external edits (see `reload.rs`).
+    // Saves to a watched file wait for edits to be merged first; other files
+    // are simply overwritten.
+    pub watched: bool,
+}
+
+impl Default for LibraryFiles {
+    // The default library: songs.json and sequence.json in the data directory
+    fn default() -> Self {
+        LibraryFiles {
+            songs: crate::SONGS_FILE.to_string(),
+            sequence: SEQUENCE_FILE.to_string(),
+            watched: true,
+        }
+    }
+}
+
+// Persisted id sequence, kept next to songs.json, along with the ids of
+// merged songs and the song each one now points to
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Sequence {
+    pub next_id: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redirects: BTreeMap<u64, u64>,
+}
+
+// A song as written to the data files. `Song` itself leaves out the file it
+// was scanned from, so that API responses never contain server paths.
+#[derive(Serialize)]
+struct StoredSong<'a> {
+    #[serde(flatten)]
+    song: &'a Song,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: &'a Option<AudioFile>,
+}
+
+impl<'a> From<&'a Song> for StoredSong<'a> {
+    fn from(song: &'a Song) -> Self {
+        StoredSong {
+            song,
+            file: &song.file,
+        }
+    }
+}
+
+// Songs as written to a data file, with their files
+pub struct StoredSongs<'a>(pub &'a [Song]);
+
+impl Serialize for StoredSongs<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_stored(self.0, serializer)
+    }
+}
+
+// For `#[serde(serialize_with = "...")]` on songs in persisted structs
+pub fn serialize_stored<S: Serializer>(songs: &[Song], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(songs.iter().map(StoredSong::from))
+}
+
+pub fn serialize_stored_option<S: Serializer>(
+    song: &Option<Song>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    song.as_ref().map(StoredSong::from).serialize(serializer)
+}
+
+// Size and modification time of a data file, used to notice edits made
+// outside the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStamp {
+    len: u64,
+    modified: SystemTime,
+}
+
+impl FileStamp {
+    // Stamp of the file at `path`, or `None` if it does not exist
+    pub fn of(path: impl AsRef<Path>) -> Option<FileStamp> {
+        let meta = fs::metadata(path).ok()?;
+        Some(FileStamp {
+            len: meta.len(),
+            modified: meta.modified().ok()?,
+        })
+    }
+}
+
+// A song as kept in a library. Its play count is held apart from the song,
+// in an atomic, so plays only need a shared lock on the library and
+// searches keep running during play bursts. Derefs to the song for its
+// other fields; the song's own `play_count` is always 0, so read the count
+// with `plays` and take a complete copy with `song`.
+#[derive(Debug)]
+pub(crate) struct LibrarySong {
+    song: Song,
+    plays: AtomicU64,
+}
+
+impl LibrarySong {
+    pub fn new(mut song: Song) -> LibrarySong {
+        let plays = AtomicU64::new(std::mem::take(&mut song.play_count));
+        LibrarySong { song, plays }
+    }
+
+    pub fn plays(&self) -> u64 {
+        self.plays.load(Ordering::Relaxed)
+    }
+
+    pub fn add_plays(&self, plays: u64) {
+        self.plays.fetch_add(plays, Ordering::Relaxed);
+    }
+
+    pub fn set_plays(&self, plays: u64) {
+        self.plays.store(plays, Ordering::Relaxed);
+    }
+
+    // The song with its current play count
+    pub fn song(&self) -> Song {
+        Song {
+            play_count: self.plays(),
+            ..self.song.clone()
+        }
+    }
+
+    fn into_song(self) -> Song {
+        Song {
+            play_count: self.plays.into_inner(),
+            ..self.song
+        }
+    }
+}
+
+impl Deref for LibrarySong {
+    type Target = Song;
+
+    fn deref(&self) -> &Song {
+        &self.song
+    }
+}
+
+impl DerefMut for LibrarySong {
+    fn deref_mut(&mut self) -> &mut Song {
+        &mut self.song
+    }
+}
+
+// The song list together with an id -> index map and a monotonic id
+// sequence. Ids are never reused, even after songs are removed or the file
+// is reordered. Derefs to the songs so read-only code can iterate directly.
+#[derive(Debug, Default)]
+pub(crate) struct Library {
+    songs: Vec<LibrarySong>,
+    index: HashMap<u64, usize>,
+    uids: HashMap<String, u64>,
+    redirects: BTreeMap<u64, u64>,
+    next_id: u64,
+    public_ids: bool,
+    files: LibraryFiles,
+    // Songs as last read from or written to the songs file, and its stamp
+    synced: Vec<Song>,
+    stamp: Option<FileStamp>,
+    // Set by `play`, cleared when the songs are saved
+    unsaved: Unsaved,
+}
+
+impl Library {
+    // Build a library from loaded songs. The sequence never goes below the
+    // highest id in use, so an outdated or missing sequence file is harmless.
+    pub fn new(
+        songs: Vec<Song>,
+        sequence: Sequence,
+        public_ids: bool,
+        files: LibraryFiles,
+    ) -> Library {
+        let mut library = Library {
+            songs: songs.into_iter().map(LibrarySong::new).collect(),
+            next_id: sequence.next_id,
+            redirects: sequence.redirects,
+            public_ids,
+            files,
+            ..Default::default()
+        };
+        library.reindex();
+        library
+    }
+
+    fn reindex(&mut self) {
+        if self.public_ids {
+            for song in self.songs.iter_mut().filter(|s| s.uid.is_none()) {
+                song.uid = Some(Ulid::new().to_string());
+            }
+        }
+
+        self.index = self
+            .songs
+            .iter()
+            .enumerate()
+            .map(|(idx, song)| (song.id, idx))
+            .collect();
+        self.uids = self
+            .songs
+            .iter()
+            .filter_map(|song| Some((song.uid.clone()?, song.id)))
+            .collect();
+
+        let max_id = self.songs.iter().map(|s| s.id).max().unwrap_or(0);
+        self.next_id = self.next_id.max(max_id + 1).max(1);
+    }
+
+    // Reserve the next song id
+    pub fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn files(&self) -> &LibraryFiles {
+        &self.files
+    }
+
+    pub fn sequence(&self) -> Sequence {
+        Sequence {
+            next_id: self.next_id,
+            redirects: self.redirects.clone(),
+        }
+    }
+
+    // Add a song whose id came from `allocate_id`
+    pub fn push(&mut self, mut song: Song) -> &LibrarySong {
+        if self.public_ids && song.uid.is_none() {
+            song.uid = Some(Ulid::new().to_string());
+        }
+        if let Some(uid) = &song.uid {
+            self.uids.insert(uid.clone(), song.id);
+        }
+
+        // A song that is present again (e.g. an undone merge) is no longer redirected
+        self.redirects.remove(&song.id);
+        self.next_id = self.next_id.max(song.id + 1);
+        self.index.insert(song.id, self.songs.len());
+        self.songs.push(LibrarySong::new(song));
+        self.songs.last().unwrap()
+    }
+
+    // Replace every song (e.g. when restoring a snapshot), keeping the
+    // sequence monotonic
+    pub fn replace(&mut self, songs: Vec<Song>, sequence: Sequence) {
+        self.songs = songs.into_iter().map(LibrarySong::new).collect();
+        self.redirects = sequence.redirects;
+        self.next_id = self.next_id.max(sequence.next_id);
+        self.reindex();
+    }
+
+    // Record that the songs now match the songs file as of `stamp`
+    pub fn mark_synced(&mut self, stamp: Option<FileStamp>) {
+        self.synced = self.to_vec();
+        self.stamp = stamp;
+        self.unsaved.clear();
+    }
+
+    // Count a play of a song. Only needs shared access; the play is saved
+    // with the next save of the library (see `plays::checkpoint`).
+    pub fn play(&self, id: u64) -> Option<&LibrarySong> {
+        let song = self.get(id)?;
+        song.add_plays(1);
+        self.unsaved.mark();
+        Some(song)
+    }
+
+    // Whether plays were counted since the songs were last saved
+    pub fn unsaved_plays(&self) -> bool {
+        self.unsaved.is_set()
+    }
+
+    // Songs as of the last load or save
+    pub fn synced(&self) -> &[Song] {
+        &self.synced
+    }
+
+    pub fn stamp(&self) -> Option<FileStamp> {
+        self.stamp
+    }
+
+    // Remove a song by id, returning it
+    pub fn remove(&mut self, id: u64) -> Option<Song> {
+        let idx = self.index.get(&id).copied()?;
+        let song = self.songs.remove(idx);
+        self.reindex();
+        Some(song.into_song())
+    }
+
+    // Make lookups of a removed (merged) id resolve to another song
+    pub fn redirect(&mut self, from: u64, to: u64) {
+        if from != to {
+            self.redirects.insert(from, to);
+        }
+    }
+
+    // Follow merge redirects to the id of the song that is still present
+    pub fn resolve(&self, mut id: u64) -> u64 {
+        // Bounded in case a hand-edited file contains a cycle
+        for _ in 0..self.redirects.len() {
+            match self.redirects.get(&id) {
+                Some(&to) if !self.index.contains_key(&id) => id = to,
+                _ => break,
+            }
+        }
+        id
+    }
+
+    // Whether a song with exactly this id exists (redirects are not followed)
+    pub fn contains(&self, id: u64) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn position(&self, id: u64) -> Option<usize> {
+        self.index.get(&self.resolve(id)).copied()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&LibrarySong> {
+        self.position(id).map(|idx| &self.songs[idx])
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut LibrarySong> {
+        self.position(id).map(|idx| &mut self.songs[idx])
+    }
+
+    // Look up a song by its opaque public id
+    pub fn get_by_uid(&self, uid: &str) -> Option<&LibrarySong> {
+        self.uids.get(uid).and_then(|&id| self.get(id))
+    }
+
+    // Every song with its current play count
+    pub fn to_vec(&self) -> Vec<Song> {
+        self.songs.iter().map(LibrarySong::song).collect()
+    }
+}
+
+impl Deref for Library {
+    type Target = [LibrarySong];
+
+    fn deref(&self) -> &[LibrarySong] {
+        &self.songs
+    }
+}
+
+// Mutable access is to the slice only, so songs cannot be added or removed
+// behind the index's back
+impl DerefMut for Library {
+    fn deref_mut(&mut self) -> &mut [LibrarySong] {
+        &mut self.songs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn files_are_stored_but_not_serialized_for_clients() {
+        let song = Song {
+            id: 1,
+            uid: None,
+            title: "Scanned".to_string(),
+            artist: "Band".to_string(),
+            album: None,
+            genre: "Rock".to_string(),
+            play_count: 2,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: Some(AudioFile {
+                path: "/srv/music/scanned.mp3".to_string(),
+                size: 1024,
+                modified: 1,
+            }),
+        };
+
+        let shown = serde_json::to_value(&song).unwrap();
+        assert!(shown.get("file").is_none());
+
+        let songs = [song];
+        let stored = serde_json::to_value(StoredSongs(&songs)).unwrap();
+        assert_eq!(stored[0]["play_count"], 2);
+        assert_eq!(
+            stored[0]["file"],
+            json!({"path": "/srv/music/scanned.mp3", "size": 1024, "modified": 1})
+        );
+        let loaded: Vec<Song> = serde_json::from_value(stored).unwrap();
+        assert_eq!(loaded, songs);
+    }
+
+    #[test]
+    fn plays_are_counted_outside_the_song() {
+        let song: Song = serde_json::from_value(json!({
+            "id": 1,
+            "title": "Played",
+            "artist": "Band",
+            "genre": "Rock",
+            "play_count": 2,
+        }))
+        .unwrap();
+        let library = Library::new(
+            vec![song],
+            Sequence::default(),
+            false,
+            LibraryFiles::default(),
+        );
+
+        assert_eq!(library.play(1).unwrap().plays(), 3);
+        assert_eq!(library.get(1).unwrap().song().play_count, 3);
+        assert_eq!(library.to_vec()[0].play_count, 3);
+        // The stored song's own count is never read
+        assert_eq!(library[0].play_count, 0);
+        assert!(library.unsaved_plays());
+    }
+}
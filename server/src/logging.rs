@@ -0,0 +1,128 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::time::Instant;
+use tracing::{Instrument, info, info_span};
+use tracing_subscriber::EnvFilter;
+
+static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Install the global JSON log subscriber, writing to stderr. `level` is an
+// env-filter directive such as `info` or `server=debug,tower=warn`; invalid
+// directives fall back to `info`.
+pub fn init(level: &str) {
+    let filter = EnvFilter::try_new(level).unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(filter)
+        .with_current_span(true)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+// Run every request inside a span carrying a request id (taken from the
+// incoming `X-Request-Id` header or freshly generated), echo that id back in
+// the response, and emit one access log line once the response is ready.
+pub async fn trace_requests(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let span = info_span!("request", request_id = %request_id, method = %method, path = %path);
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    let start = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    span.in_scope(|| {
+        info!(
+            target: "access",
+            method = %method,
+            path = %path,
+            status = response.status().as_u16(),
+            latency_ms,
+            "request completed"
+        );
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::HeaderMap;
+    use axum::{Router, middleware, routing::get};
+    use tower::ServiceExt;
+
+    // The id the handler saw, and the id echoed in the response
+    async fn ids(id: Option<&str>) -> (String, String) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    headers[&X_REQUEST_ID].to_str().unwrap().to_string()
+                }),
+            )
+            .layer(middleware::from_fn(trace_requests));
+
+        let mut request = Request::get("/");
+        if let Some(id) = id {
+            request = request.header(&X_REQUEST_ID, id);
+        }
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let echoed = response.headers()[&X_REQUEST_ID]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), echoed)
+    }
+
+    #[tokio::test]
+    async fn incoming_request_ids_are_kept_and_echoed() {
+        assert_eq!(
+            ids(Some("abc-123")).await,
+            ("abc-123".into(), "abc-123".into())
+        );
+
+        let longest = "x".repeat(128);
+        assert_eq!(ids(Some(&longest)).await.1, longest);
+    }
+
+    #[tokio::test]
+    async fn missing_or_oversized_ids_are_replaced() {
+        for id in [None, Some(""), Some(&*"x".repeat(129))] {
+            let (seen, echoed) = ids(id).await;
+            assert_eq!(seen, echoed);
+            assert!(uuid::Uuid::parse_str(&echoed).is_ok(), "{}", echoed);
+        }
+
+        // Each request gets its own id
+        assert_ne!(ids(None).await.1, ids(None).await.1);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::ErrorMessage;
+use axum::{
+    Json, async_trait,
+    extract::FromRequestParts,
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::error;
+
+// Response format picked from the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    MessagePack,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Format> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "text/csv" | "text/*" => Some(Format::Csv),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    // The supported media type with the highest quality in an `Accept`
+    // header (earlier entries win ties). JSON when the header is absent or
+    // empty; `None` when it lists no supported type.
+    fn from_accept(accept: &str) -> Option<Format> {
+        if accept.trim().is_empty() {
+            return Some(Format::Json);
+        }
+        let mut best: Option<(Format, f32)> = None;
+
+        for entry in accept.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or("").to_ascii_lowercase();
+            let quality = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if let Some(format) = Format::from_media_type(&media_type)
+                && quality > 0.0
+                && best.is_none_or(|(_, q)| quality > q)
+            {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map(|(format, _)| format)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        Format::from_accept(accept).ok_or_else(|| {
+            let message = ErrorMessage {
+                error: "Supported formats are application/json, text/csv and application/msgpack",
+            };
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                [(header::VARY, "Accept")],
+                Json(message),
+            )
+                .into_response()
+        })
+    }
+}
+
+// A response body serialized as JSON, CSV or MessagePack, depending on the
+// request's `Accept` header. Responses carry `Vary: Accept` so caches keep
+// the formats apart.
+pub struct Negotiated<T>(pub Format, pub T);
+
+impl<T: Serialize> IntoResponse for Negotiated<T> {
+    fn into_response(self) -> Response {
+        let mut response = self.encode();
+        response
+            .headers_mut()
+            .insert(header::VARY, header::HeaderValue::from_static("Accept"));
+        response
+    }
+}
+
+impl<T: Serialize> Negotiated<T> {
+    fn encode(self) -> Response {
+        let Negotiated(format, body) = self;
+
+        let encoded = match format {
+            Format::Json => return Json(body).into_response(),
+            Format::Csv => to_csv(&body).map(|bytes| ("text/csv; charset=utf-8", bytes)),
+            Format::MessagePack => rmp_serde::to_vec_named(&body)
+                .map(|bytes| ("application/msgpack", bytes))
+                .map_err(|e| e.to_string()),
+        };
+
+        match encoded {
+            Ok((content_type, bytes)) => {
+                ([(header::CONTENT_TYPE, content_type)], bytes).into_response()
+            }
+            Err(e) => {
+                error!(?format, error = %e, "failed to encode response");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// Write a value as CSV with a header row. A list becomes one row per item,
+// anything else a single row. Nested objects are flattened into dotted
+// columns (`file.path`), and nested lists are written as JSON text.
+fn to_csv<T: Serialize>(body: &T) -> Result<Vec<u8>, String> {
+    let value = serde_json::to_value(body).map_err(|e| e.to_string())?;
+    let items = match value {
+        Value::Array(items) => items,
+        other => vec![other],
+    };
+
+    let rows: Vec<Vec<(String, String)>> = items
+        .iter()
+        .map(|item| {
+            let mut row = Vec::new();
+            flatten("", item, &mut row);
+            row
+        })
+        .collect();
+
+    // Columns in order of first appearance, since optional fields may be
+    // missing from some rows
+    let mut columns: Vec<&str> = Vec::new();
+    for (column, _) in rows.iter().flatten() {
+        if !columns.contains(&column.as_str()) {
+            columns.push(column);
+        }
+    }
+
+    // No rows and no columns: an empty body rather than a blank header
+    if columns.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&columns).map_err(|e| e.to_string())?;
+    for row in &rows {
+        let record = columns.iter().map(|column| {
+            row.iter()
+                .find(|(c, _)| c == column)
+                .map_or("", |(_, v)| v.as_str())
+        });
+        writer.write_record(record).map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+fn flatten(prefix: &str, value: &Value, row: &mut Vec<(String, String)>) {
+    let cell = match value {
+        Value::Object(fields) => return flatten_object(prefix, fields, row),
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Bool(_) | Value::Number(_) | Value::Array(_) => value.to_string(),
+    };
+    let column = if prefix.is_empty() { "value" } else { prefix };
+    row.push((column.to_string(), cell));
+}
+
+fn flatten_object(prefix: &str, fields: &Map<String, Value>, row: &mut Vec<(String, String)>) {
+    for (key, value) in fields {
+        let column = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        flatten(&column, value, row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use axum::http::HeaderMap;
+    use serde_json::json;
+
+    async fn respond(format: Format, body: Value) -> (HeaderMap, String) {
+        let (parts, body) = Negotiated(format, body).into_response().into_parts();
+        let bytes = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.headers, String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    #[test]
+    fn accept_header_picks_the_best_supported_format() {
+        assert_eq!(Format::from_accept(""), Some(Format::Json));
+        assert_eq!(Format::from_accept("text/csv"), Some(Format::Csv));
+        assert_eq!(
+            Format::from_accept("Application/MsgPack"),
+            Some(Format::MessagePack)
+        );
+        // Browsers: HTML is not supported, the wildcard picks JSON
+        assert_eq!(
+            Format::from_accept("text/html,application/xhtml+xml,*/*;q=0.8"),
+            Some(Format::Json)
+        );
+    }
+
+    #[test]
+    fn quality_values_rank_formats() {
+        assert_eq!(
+            Format::from_accept("application/json;q=0.5, text/csv"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv;q=0.9, application/msgpack;q=0.9"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv; q=0, application/json; q=0.1"),
+            Some(Format::Json)
+        );
+        // An unparsable quality counts as 1
+        assert_eq!(
+            Format::from_accept("application/json;q=0.5, text/csv;q=high"),
+            Some(Format::Csv)
+        );
+    }
+
+    #[tokio::test]
+    async fn unsupported_accept_is_not_acceptable() {
+        assert_eq!(Format::from_accept("text/html"), None);
+        assert_eq!(Format::from_accept("text/csv;q=0"), None);
+
+        let (mut parts, ()) = axum::http::Request::builder()
+            .header(header::ACCEPT, "image/png")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let rejection = Format::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(rejection.headers()[header::VARY], "Accept");
+    }
+
+    #[tokio::test]
+    async fn responses_vary_on_accept() {
+        for format in [Format::Json, Format::Csv, Format::MessagePack] {
+            let (headers, _) = respond(format, json!([])).await;
+            assert_eq!(headers[header::VARY], "Accept", "{:?}", format);
+        }
+        let (headers, _) = respond(Format::MessagePack, json!({"a": 1})).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "application/msgpack");
+    }
+
+    #[tokio::test]
+    async fn csv_flattens_nested_fields_and_fills_missing_columns() {
+        let body = json!([
+            {"id": 1, "title": "One, Two", "file": {"path": "/a.mp3"}, "tags": ["x"]},
+            {"id": 2, "title": "Three", "rating": null, "extra": true},
+        ]);
+        let (headers, csv) = respond(Format::Csv, body).await;
+        assert_eq!(headers[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+        assert_eq!(
+            csv,
+            "id,title,file.path,tags,rating,extra\n\
+             1,\"One, Two\",/a.mp3,\"[\"\"x\"\"]\",,\n\
+             2,Three,,,,true\n"
+        );
+
+        assert_eq!(respond(Format::Csv, json!([])).await.1, "");
+        assert_eq!(respond(Format::Csv, json!(5)).await.1, "value\n5\n");
+    }
+}
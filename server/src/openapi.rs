@@ -0,0 +1,190 @@
+use crate::audit::{AuditEntry, PlaylistChange, SongChange};
+use crate::catalog::{Album, AlbumSummary, Artist, ArtistSort, GenreBreakdown};
+use crate::duplicates::{DuplicateCluster, MergeRequest, MergeResponse};
+use crate::health::{PersistenceError, Readiness, ServerInfo};
+use crate::libraries::{LibraryInfo, NewLibraryRequest};
+use crate::playlists::{
+    NewPlaylistRequest, PlaylistKind, PlaylistSongsRequest, PlaylistSummary, PlaylistView,
+    SmartRule,
+};
+use crate::query::QueryError;
+use crate::queue::{EnqueueRequest, QueueModeRequest, QueueView, RepeatMode};
+use crate::ratings::{FavouriteRequest, RatingRequest, Review, UserRating};
+use crate::reload::{ReloadConflict, ReloadStatus};
+use crate::scanner::{ScanError, ScanSummary};
+use crate::scheduler::JobStatus;
+use crate::snapshot::SnapshotInfo;
+use crate::trending::TrendingSong;
+use crate::webhooks::{Delivery, Event, EventPayload, NewWebhookRequest, WebhookInfo};
+use crate::{
+    ErrorMessage, NewSongRequest, ScanRequest, Song, SongSearchQuery, SongSort, SortOrder,
+};
+use axum::{
+    http::header,
+    response::{Html, IntoResponse},
+};
+use utoipa::OpenApi;
+
+// OpenAPI 3 document generated from the handlers and their request/response types
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Music Library API",
+        description = "Personal music library server"
+    ),
+    paths(
+        crate::handle_songs_new,
+        crate::handle_songs_search,
+        crate::handle_songs_play,
+        crate::handle_songs_by_uid,
+        crate::libraries::handle_libraries_list,
+        crate::libraries::handle_libraries_create,
+        crate::libraries::handle_library_songs_new,
+        crate::libraries::handle_library_songs_search,
+        crate::libraries::handle_library_songs_play,
+        crate::libraries::handle_library_songs_by_uid,
+        crate::libraries::handle_library_songs_stream,
+        crate::libraries::handle_library_songs_duplicates,
+        crate::trending::handle_songs_trending,
+        crate::catalog::handle_artists_list,
+        crate::catalog::handle_artist_songs,
+        crate::catalog::handle_album_get,
+        crate::playlists::handle_playlists_list,
+        crate::playlists::handle_playlists_create,
+        crate::playlists::handle_playlists_get,
+        crate::playlists::handle_playlists_add_songs,
+        crate::playlists::handle_playlists_delete,
+        crate::duplicates::handle_songs_duplicates,
+        crate::duplicates::handle_songs_merge,
+        crate::audit::handle_audit_list,
+        crate::audit::handle_audit_undo,
+        crate::stream::handle_songs_stream,
+        crate::ratings::handle_songs_rate,
+        crate::ratings::handle_songs_favourite,
+        crate::ratings::handle_songs_reviews,
+        crate::ratings::handle_user_favourites,
+        crate::queue::handle_queue_create,
+        crate::queue::handle_queue_get,
+        crate::queue::handle_queue_delete,
+        crate::queue::handle_queue_enqueue,
+        crate::queue::handle_queue_next,
+        crate::queue::handle_queue_skip,
+        crate::queue::handle_queue_previous,
+        crate::queue::handle_queue_mode,
+        crate::health::handle_healthz,
+        crate::health::handle_readyz,
+        crate::health::handle_admin_info,
+        crate::handle_admin_scan,
+        crate::scheduler::handle_jobs_list,
+        crate::scheduler::handle_jobs_run,
+        crate::reload::handle_reload_status,
+        crate::reload::handle_reload,
+        crate::snapshot::handle_snapshot_list,
+        crate::snapshot::handle_snapshot_create,
+        crate::snapshot::handle_snapshot_restore,
+        crate::webhooks::handle_webhooks_list,
+        crate::webhooks::handle_webhooks_create,
+        crate::webhooks::handle_webhooks_delete,
+        crate::webhooks::handle_dead_letters_list,
+        crate::webhooks::handle_dead_letters_retry,
+        crate::graphql::handle_graphql,
+        crate::graphql::handle_graphiql,
+    ),
+    components(schemas(
+        Song,
+        NewSongRequest,
+        ErrorMessage,
+        QueryError,
+        SongSort,
+        SortOrder,
+        RatingRequest,
+        FavouriteRequest,
+        Review,
+        UserRating,
+        DuplicateCluster,
+        MergeRequest,
+        MergeResponse,
+        AuditEntry,
+        SongChange,
+        PlaylistChange,
+        NewLibraryRequest,
+        LibraryInfo,
+        SongSearchQuery,
+        EnqueueRequest,
+        QueueModeRequest,
+        QueueView,
+        RepeatMode,
+        Readiness,
+        ServerInfo,
+        PersistenceError,
+        ReloadStatus,
+        ReloadConflict,
+        JobStatus,
+        TrendingSong,
+        Artist,
+        ArtistSort,
+        Album,
+        AlbumSummary,
+        GenreBreakdown,
+        PlaylistSummary,
+        PlaylistView,
+        PlaylistKind,
+        SmartRule,
+        NewPlaylistRequest,
+        PlaylistSongsRequest,
+        WebhookInfo,
+        NewWebhookRequest,
+        Event,
+        EventPayload,
+        Delivery,
+        ScanRequest,
+        ScanSummary,
+        ScanError,
+        SnapshotInfo,
+    ))
+)]
+pub struct ApiDoc;
+
+// Serve the OpenAPI document
+pub async fn handle_openapi_json() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        ApiDoc::openapi().to_json().unwrap(),
+    )
+}
+
+// Serve the docs page that renders /openapi.json. The page loads a pinned
+// Swagger UI release from the unpkg CDN, so the browser needs internet access
+// (and a Content-Security-Policy, if set, must allow unpkg.com); the spec
+// itself is always available at /openapi.json.
+pub async fn handle_docs() -> Html<&'static str> {
+    Html(include_str!("../static/docs.html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_covers_admin_user_and_graphql_routes() {
+        let spec = ApiDoc::openapi();
+        for path in [
+            "/admin/scan",
+            "/admin/snapshots",
+            "/admin/snapshots/{name}/restore",
+            "/users/{user}/favourites",
+            "/graphql",
+        ] {
+            assert!(spec.paths.paths.contains_key(path), "{} is missing", path);
+        }
+        let schemas = &spec.components.as_ref().unwrap().schemas;
+        assert!(schemas.contains_key("ScanSummary"));
+        assert!(schemas.contains_key("SnapshotInfo"));
+
+        // Songs never carry the server path of their file
+        let song = serde_json::to_value(&schemas["Song"]).unwrap();
+        assert!(song["properties"].get("title").is_some());
+        assert!(song["properties"].get("file").is_none());
+        assert!(!schemas.contains_key("AudioFile"));
+    }
+}
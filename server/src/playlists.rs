@@ -0,0 +1,716 @@
+use crate::audit::{self, Actor};
+use crate::health::Persistence;
+use crate::library::{Library, LibrarySong, Sequence};
+use crate::negotiate::{Format, Negotiated};
+use crate::query::{self, QueryError};
+use crate::{
+    AppState, ErrorMessage, Song, SongSearchQuery, SongSort, SortOrder, load_json, save_json,
+    search_songs,
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+// Playlists of the default library. A regular playlist holds fixed song
+// ids; a smart playlist holds a rule instead, a saved search that is
+// evaluated against the current songs whenever the playlist is read, so it
+// follows new songs, plays and edits without being updated.
+pub const PLAYLISTS_FILE: &str = "playlists.json";
+pub const PLAYLIST_SEQUENCE_FILE: &str = "playlist_sequence.json";
+
+// Longest playlist name, in characters
+const MAX_NAME_LEN: usize = 100;
+
+// Rule of a smart playlist, e.g. `genre:rock AND plays>5`, most played
+// first, at most 50 songs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SmartRule {
+    // Search expression, as on GET /songs/search
+    query: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sort: Option<SongSort>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    order: Option<SortOrder>,
+    // Maximum number of songs (all matching songs when omitted)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistKind {
+    Regular,
+    Smart,
+}
+
+// A saved playlist, as stored in playlists.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    id: u64,
+    name: String,
+    // Songs of a regular playlist, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    song_ids: Vec<u64>,
+    // Set for smart playlists, which have no fixed songs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rule: Option<SmartRule>,
+    created_by: String,
+    created_at: String,
+}
+
+// The saved playlists and a monotonic id sequence, so a deleted playlist's
+// id never points at another playlist. Derefs to the playlists.
+#[derive(Debug, Default)]
+pub struct Playlists {
+    playlists: Vec<Playlist>,
+    next_id: u64,
+}
+
+// A playlist as listed by GET /playlists
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PlaylistSummary {
+    pub id: u64,
+    pub name: String,
+    pub kind: PlaylistKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<SmartRule>,
+    // Songs currently in the playlist
+    pub song_count: usize,
+    pub created_by: String,
+    pub created_at: String,
+    // Why a smart playlist's rule could not be evaluated (it is then empty)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<QueryError>,
+}
+
+// A playlist with its songs
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PlaylistView {
+    #[serde(flatten)]
+    summary: PlaylistSummary,
+    songs: Vec<Song>,
+}
+
+// Structure for receiving a new playlist from POST JSON: fixed songs, or a
+// rule for a smart playlist
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewPlaylistRequest {
+    name: String,
+    #[serde(default)]
+    song_ids: Vec<u64>,
+    rule: Option<SmartRule>,
+}
+
+// Structure for receiving songs to append to a regular playlist
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PlaylistSongsRequest {
+    song_ids: Vec<u64>,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Load the playlists and their id sequence. The sequence never goes below
+// the highest id in use, so an outdated or missing sequence file is harmless.
+pub fn load(persistence: &Persistence) -> Playlists {
+    let playlists: Vec<Playlist> = load_json(persistence, PLAYLISTS_FILE);
+    let sequence: Sequence = load_json(persistence, PLAYLIST_SEQUENCE_FILE);
+    let next_id = playlists
+        .iter()
+        .map(|p| p.id + 1)
+        .fold(sequence.next_id.max(1), u64::max);
+    Playlists { playlists, next_id }
+}
+
+impl Playlists {
+    // Reserve the next playlist id
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    // Replace the playlists, e.g. with those of a snapshot, and save them.
+    // Ids already handed out are never reused.
+    pub fn restore(&mut self, playlists: Vec<Playlist>, persistence: &Persistence) {
+        self.next_id = playlists
+            .iter()
+            .map(|p| p.id + 1)
+            .fold(self.next_id, u64::max);
+        self.playlists = playlists;
+        self.save(persistence);
+    }
+
+    // The playlist with this id
+    pub fn find(&self, id: u64) -> Option<&Playlist> {
+        self.playlists.iter().find(|p| p.id == id)
+    }
+
+    // Put a playlist back as it was (`None` deletes it), e.g. when a change
+    // is undone, and save the playlists. Playlists stay in id order.
+    pub fn put(&mut self, id: u64, playlist: Option<Playlist>, persistence: &Persistence) {
+        self.playlists.retain(|p| p.id != id);
+        if let Some(playlist) = playlist {
+            let idx = self.playlists.partition_point(|p| p.id < id);
+            self.playlists.insert(idx, playlist);
+            self.next_id = self.next_id.max(id + 1);
+        }
+        self.save(persistence);
+    }
+
+    // Save the playlists and the id sequence
+    fn save(&self, persistence: &Persistence) {
+        let sequence = Sequence {
+            next_id: self.next_id,
+            ..Default::default()
+        };
+        save_json(persistence, PLAYLIST_SEQUENCE_FILE, &sequence);
+        save_json(persistence, PLAYLISTS_FILE, &self.playlists);
+    }
+}
+
+impl Deref for Playlists {
+    type Target = Vec<Playlist>;
+
+    fn deref(&self) -> &Vec<Playlist> {
+        &self.playlists
+    }
+}
+
+impl DerefMut for Playlists {
+    fn deref_mut(&mut self) -> &mut Vec<Playlist> {
+        &mut self.playlists
+    }
+}
+
+impl Playlist {
+    fn kind(&self) -> PlaylistKind {
+        match self.rule {
+            Some(_) => PlaylistKind::Smart,
+            None => PlaylistKind::Regular,
+        }
+    }
+
+    // The playlist's songs as of now. Ids of merged songs resolve to the song
+    // they were merged into; deleted songs are left out. Fails for a smart
+    // playlist whose rule no longer parses, e.g. one saved before the query
+    // limits were introduced.
+    fn songs(&self, songs: &Library) -> Result<Vec<Song>, QueryError> {
+        let Some(rule) = &self.rule else {
+            return Ok(self
+                .song_ids
+                .iter()
+                .filter_map(|&id| songs.get(id).map(LibrarySong::song))
+                .collect());
+        };
+
+        let search = SongSearchQuery {
+            query: Some(rule.query.clone()),
+            sort: rule.sort,
+            order: rule.order,
+            ..Default::default()
+        };
+        let mut results = search_songs(songs, &search).inspect_err(|e| {
+            warn!(
+                id = self.id,
+                error = e.message(),
+                "smart playlist rule does not parse"
+            );
+        })?;
+        if let Some(limit) = rule.limit {
+            results.truncate(limit);
+        }
+        Ok(results)
+    }
+
+    fn summary(&self, songs: &Result<Vec<Song>, QueryError>) -> PlaylistSummary {
+        PlaylistSummary {
+            id: self.id,
+            name: self.name.clone(),
+            kind: self.kind(),
+            rule: self.rule.clone(),
+            song_count: songs.as_ref().map_or(0, Vec::len),
+            created_by: self.created_by.clone(),
+            created_at: self.created_at.clone(),
+            error: songs.as_ref().err().cloned(),
+        }
+    }
+
+    // The playlist with its songs, or why its rule could not be evaluated
+    fn view(&self, songs: &Library) -> Result<PlaylistView, QueryError> {
+        let songs = self.songs(songs);
+        let summary = self.summary(&songs);
+        Ok(PlaylistView {
+            summary,
+            songs: songs?,
+        })
+    }
+}
+
+// Every playlist with its current songs, for GraphQL. A smart playlist whose
+// rule does not parse comes with the parse error instead.
+pub fn evaluate_all(state: &AppState) -> Vec<(PlaylistSummary, Result<Vec<Song>, QueryError>)> {
+    let playlists = state.playlists.read().to_vec();
+    let songs = state.songs.read();
+    playlists
+        .iter()
+        .map(|playlist| {
+            let result = playlist.songs(&songs);
+            (playlist.summary(&result), result)
+        })
+        .collect()
+}
+
+// Response for a playlist whose rule no longer parses
+fn invalid_rule(e: QueryError) -> Response {
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(e)).into_response()
+}
+
+// Copy of a playlist, so the playlist lock is not held while the songs are
+// read
+fn find(state: &AppState, id: u64) -> Result<Playlist, ApiError> {
+    state
+        .playlists
+        .read()
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Playlist not found"))
+}
+
+// List regular and smart playlists
+#[utoipa::path(
+    get,
+    path = "/playlists",
+    tag = "playlists",
+    responses((status = 200, description = "Every playlist, with smart playlists evaluated now (a rule that no longer parses is reported in `error`)", body = [PlaylistSummary], content_type = ["application/json", "text/csv", "application/msgpack"]))
+)]
+pub async fn handle_playlists_list(
+    State(state): State<Arc<AppState>>,
+    format: Format,
+) -> Negotiated<Vec<PlaylistSummary>> {
+    let playlists = state.playlists.read().to_vec();
+    let songs = state.songs.read();
+
+    let summaries = playlists
+        .iter()
+        .map(|playlist| playlist.summary(&playlist.songs(&songs)))
+        .collect();
+    Negotiated(format, summaries)
+}
+
+// Create a regular or smart playlist
+#[utoipa::path(
+    post,
+    path = "/playlists",
+    tag = "playlists",
+    request_body = NewPlaylistRequest,
+    responses(
+        (status = 201, description = "The playlist with its current songs", body = PlaylistView),
+        (status = 400, description = "Invalid name, unknown song, songs given with a rule, or a rule whose query does not parse (with the position of the error)", body = ErrorMessage),
+    )
+)]
+pub async fn handle_playlists_create(
+    State(state): State<Arc<AppState>>,
+    Actor(user): Actor,
+    Json(payload): Json<NewPlaylistRequest>,
+) -> Response {
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "Playlist names must be 1 to 100 characters",
+        )
+        .into_response();
+    }
+
+    if let Some(rule) = &payload.rule {
+        if !payload.song_ids.is_empty() {
+            return error(
+                StatusCode::BAD_REQUEST,
+                "A smart playlist is defined by its rule and cannot have songs",
+            )
+            .into_response();
+        }
+        if let Err(e) = query::parse(&rule.query) {
+            return (StatusCode::BAD_REQUEST, Json(e)).into_response();
+        }
+    }
+
+    // Lock order: songs before playlists
+    let songs = state.songs.read();
+    if payload.song_ids.iter().any(|&id| songs.get(id).is_none()) {
+        return error(StatusCode::BAD_REQUEST, "Song not found").into_response();
+    }
+
+    let mut playlists = state.playlists.write();
+    let playlist = Playlist {
+        id: playlists.allocate_id(),
+        name: name.to_string(),
+        song_ids: payload.song_ids,
+        rule: payload.rule,
+        created_by: user.clone(),
+        created_at: Utc::now().to_rfc3339(),
+    };
+    playlists.push(playlist.clone());
+    playlists.save(&state.persistence);
+    audit::record_playlist(
+        &state,
+        &user,
+        "playlist-create",
+        playlist.id,
+        None,
+        Some(playlist.clone()),
+    );
+
+    info!(id = playlist.id, name = playlist.name, kind = ?playlist.kind(), "playlist created");
+    match playlist.view(&songs) {
+        Ok(view) => (StatusCode::CREATED, Json(view)).into_response(),
+        Err(e) => invalid_rule(e),
+    }
+}
+
+// A playlist with its songs
+#[utoipa::path(
+    get,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = u64, Path, description = "Playlist id")),
+    responses(
+        (status = 200, description = "The playlist; a smart playlist's songs match its rule now", body = PlaylistView),
+        (status = 404, description = "No playlist with this id", body = ErrorMessage),
+        (status = 422, description = "The smart playlist's rule no longer parses", body = QueryError),
+    )
+)]
+pub async fn handle_playlists_get(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> Response {
+    let playlist = match find(&state, id) {
+        Ok(playlist) => playlist,
+        Err(e) => return e.into_response(),
+    };
+    match playlist.view(&state.songs.read()) {
+        Ok(view) => Json(view).into_response(),
+        Err(e) => invalid_rule(e),
+    }
+}
+
+// Append songs to a regular playlist
+#[utoipa::path(
+    post,
+    path = "/playlists/{id}/songs",
+    tag = "playlists",
+    params(("id" = u64, Path, description = "Playlist id")),
+    request_body = PlaylistSongsRequest,
+    responses(
+        (status = 200, description = "The updated playlist", body = PlaylistView),
+        (status = 400, description = "Unknown song, or a smart playlist", body = ErrorMessage),
+        (status = 404, description = "No playlist with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_playlists_add_songs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Actor(user): Actor,
+    Json(payload): Json<PlaylistSongsRequest>,
+) -> Result<Json<PlaylistView>, ApiError> {
+    // Lock order: songs, playlists, then the audit log
+    let songs = state.songs.read();
+    if payload.song_ids.iter().any(|&id| songs.get(id).is_none()) {
+        return Err(error(StatusCode::BAD_REQUEST, "Song not found"));
+    }
+
+    let mut playlists = state.playlists.write();
+    let Some(playlist) = playlists.iter_mut().find(|p| p.id == id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Playlist not found"));
+    };
+    if playlist.rule.is_some() {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "A smart playlist is defined by its rule and cannot have songs",
+        ));
+    }
+    let before = playlist.clone();
+    playlist.song_ids.extend(payload.song_ids);
+    let playlist = playlist.clone();
+    playlists.save(&state.persistence);
+    audit::record_playlist(
+        &state,
+        &user,
+        "playlist-add-songs",
+        id,
+        Some(before),
+        Some(playlist.clone()),
+    );
+
+    // Only regular playlists get here, and their songs always evaluate
+    let songs = playlist.songs(&songs).unwrap_or_default();
+    Ok(Json(PlaylistView {
+        summary: playlist.summary(&Ok(songs.clone())),
+        songs,
+    }))
+}
+
+// Delete a playlist (its songs stay in the library)
+#[utoipa::path(
+    delete,
+    path = "/playlists/{id}",
+    tag = "playlists",
+    params(("id" = u64, Path, description = "Playlist id")),
+    responses(
+        (status = 204, description = "The playlist was deleted"),
+        (status = 404, description = "No playlist with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_playlists_delete(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Actor(user): Actor,
+) -> Result<StatusCode, ApiError> {
+    // Lock order: playlists before the audit log
+    let mut playlists = state.playlists.write();
+    let Some(idx) = playlists.iter().position(|p| p.id == id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Playlist not found"));
+    };
+    let playlist = playlists.remove(idx);
+    playlists.save(&state.persistence);
+    audit::record_playlist(&state, &user, "playlist-delete", id, Some(playlist), None);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, NewSongRequest, add_song, load_state, record_play};
+    use axum::body::to_bytes;
+    use serde_json::{Value, json};
+
+    fn state(dir: &std::path::Path) -> Arc<AppState> {
+        load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        })
+        .0
+    }
+
+    fn add(state: &AppState, title: &str, genre: &str) -> Song {
+        let song = NewSongRequest {
+            title: title.to_string(),
+            artist: "Lister".to_string(),
+            genre: genre.to_string(),
+            album: None,
+        };
+        add_song(state, "alice", song)
+    }
+
+    async fn create(state: &Arc<AppState>, payload: Value) -> (StatusCode, Value) {
+        let payload = serde_json::from_value(payload).unwrap();
+        let response = handle_playlists_create(
+            State(state.clone()),
+            Actor("alice".to_string()),
+            Json(payload),
+        )
+        .await;
+        read(response).await
+    }
+
+    async fn get(state: &Arc<AppState>, id: u64) -> (StatusCode, Value) {
+        read(handle_playlists_get(State(state.clone()), Path(id)).await).await
+    }
+
+    async fn read(response: Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn titles(view: &Value) -> Vec<&str> {
+        view["songs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn smart_rules_follow_songs_and_plays() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let a = add(&state, "A", "Rock");
+        let b = add(&state, "B", "Rock");
+        add(&state, "C", "Jazz");
+        record_play(&state, b.id);
+
+        let rule = json!({"query": "genre:rock", "sort": "plays", "order": "desc", "limit": 2});
+        let (status, view) = create(&state, json!({"name": "Top rock", "rule": rule})).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(view["kind"], "smart");
+        assert_eq!(titles(&view), ["B", "A"]);
+
+        // A new song and more plays change the playlist without touching it
+        let d = add(&state, "D", "Rock");
+        for _ in 0..2 {
+            record_play(&state, d.id);
+        }
+        record_play(&state, a.id);
+        let (_, view) = get(&state, view["id"].as_u64().unwrap()).await;
+        assert_eq!(titles(&view), ["D", "A"]);
+        assert_eq!(view["song_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn regular_playlists_skip_deleted_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let a = add(&state, "A", "Rock");
+        let b = add(&state, "B", "Rock");
+
+        let (_, view) = create(&state, json!({"name": "Mix", "song_ids": [b.id, a.id]})).await;
+        assert_eq!(view["kind"], "regular");
+        assert_eq!(titles(&view), ["B", "A"]);
+
+        state.songs.write().remove(b.id);
+        let (_, view) = get(&state, view["id"].as_u64().unwrap()).await;
+        assert_eq!(titles(&view), ["A"]);
+
+        let (status, _) = create(&state, json!({"name": "Bad", "song_ids": [99]})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn a_rule_that_no_longer_parses_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        add(&state, "A", "Rock");
+
+        let (status, _) = create(&state, json!({"name": "Bad", "rule": {"query": "genre:"}})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // A stored rule that the current query language rejects
+        let (_, view) = create(
+            &state,
+            json!({"name": "Rock", "rule": {"query": "genre:rock"}}),
+        )
+        .await;
+        let id = view["id"].as_u64().unwrap();
+        state.playlists.write()[0].rule.as_mut().unwrap().query = "genre:".to_string();
+
+        let (status, body) = get(&state, id).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["position"].is_number());
+
+        let summaries = handle_playlists_list(State(state.clone()), Format::Json).await;
+        let summary = &summaries.1[0];
+        assert_eq!(summary.song_count, 0);
+        assert!(summary.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn ids_are_never_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+
+        let (_, first) = create(&state, json!({"name": "One"})).await;
+        let (_, second) = create(&state, json!({"name": "Two"})).await;
+        let second = second["id"].as_u64().unwrap();
+        assert!(second > first["id"].as_u64().unwrap());
+
+        handle_playlists_delete(State(state.clone()), Path(second), Actor("x".to_string()))
+            .await
+            .unwrap();
+        let (_, third) = create(&state, json!({"name": "Three"})).await;
+        let third = third["id"].as_u64().unwrap();
+        assert!(third > second);
+
+        // Nor after a restart, once the newest playlist is gone
+        handle_playlists_delete(State(state.clone()), Path(third), Actor("x".to_string()))
+            .await
+            .unwrap();
+        drop(state);
+        let state = super::tests::state(dir.path());
+        let (_, fourth) = create(&state, json!({"name": "Four"})).await;
+        assert!(fourth["id"].as_u64().unwrap() > third);
+    }
+
+    #[tokio::test]
+    async fn changes_are_audited_and_can_be_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let a = add(&state, "A", "Rock");
+        let b = add(&state, "B", "Rock");
+
+        let (_, view) = create(&state, json!({"name": "Mix", "song_ids": [a.id]})).await;
+        let id = view["id"].as_u64().unwrap();
+        let payload = serde_json::from_value(json!({"song_ids": [b.id]})).unwrap();
+        let added = handle_playlists_add_songs(
+            State(state.clone()),
+            Path(id),
+            Actor("bob".to_string()),
+            Json(payload),
+        )
+        .await;
+        assert!(added.is_ok());
+        handle_playlists_delete(State(state.clone()), Path(id), Actor("carol".to_string()))
+            .await
+            .unwrap();
+
+        let recorded: Vec<Value> = state
+            .audit
+            .read()
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .filter(|e| e["playlist"].is_object())
+            .collect();
+        let actions: Vec<(&str, &str)> = recorded
+            .iter()
+            .map(|e| (e["action"].as_str().unwrap(), e["user"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("playlist-create", "alice"),
+                ("playlist-add-songs", "bob"),
+                ("playlist-delete", "carol"),
+            ]
+        );
+
+        // Undoing the delete brings the playlist back with both songs
+        let delete = recorded[2]["id"].as_u64().unwrap();
+        let undo = audit::handle_audit_undo(
+            State(state.clone()),
+            Path(delete),
+            Actor("carol".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(serde_json::to_value(&undo.0).unwrap()["undo_of"], delete);
+        let (status, view) = get(&state, id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&view), ["A", "B"]);
+
+        let Err((status, _)) = audit::handle_audit_undo(
+            State(state.clone()),
+            Path(delete),
+            Actor("carol".to_string()),
+        )
+        .await
+        else {
+            panic!("a change can only be undone once");
+        };
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::{AppState, save_songs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::info;
+
+// Changes counted in memory that have not been written to disk yet: the
+// plays of a library, or webhook dead letters
+#[derive(Debug, Default)]
+pub struct Unsaved(AtomicBool);
+
+impl Unsaved {
+    pub fn mark(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Write play counts to disk for every library with unsaved plays, returning
+// whether all of them were saved. A save that is deferred or fails leaves the
+// plays marked, so they are retried. Run by the `checkpoint-plays` job.
+pub fn checkpoint(state: &AppState) -> bool {
+    let mut libraries = vec![state.songs.clone()];
+    libraries.extend(state.libraries.read().values().cloned());
+
+    let mut saved = true;
+    for library in libraries {
+        if library.read().unsaved_plays() {
+            let mut songs = library.write();
+            save_songs(&state.persistence, &mut songs);
+            saved &= !songs.unsaved_plays();
+        }
+    }
+    saved
+}
+
+// Resolves on Ctrl-C or SIGTERM. The server then stops accepting
+// connections, finishes the requests in flight and saves the plays counted
+// since the last checkpoint (see `run`).
+pub async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut term = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+
+    info!("shutting down");
+}
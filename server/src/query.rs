@@ -0,0 +1,607 @@
+use crate::library::LibrarySong;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// Search expression language used by `GET /songs/search?query=...`:
+//
+//   artist:"Taylor Swift" AND NOT genre:pop AND plays>10
+//
+// * `field:word` matches a case-insensitive substring of title/artist/album/genre
+// * `field:"some phrase"` matches the whole field exactly (case-insensitive)
+// * `field:pre*` matches fields containing a word starting with `pre`
+// * a bare word or phrase matches any of the three text fields
+// * `plays`, `rating`, `favourites` and `id` compare with `= != < <= > >=`
+// * `AND` (or juxtaposition), `OR`, `NOT` and parentheses combine terms
+
+// Deepest nesting of `NOT` and parentheses. Each level is a recursive call
+// in the parser, so this keeps hostile queries from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+// Most search terms in one query. `AND` and `OR` chains build a tree as deep
+// as they are long, which is evaluated and dropped recursively.
+const MAX_TERMS: usize = 256;
+
+// A query that failed to parse, pointing at the offending character
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QueryError {
+    error: String,
+    // 0-based character offset into the query
+    position: usize,
+}
+
+impl QueryError {
+    pub fn message(&self) -> &str {
+        &self.error
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+fn query_error(error: impl Into<String>, position: usize) -> QueryError {
+    QueryError {
+        error: error.into(),
+        position,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberField {
+    Id,
+    Plays,
+    Rating,
+    Favourites,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Contains(String),
+    Exact(String),
+    Prefix(String),
+}
+
+// Parsed search expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Text(Option<TextField>, Pattern),
+    Number(NumberField, Comparison, f64),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(String),
+    Colon,
+    Compare(Comparison),
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | ':' | '"' | '<' | '>' | '=' | '!')
+}
+
+// Split a query into tokens, each with the character position it starts at
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                i += 1;
+                Token::LParen
+            }
+            ')' => {
+                i += 1;
+                Token::RParen
+            }
+            ':' => {
+                i += 1;
+                Token::Colon
+            }
+            '"' => {
+                i += 1;
+                let mut phrase = String::new();
+                while i < chars.len() && chars[i] != '"' {
+                    phrase.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(query_error("unterminated quoted phrase", start));
+                }
+                i += 1;
+                Token::Phrase(phrase)
+            }
+            '<' | '>' | '=' | '!' => {
+                let next = chars.get(i + 1).copied();
+                let (op, len) = match (c, next) {
+                    ('<', Some('=')) => (Comparison::Le, 2),
+                    ('>', Some('=')) => (Comparison::Ge, 2),
+                    ('!', Some('=')) => (Comparison::Ne, 2),
+                    ('<', _) => (Comparison::Lt, 1),
+                    ('>', _) => (Comparison::Gt, 1),
+                    ('=', _) => (Comparison::Eq, 1),
+                    _ => return Err(query_error("expected '!='", start)),
+                };
+                i += len;
+                Token::Compare(op)
+            }
+            _ => {
+                let mut word = String::new();
+                while i < chars.len() && is_word_char(chars[i]) {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                }
+            }
+        };
+
+        tokens.push((token, start));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+    // Current nesting of `NOT` and parentheses
+    depth: usize,
+    terms: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    // Position of the next token (or the end of the input)
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, p)| *p)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    // Go one level deeper for a `NOT` or `(` at `position`
+    fn enter(&mut self, position: usize) -> Result<(), QueryError> {
+        if self.depth == MAX_DEPTH {
+            return Err(query_error(
+                format!("query is nested more than {} levels deep", MAX_DEPTH),
+                position,
+            ));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Count a search term starting at `position`
+    fn term(&mut self, position: usize) -> Result<(), QueryError> {
+        if self.terms == MAX_TERMS {
+            return Err(query_error(
+                format!("query has more than {} terms", MAX_TERMS),
+                position,
+            ));
+        }
+        self.terms += 1;
+        Ok(())
+    }
+
+    // or := and ("OR" and)*
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            let rhs = self.parse_and()?;
+            expr = Expr::Or(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    // and := not (["AND"] not)*
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Word(_) | Token::Phrase(_) | Token::Not | Token::LParen) => {}
+                _ => break,
+            }
+            let rhs = self.parse_not()?;
+            expr = Expr::And(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    // not := "NOT" not | primary
+    fn parse_not(&mut self) -> Result<Expr, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.enter(self.position())?;
+            self.next();
+            let expr = Expr::Not(Box::new(self.parse_not()?));
+            self.depth -= 1;
+            return Ok(expr);
+        }
+        self.parse_primary()
+    }
+
+    // primary := "(" or ")" | field ":" value | field op number | value
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let position = self.position();
+        if matches!(self.peek(), Some(Token::Word(_) | Token::Phrase(_))) {
+            self.term(position)?;
+        }
+
+        match self.next() {
+            Some(Token::LParen) => {
+                self.enter(position)?;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(query_error("expected ')'", self.position()));
+                }
+                self.next();
+                self.depth -= 1;
+                Ok(expr)
+            }
+            Some(Token::Word(word)) => match self.peek() {
+                Some(Token::Colon) => {
+                    let field = text_field(&word).ok_or_else(|| {
+                        query_error(format!("unknown field '{}'", word), position)
+                    })?;
+                    self.next();
+                    let pattern = self.parse_pattern()?;
+                    Ok(Expr::Text(Some(field), pattern))
+                }
+                Some(Token::Compare(op)) => {
+                    let op = *op;
+                    let field = number_field(&word).ok_or_else(|| {
+                        query_error(format!("unknown numeric field '{}'", word), position)
+                    })?;
+                    self.next();
+                    let value_position = self.position();
+                    match self.next() {
+                        Some(Token::Word(value)) => {
+                            let value = value.parse::<f64>().map_err(|_| {
+                                query_error(
+                                    format!("expected a number, found '{}'", value),
+                                    value_position,
+                                )
+                            })?;
+                            Ok(Expr::Number(field, op, value))
+                        }
+                        _ => Err(query_error("expected a number", value_position)),
+                    }
+                }
+                _ => Ok(Expr::Text(None, word_pattern(word))),
+            },
+            Some(Token::Phrase(phrase)) => {
+                Ok(Expr::Text(None, Pattern::Contains(phrase.to_lowercase())))
+            }
+            Some(Token::RParen) => Err(query_error("unexpected ')'", position)),
+            Some(Token::Colon) => Err(query_error("expected a field name before ':'", position)),
+            Some(Token::Compare(_)) => Err(query_error(
+                "expected a field name before comparison",
+                position,
+            )),
+            Some(Token::And | Token::Or) => Err(query_error("expected a search term", position)),
+            Some(Token::Not) => unreachable!("NOT is handled by parse_not"),
+            None => Err(query_error("unexpected end of query", position)),
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, QueryError> {
+        let position = self.position();
+        match self.next() {
+            Some(Token::Phrase(phrase)) => Ok(Pattern::Exact(phrase.to_lowercase())),
+            Some(Token::Word(word)) => Ok(word_pattern(word)),
+            _ => Err(query_error("expected a value after ':'", position)),
+        }
+    }
+}
+
+fn word_pattern(word: String) -> Pattern {
+    let word = word.to_lowercase();
+    match word.strip_suffix('*') {
+        Some(prefix) if !prefix.is_empty() => Pattern::Prefix(prefix.to_string()),
+        _ => Pattern::Contains(word),
+    }
+}
+
+fn text_field(name: &str) -> Option<TextField> {
+    match name.to_lowercase().as_str() {
+        "title" => Some(TextField::Title),
+        "artist" => Some(TextField::Artist),
+        "album" => Some(TextField::Album),
+        "genre" => Some(TextField::Genre),
+        _ => None,
+    }
+}
+
+fn number_field(name: &str) -> Option<NumberField> {
+    match name.to_lowercase().as_str() {
+        "id" => Some(NumberField::Id),
+        "plays" | "play_count" => Some(NumberField::Plays),
+        "rating" => Some(NumberField::Rating),
+        "favourites" | "favorites" => Some(NumberField::Favourites),
+        _ => None,
+    }
+}
+
+// Parse a query expression
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    let tokens = tokenize(input)?;
+    let end = input.chars().count();
+    if tokens.is_empty() {
+        return Err(query_error("empty query", 0));
+    }
+
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end,
+        depth: 0,
+        terms: 0,
+    };
+    let expr = parser.parse_or()?;
+
+    if parser.pos < parser.tokens.len() {
+        let position = parser.position();
+        let message = if parser.peek() == Some(&Token::RParen) {
+            "unexpected ')'"
+        } else {
+            "unexpected token"
+        };
+        return Err(query_error(message, position));
+    }
+
+    Ok(expr)
+}
+
+fn matches_pattern(value: &str, pattern: &Pattern) -> bool {
+    let value = value.to_lowercase();
+    match pattern {
+        Pattern::Contains(needle) => value.contains(needle.as_str()),
+        Pattern::Exact(phrase) => value == *phrase,
+        Pattern::Prefix(prefix) => value
+            .split(|c: char| !c.is_alphanumeric())
+            .any(|word| word.starts_with(prefix.as_str())),
+    }
+}
+
+impl Expr {
+    // Evaluate the expression against a song
+    pub fn matches(&self, song: &LibrarySong) -> bool {
+        match self {
+            Expr::Text(Some(field), pattern) => {
+                let value = match field {
+                    TextField::Title => song.title.as_str(),
+                    TextField::Artist => song.artist.as_str(),
+                    TextField::Album => song.album.as_deref().unwrap_or(""),
+                    TextField::Genre => song.genre.as_str(),
+                };
+                matches_pattern(value, pattern)
+            }
+            Expr::Text(None, pattern) => [&song.title, &song.artist, &song.genre]
+                .iter()
+                .any(|value| matches_pattern(value, pattern)),
+            Expr::Number(field, op, rhs) => {
+                let lhs = match field {
+                    NumberField::Id => song.id as f64,
+                    NumberField::Plays => song.plays() as f64,
+                    NumberField::Favourites => song.favourite_count as f64,
+                    // Unrated songs never satisfy a rating comparison
+                    NumberField::Rating => match song.average_rating {
+                        Some(rating) => rating,
+                        None => return false,
+                    },
+                };
+                match op {
+                    Comparison::Eq => lhs == *rhs,
+                    Comparison::Ne => lhs != *rhs,
+                    Comparison::Lt => lhs < *rhs,
+                    Comparison::Le => lhs <= *rhs,
+                    Comparison::Gt => lhs > *rhs,
+                    Comparison::Ge => lhs >= *rhs,
+                }
+            }
+            Expr::Not(inner) => !inner.matches(song),
+            Expr::And(lhs, rhs) => lhs.matches(song) && rhs.matches(song),
+            Expr::Or(lhs, rhs) => lhs.matches(song) || rhs.matches(song),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: &str, genre: &str, plays: u64) -> LibrarySong {
+        LibrarySong::new(
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "title": title,
+                "artist": artist,
+                "album": "Greatest Hits",
+                "genre": genre,
+                "play_count": plays,
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn error_at(query: &str) -> (String, usize) {
+        let e = parse(query).unwrap_err();
+        (e.error, e.position)
+    }
+
+    fn text(field: Option<TextField>, pattern: Pattern) -> Expr {
+        Expr::Text(field, pattern)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let a = || text(None, Pattern::Contains("a".to_string()));
+        let b = || text(None, Pattern::Contains("b".to_string()));
+        let c = || text(None, Pattern::Contains("c".to_string()));
+        assert_eq!(
+            parse("a OR b c").unwrap(),
+            Expr::Or(
+                Box::new(a()),
+                Box::new(Expr::And(Box::new(b()), Box::new(c())))
+            )
+        );
+        assert_eq!(
+            parse("(a OR b) AND NOT c").unwrap(),
+            Expr::And(
+                Box::new(Expr::Or(Box::new(a()), Box::new(b()))),
+                Box::new(Expr::Not(Box::new(c())))
+            )
+        );
+    }
+
+    #[test]
+    fn fields_patterns_and_comparisons() {
+        assert_eq!(
+            parse("artist:\"Taylor Swift\"").unwrap(),
+            text(
+                Some(TextField::Artist),
+                Pattern::Exact("taylor swift".to_string())
+            )
+        );
+        assert_eq!(
+            parse("Title:Lov*").unwrap(),
+            text(Some(TextField::Title), Pattern::Prefix("lov".to_string()))
+        );
+        assert_eq!(
+            parse("plays>=10").unwrap(),
+            Expr::Number(NumberField::Plays, Comparison::Ge, 10.0)
+        );
+        assert_eq!(
+            parse("rating != 2.5").unwrap(),
+            Expr::Number(NumberField::Rating, Comparison::Ne, 2.5)
+        );
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_at(""), ("empty query".to_string(), 0));
+        assert_eq!(
+            error_at("title:\"open"),
+            ("unterminated quoted phrase".to_string(), 6)
+        );
+        assert_eq!(
+            error_at("mood:happy"),
+            ("unknown field 'mood'".to_string(), 0)
+        );
+        assert_eq!(
+            error_at("genre:rock AND length>3"),
+            ("unknown numeric field 'length'".to_string(), 15)
+        );
+        assert_eq!(
+            error_at("plays>many"),
+            ("expected a number, found 'many'".to_string(), 6)
+        );
+        assert_eq!(error_at("plays>"), ("expected a number".to_string(), 6));
+        assert_eq!(error_at("(rock"), ("expected ')'".to_string(), 5));
+        assert_eq!(error_at("rock)"), ("unexpected ')'".to_string(), 4));
+        assert_eq!(
+            error_at("genre:rock AND ("),
+            ("unexpected end of query".to_string(), 16)
+        );
+        assert_eq!(error_at("a ! b"), ("expected '!='".to_string(), 2));
+        assert_eq!(
+            error_at("OR rock"),
+            ("expected a search term".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let ok = format!("{}rock", "NOT ".repeat(MAX_DEPTH));
+        assert!(parse(&ok).is_ok());
+
+        // Rejected before recursing any deeper, with the position of the
+        // first NOT past the limit
+        let deep = format!("{}rock", "NOT ".repeat(5000));
+        assert_eq!(
+            error_at(&deep),
+            (
+                "query is nested more than 64 levels deep".to_string(),
+                MAX_DEPTH * 4
+            )
+        );
+
+        let parens = format!("{}rock{}", "(".repeat(5000), ")".repeat(5000));
+        assert_eq!(error_at(&parens).1, MAX_DEPTH);
+    }
+
+    #[test]
+    fn terms_are_limited() {
+        assert!(parse(&"a ".repeat(MAX_TERMS)).is_ok());
+        assert_eq!(
+            error_at(&"a OR ".repeat(10_000)),
+            ("query has more than 256 terms".to_string(), MAX_TERMS * 5)
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let song = song("Love Story", "Taylor Swift", "Pop", 12);
+        let matches = |query: &str| parse(query).unwrap().matches(&song);
+
+        assert!(matches("artist:\"taylor swift\" AND plays>10"));
+        assert!(matches("sto*"));
+        assert!(matches("album:greatest"));
+        assert!(!matches("artist:\"taylor\""));
+        assert!(!matches("NOT genre:pop"));
+        assert!(matches("genre:rock OR genre:pop"));
+        // Unrated songs never satisfy a rating comparison
+        assert!(!matches("rating<5"));
+        assert!(!matches("rating>=0"));
+    }
+}
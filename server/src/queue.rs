@@ -0,0 +1,589 @@
+use crate::{AppState, ErrorMessage, Song, SongSearchQuery, record_play, search_songs};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    One,
+    All,
+}
+
+// A listening session's queue. `items` is the play order (shuffled when
+// shuffle is on) and `original` the order songs were enqueued in. Queues
+// live in memory only; idle ones expire after QUEUE_TTL and at most
+// MAX_QUEUES are kept.
+#[derive(Debug, Clone)]
+pub struct PlayQueue {
+    items: Vec<u64>,
+    original: Vec<u64>,
+    position: Option<usize>,
+    shuffle: bool,
+    repeat: RepeatMode,
+    last_used: Instant,
+}
+
+// Structure for receiving songs to enqueue from POST JSON: explicit ids,
+// the results of a search, or both
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct EnqueueRequest {
+    #[serde(default)]
+    song_ids: Vec<u64>,
+    search: Option<SongSearchQuery>,
+}
+
+// Structure for receiving playback mode changes from POST JSON
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct QueueModeRequest {
+    shuffle: Option<bool>,
+    repeat: Option<RepeatMode>,
+}
+
+// A queue as returned by the queue endpoints
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueueView {
+    id: String,
+    songs: Vec<Song>,
+    // Index of `current` in `songs`
+    position: Option<usize>,
+    current: Option<Song>,
+    shuffle: bool,
+    repeat: RepeatMode,
+}
+
+fn error(status: StatusCode, error: &'static str) -> Response {
+    (status, Json(ErrorMessage { error })).into_response()
+}
+
+fn queue_not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "Queue not found")
+}
+
+impl PlayQueue {
+    fn new() -> PlayQueue {
+        PlayQueue {
+            items: Vec::new(),
+            original: Vec::new(),
+            position: None,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            last_used: Instant::now(),
+        }
+    }
+
+    fn current(&self) -> Option<u64> {
+        self.position.and_then(|p| self.items.get(p).copied())
+    }
+
+    fn enqueue(&mut self, ids: &[u64]) {
+        self.original.extend_from_slice(ids);
+
+        let start = self.items.len();
+        self.items.extend_from_slice(ids);
+        if self.shuffle {
+            self.items[start..].shuffle(&mut rand::thread_rng());
+        }
+    }
+
+    // Shuffle everything after the current song, or restore the enqueue
+    // order while keeping the current song current
+    fn set_shuffle(&mut self, shuffle: bool) {
+        if shuffle == self.shuffle {
+            return;
+        }
+        self.shuffle = shuffle;
+
+        let current = self.current();
+        if shuffle {
+            let rest = self.position.map_or(0, |p| p + 1);
+            self.items[rest..].shuffle(&mut rand::thread_rng());
+        } else {
+            self.items = self.original.clone();
+            self.position = current.and_then(|id| self.items.iter().position(|&i| i == id));
+        }
+    }
+
+    // Move to the song after the current one, honouring the repeat mode.
+    // `manual` is true for skips, which never stay on the same song.
+    fn advance(&mut self, manual: bool) {
+        if self.items.is_empty() {
+            self.position = None;
+            return;
+        }
+
+        self.position = match (self.position, self.repeat) {
+            (None, _) => Some(0),
+            (Some(p), RepeatMode::One) if !manual => Some(p),
+            (Some(p), _) if p + 1 < self.items.len() => Some(p + 1),
+            (Some(_), RepeatMode::All | RepeatMode::One) => {
+                // Reshuffle for the next pass through the queue
+                if self.shuffle {
+                    self.items.shuffle(&mut rand::thread_rng());
+                }
+                Some(0)
+            }
+            (Some(_), RepeatMode::Off) => None,
+        };
+    }
+
+    fn back(&mut self) {
+        self.position = match self.position {
+            Some(p) if p > 0 => Some(p - 1),
+            Some(_) if self.repeat == RepeatMode::All => self.items.len().checked_sub(1),
+            Some(p) => Some(p),
+            None => self.items.len().checked_sub(1),
+        };
+    }
+}
+
+// The queue's songs as they are now. Songs deleted since they were enqueued
+// are left out, so `position` is recomputed as the index of the current song
+// among the songs that remain (`None` if the current song was deleted).
+fn view(state: &AppState, id: &str, queue: &PlayQueue) -> QueueView {
+    let library = state.songs.read();
+
+    let mut songs = Vec::new();
+    let mut position = None;
+    for (i, &song_id) in queue.items.iter().enumerate() {
+        let Some(song) = library.get(song_id) else {
+            continue;
+        };
+        if queue.position == Some(i) {
+            position = Some(songs.len());
+        }
+        songs.push(song.song());
+    }
+
+    QueueView {
+        id: id.to_string(),
+        current: position.map(|p| songs[p].clone()),
+        songs,
+        position,
+        shuffle: queue.shuffle,
+        repeat: queue.repeat,
+    }
+}
+
+fn expired(state: &AppState, queue: &PlayQueue) -> bool {
+    queue.last_used.elapsed() >= state.config.queue_ttl
+}
+
+// Drop queues that have not been used within QUEUE_TTL, returning how many
+// were dropped
+pub fn expire(state: &AppState) -> usize {
+    let mut queues = state.queues.lock();
+    let before = queues.len();
+    queues.retain(|_, queue| !expired(state, queue));
+    before - queues.len()
+}
+
+// Apply `update` to a queue and return its new state. When `play` is set,
+// the song that was current before the update is counted as played through
+// the same logic as GET /songs/play/:id.
+fn update_queue(
+    state: &AppState,
+    id: &str,
+    play: bool,
+    update: impl FnOnce(&mut PlayQueue),
+) -> Response {
+    let (played, queue) = {
+        let mut queues = state.queues.lock();
+        let Some(queue) = queues.get_mut(id) else {
+            return queue_not_found();
+        };
+        if expired(state, queue) {
+            queues.remove(id);
+            return queue_not_found();
+        }
+        queue.last_used = Instant::now();
+        let played = queue.current();
+        update(queue);
+        (played, queue.clone())
+    };
+
+    if play && let Some(song_id) = played {
+        record_play(state, song_id);
+    }
+
+    Json(view(state, id, &queue)).into_response()
+}
+
+// Start a new listening session with an empty queue. When MAX_QUEUES are in
+// use, the least recently used queue is dropped to make room.
+#[utoipa::path(
+    post,
+    path = "/queue",
+    tag = "queue",
+    responses((status = 200, description = "The new queue", body = QueueView))
+)]
+pub async fn handle_queue_create(State(state): State<Arc<AppState>>) -> Json<QueueView> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let queue = PlayQueue::new();
+    {
+        let mut queues = state.queues.lock();
+        queues.retain(|_, queue| !expired(&state, queue));
+        while queues.len() >= state.config.max_queues {
+            let Some(oldest) = queues
+                .iter()
+                .min_by_key(|(_, queue)| queue.last_used)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            queues.remove(&oldest);
+            info!(id = oldest, "dropped the least recently used queue");
+        }
+        queues.insert(id.clone(), queue.clone());
+    }
+    Json(view(&state, &id, &queue))
+}
+
+// Show a queue
+#[utoipa::path(
+    get,
+    path = "/queue/{id}",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    responses(
+        (status = 200, description = "The queue", body = QueueView),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_get(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    update_queue(&state, &id, false, |_| {})
+}
+
+// End a listening session
+#[utoipa::path(
+    delete,
+    path = "/queue/{id}",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    responses(
+        (status = 204, description = "The queue was deleted"),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_delete(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    match state.queues.lock().remove(&id) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => queue_not_found(),
+    }
+}
+
+// Add songs, or every result of a search, to the end of a queue
+#[utoipa::path(
+    post,
+    path = "/queue/{id}/enqueue",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    request_body = EnqueueRequest,
+    responses(
+        (status = 200, description = "The updated queue", body = QueueView),
+        (status = 400, description = "Invalid search query", body = QueryError),
+        (status = 404, description = "No queue or song with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_enqueue(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(payload): Json<EnqueueRequest>,
+) -> Response {
+    let ids = {
+        let songs = state.songs.read();
+
+        let mut ids = Vec::new();
+        for &song_id in &payload.song_ids {
+            match songs.get(song_id) {
+                Some(song) => ids.push(song.id),
+                None => return error(StatusCode::NOT_FOUND, "Song not found"),
+            }
+        }
+
+        if let Some(search) = &payload.search {
+            match search_songs(&songs, search) {
+                Ok(results) => ids.extend(results.iter().map(|s| s.id)),
+                Err(e) => return (StatusCode::BAD_REQUEST, Json(e)).into_response(),
+            }
+        }
+        ids
+    };
+
+    update_queue(&state, &id, false, |queue| queue.enqueue(&ids))
+}
+
+// The current song finished: count it as played and move to the next one
+#[utoipa::path(
+    post,
+    path = "/queue/{id}/next",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    responses(
+        (status = 200, description = "The queue with the next song current", body = QueueView),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_next(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    update_queue(&state, &id, true, |queue| queue.advance(false))
+}
+
+// Skip the current song without counting a play
+#[utoipa::path(
+    post,
+    path = "/queue/{id}/skip",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    responses(
+        (status = 200, description = "The queue with the next song current", body = QueueView),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_skip(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    update_queue(&state, &id, false, |queue| queue.advance(true))
+}
+
+// Go back to the previous song without counting a play
+#[utoipa::path(
+    post,
+    path = "/queue/{id}/previous",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    responses(
+        (status = 200, description = "The queue with the previous song current", body = QueueView),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_previous(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Response {
+    update_queue(&state, &id, false, PlayQueue::back)
+}
+
+// Turn shuffle on or off and set the repeat mode
+#[utoipa::path(
+    post,
+    path = "/queue/{id}/mode",
+    tag = "queue",
+    params(("id" = String, Path, description = "Queue id")),
+    request_body = QueueModeRequest,
+    responses(
+        (status = 200, description = "The updated queue", body = QueueView),
+        (status = 404, description = "No queue with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_queue_mode(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(payload): Json<QueueModeRequest>,
+) -> Response {
+    update_queue(&state, &id, false, |queue| {
+        if let Some(shuffle) = payload.shuffle {
+            queue.set_shuffle(shuffle);
+        }
+        if let Some(repeat) = payload.repeat {
+            queue.repeat = repeat;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, NewSongRequest, add_song, load_state};
+    use axum::body::to_bytes;
+    use std::time::Duration;
+
+    fn queue(ids: &[u64]) -> PlayQueue {
+        let mut queue = PlayQueue::new();
+        queue.enqueue(ids);
+        queue
+    }
+
+    fn state(dir: &std::path::Path, config: Config) -> Arc<AppState> {
+        load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..config
+        })
+        .0
+    }
+
+    async fn read(response: Response) -> (StatusCode, serde_json::Value) {
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn advance_and_skip_follow_the_repeat_mode() {
+        let mut q = queue(&[1, 2]);
+        q.advance(false);
+        assert_eq!(q.current(), Some(1));
+        q.advance(false);
+        assert_eq!(q.current(), Some(2));
+        q.advance(false);
+        assert_eq!(q.current(), None);
+
+        // Repeat one stays on the song when it ends, but a skip moves on
+        q.repeat = RepeatMode::One;
+        q.position = Some(0);
+        q.advance(false);
+        assert_eq!(q.current(), Some(1));
+        q.advance(true);
+        assert_eq!(q.current(), Some(2));
+        q.advance(true);
+        assert_eq!(q.current(), Some(1));
+
+        // Repeat all wraps around
+        q.repeat = RepeatMode::All;
+        q.position = Some(1);
+        q.advance(false);
+        assert_eq!(q.current(), Some(1));
+
+        let mut empty = PlayQueue::new();
+        empty.advance(false);
+        assert_eq!(empty.current(), None);
+    }
+
+    #[test]
+    fn previous_stops_at_the_start_unless_repeating_all() {
+        let mut q = queue(&[1, 2, 3]);
+        q.back();
+        assert_eq!(q.current(), Some(3));
+        q.position = Some(0);
+        q.back();
+        assert_eq!(q.current(), Some(1));
+        q.repeat = RepeatMode::All;
+        q.back();
+        assert_eq!(q.current(), Some(3));
+        q.back();
+        assert_eq!(q.current(), Some(2));
+    }
+
+    #[test]
+    fn shuffle_keeps_the_current_song_and_unshuffle_restores_the_order() {
+        let ids: Vec<u64> = (1..=50).collect();
+        let mut q = queue(&ids);
+        q.advance(false);
+        q.advance(false);
+        assert_eq!(q.current(), Some(2));
+
+        q.set_shuffle(true);
+        assert_eq!(q.current(), Some(2));
+        assert_eq!(q.items[..2], [1, 2]);
+        let mut sorted = q.items.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, ids);
+
+        q.enqueue(&[51]);
+        assert_eq!(q.original.last(), Some(&51));
+
+        q.set_shuffle(false);
+        assert_eq!(q.items, (1..=51).collect::<Vec<u64>>());
+        assert_eq!(q.current(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn position_skips_deleted_songs() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path(), Config::default());
+        let ids: Vec<u64> = ["A", "B", "C"]
+            .iter()
+            .map(|title| {
+                let song = NewSongRequest {
+                    title: title.to_string(),
+                    artist: "Queuer".to_string(),
+                    genre: "Pop".to_string(),
+                    album: None,
+                };
+                add_song(&state, "alice", song).id
+            })
+            .collect();
+
+        let mut q = queue(&ids);
+        q.position = Some(2);
+        state.songs.write().remove(ids[0]);
+        let shown = view(&state, "q", &q);
+        assert_eq!(shown.songs.len(), 2);
+        assert_eq!(shown.position, Some(1));
+        assert_eq!(shown.current.unwrap().title, "C");
+
+        // The current song itself was deleted
+        q.position = Some(0);
+        let shown = view(&state, "q", &q);
+        assert_eq!(shown.position, None);
+        assert!(shown.current.is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_queues_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            queue_ttl: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let state = state(dir.path(), config);
+
+        let Json(created) = handle_queue_create(State(state.clone())).await;
+        let (status, _) =
+            read(handle_queue_get(State(state.clone()), Path(created.id.clone())).await).await;
+        assert_eq!(status, StatusCode::OK);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (status, _) =
+            read(handle_queue_get(State(state.clone()), Path(created.id)).await).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(state.queues.lock().is_empty());
+
+        let _ = handle_queue_create(State(state.clone())).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(expire(&state), 1);
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_queue_makes_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            max_queues: 2,
+            ..Default::default()
+        };
+        let state = state(dir.path(), config);
+
+        let Json(first) = handle_queue_create(State(state.clone())).await;
+        let Json(second) = handle_queue_create(State(state.clone())).await;
+        // Using the first queue makes the second the least recently used
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        handle_queue_get(State(state.clone()), Path(first.id.clone())).await;
+        let Json(third) = handle_queue_create(State(state.clone())).await;
+
+        let queues = state.queues.lock();
+        assert_eq!(queues.len(), 2);
+        assert!(queues.contains_key(&first.id));
+        assert!(!queues.contains_key(&second.id));
+        assert!(queues.contains_key(&third.id));
+    }
+}
@@ -0,0 +1,588 @@
+use crate::audit::{self, Actor};
+use crate::library::LibrarySong;
+use crate::negotiate::{Format, Negotiated};
+use crate::{AppState, ErrorMessage, Song, now_secs, save_json, save_songs};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+pub const RATINGS_FILE: &str = "ratings.json";
+
+// One user's rating, review and favourite flag for a song
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct UserRating {
+    song_id: u64,
+    user: String,
+    rating: Option<u8>,
+    review: Option<String>,
+    favourite: bool,
+    updated_at: u64,
+}
+
+// Structure for receiving a rating (and optional review) from POST JSON. The
+// rater is the `X-User` of the request.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RatingRequest {
+    rating: u8,
+    review: Option<String>,
+}
+
+// Structure for receiving a favourite toggle from POST JSON, for the
+// request's `X-User`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FavouriteRequest {
+    #[serde(default = "default_favourite")]
+    favourite: bool,
+}
+
+fn default_favourite() -> bool {
+    true
+}
+
+// A review as returned by GET /songs/:id/reviews
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Review {
+    user: String,
+    rating: Option<u8>,
+    review: Option<String>,
+    updated_at: u64,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Recompute the average rating and favourite count stored on a song
+pub fn refresh_song_stats(song: &mut Song, ratings: &[UserRating]) {
+    let mut sum = 0u64;
+    let mut count = 0u64;
+    let mut favourites = 0u64;
+
+    for entry in ratings.iter().filter(|r| r.song_id == song.id) {
+        if let Some(rating) = entry.rating {
+            sum += rating as u64;
+            count += 1;
+        }
+        if entry.favourite {
+            favourites += 1;
+        }
+    }
+
+    song.average_rating = if count == 0 {
+        None
+    } else {
+        Some((sum as f64 / count as f64 * 100.0).round() / 100.0)
+    };
+    song.rating_count = count;
+    song.favourite_count = favourites;
+}
+
+// Move ratings from a merged song onto the song it was merged into. When a
+// user rated both, the newer rating wins and either favourite flag is kept.
+pub fn reassign(ratings: &mut Vec<UserRating>, from: u64, to: u64) {
+    let moved: Vec<UserRating> = ratings
+        .iter()
+        .filter(|r| r.song_id == from)
+        .cloned()
+        .collect();
+    ratings.retain(|r| r.song_id != from);
+
+    for mut entry in moved {
+        entry.song_id = to;
+        match ratings
+            .iter_mut()
+            .find(|r| r.song_id == to && r.user == entry.user)
+        {
+            Some(existing) => {
+                if entry.rating.is_some()
+                    && (existing.rating.is_none() || entry.updated_at > existing.updated_at)
+                {
+                    existing.rating = entry.rating;
+                    existing.review = entry.review;
+                }
+                existing.favourite |= entry.favourite;
+                existing.updated_at = existing.updated_at.max(entry.updated_at);
+            }
+            None => ratings.push(entry),
+        }
+    }
+}
+
+// Ratings of the given songs
+pub fn of_songs(ratings: &[UserRating], song_ids: &[u64]) -> Vec<UserRating> {
+    ratings
+        .iter()
+        .filter(|r| song_ids.contains(&r.song_id))
+        .cloned()
+        .collect()
+}
+
+// Put back the ratings of the given songs as they were (`before`), e.g. when
+// a merge is undone. Ratings changed after `since` (seconds since the Unix
+// epoch) are newer than the snapshot and are kept.
+pub fn restore(ratings: &mut Vec<UserRating>, song_ids: &[u64], before: &[UserRating], since: u64) {
+    ratings.retain(|r| !song_ids.contains(&r.song_id) || r.updated_at > since);
+    for entry in before {
+        if !ratings
+            .iter()
+            .any(|r| r.song_id == entry.song_id && r.user == entry.user)
+        {
+            ratings.push(entry.clone());
+        }
+    }
+}
+
+// Apply `update` to the user's entry for a song (creating it if needed),
+// then refresh that song's aggregates, persist both files and record the
+// change as `action`
+fn update_rating(
+    state: &AppState,
+    id: u64,
+    user: &str,
+    action: &str,
+    update: impl FnOnce(&mut UserRating),
+) -> Result<Song, ApiError> {
+    // Lock order: ratings before songs
+    let mut ratings = state.ratings.write();
+    let mut songs = state.songs.write();
+
+    let Some(song) = songs.get_mut(id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Song not found"));
+    };
+    // `id` may have been merged into another song
+    let id = song.id;
+    let before = song.song();
+    let ratings_before = of_songs(&ratings, &[id]);
+
+    let idx = match ratings
+        .iter()
+        .position(|r| r.song_id == id && r.user == user)
+    {
+        Some(idx) => idx,
+        None => {
+            ratings.push(UserRating {
+                song_id: id,
+                user: user.to_string(),
+                rating: None,
+                review: None,
+                favourite: false,
+                updated_at: 0,
+            });
+            ratings.len() - 1
+        }
+    };
+    update(&mut ratings[idx]);
+    ratings[idx].updated_at = now_secs();
+
+    refresh_song_stats(song, &ratings);
+    let song = song.song();
+
+    save_json(&state.persistence, RATINGS_FILE, &*ratings);
+    save_songs(&state.persistence, &mut songs);
+    audit::record_with_ratings(
+        state,
+        user,
+        action,
+        audit::change(Some(&before), Some(&song)),
+        ratings_before,
+    );
+
+    Ok(song)
+}
+
+// Rate a song from 1 to 5, optionally with a written review
+#[utoipa::path(
+    post,
+    path = "/songs/{id}/rating",
+    tag = "songs",
+    params(("id" = u64, Path, description = "Song id")),
+    request_body = RatingRequest,
+    responses(
+        (status = 200, description = "The song with its updated rating", body = Song),
+        (status = 400, description = "Rating not between 1 and 5", body = ErrorMessage),
+        (status = 404, description = "No song with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_songs_rate(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Actor(user): Actor,
+    Json(payload): Json<RatingRequest>,
+) -> Result<Json<Song>, ApiError> {
+    if !(1..=5).contains(&payload.rating) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "Rating must be between 1 and 5",
+        ));
+    }
+
+    let review = payload
+        .review
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+
+    update_rating(&state, id, &user, "rate", |entry| {
+        entry.rating = Some(payload.rating);
+        entry.review = review;
+    })
+    .map(Json)
+}
+
+// Mark (or unmark) a song as one of the user's favourites
+#[utoipa::path(
+    post,
+    path = "/songs/{id}/favourite",
+    tag = "songs",
+    params(("id" = u64, Path, description = "Song id")),
+    request_body = FavouriteRequest,
+    responses(
+        (status = 200, description = "The song with its updated favourite count", body = Song),
+        (status = 404, description = "No song with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_songs_favourite(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    Actor(user): Actor,
+    Json(payload): Json<FavouriteRequest>,
+) -> Result<Json<Song>, ApiError> {
+    update_rating(&state, id, &user, "favourite", |entry| {
+        entry.favourite = payload.favourite;
+    })
+    .map(Json)
+}
+
+// List the ratings and reviews left on a song, newest first
+#[utoipa::path(
+    get,
+    path = "/songs/{id}/reviews",
+    tag = "songs",
+    params(("id" = u64, Path, description = "Song id")),
+    responses(
+        (status = 200, description = "Ratings and reviews, newest first", body = [Review], content_type = ["application/json", "text/csv", "application/msgpack"]),
+        (status = 404, description = "No song with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_songs_reviews(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    format: Format,
+) -> Result<Negotiated<Vec<Review>>, ApiError> {
+    let Some(id) = state.songs.read().get(id).map(|s| s.id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Song not found"));
+    };
+
+    let ratings = state.ratings.read();
+    let mut reviews: Vec<Review> = ratings
+        .iter()
+        .filter(|r| r.song_id == id && r.rating.is_some())
+        .map(|r| Review {
+            user: r.user.clone(),
+            rating: r.rating,
+            review: r.review.clone(),
+            updated_at: r.updated_at,
+        })
+        .collect();
+    reviews.sort_by_key(|r| std::cmp::Reverse(r.updated_at));
+
+    Ok(Negotiated(format, reviews))
+}
+
+// List the songs a user has marked as favourite
+#[utoipa::path(
+    get,
+    path = "/users/{user}/favourites",
+    tag = "songs",
+    params(("user" = String, Path, description = "User name, as sent in `X-User`")),
+    responses(
+        (status = 200, description = "The user's favourite songs", body = [Song], content_type = ["application/json", "text/csv", "application/msgpack"]),
+    )
+)]
+pub async fn handle_user_favourites(
+    State(state): State<Arc<AppState>>,
+    Path(user): Path<String>,
+    format: Format,
+) -> Negotiated<Vec<Song>> {
+    let ratings = state.ratings.read();
+    let songs = state.songs.read();
+
+    let favourites = songs
+        .iter()
+        .filter(|song| {
+            ratings
+                .iter()
+                .any(|r| r.song_id == song.id && r.user == user && r.favourite)
+        })
+        .map(LibrarySong::song)
+        .collect();
+
+    Negotiated(format, favourites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Config, NewSongRequest, SongSearchQuery, SongSort, add_song, load_state, search_songs,
+    };
+    use serde_json::json;
+
+    fn setup(dir: &std::path::Path) -> Arc<AppState> {
+        let (state, _receiver) = load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        });
+        state
+    }
+
+    fn add(state: &AppState, title: &str) -> Song {
+        let song = NewSongRequest {
+            title: title.to_string(),
+            artist: "Rater".to_string(),
+            genre: "Pop".to_string(),
+            album: None,
+        };
+        add_song(state, "alice", song)
+    }
+
+    async fn rate(
+        state: &Arc<AppState>,
+        id: u64,
+        user: &str,
+        body: serde_json::Value,
+    ) -> Result<Song, ApiError> {
+        let request = serde_json::from_value(body).unwrap();
+        let actor = Actor(user.to_string());
+        handle_songs_rate(State(state.clone()), Path(id), actor, Json(request))
+            .await
+            .map(|Json(song)| song)
+    }
+
+    async fn favourite(
+        state: &Arc<AppState>,
+        id: u64,
+        user: &str,
+        body: serde_json::Value,
+    ) -> Song {
+        let request = serde_json::from_value(body).unwrap();
+        let actor = Actor(user.to_string());
+        let Json(song) =
+            handle_songs_favourite(State(state.clone()), Path(id), actor, Json(request))
+                .await
+                .unwrap();
+        song
+    }
+
+    #[tokio::test]
+    async fn rating_again_replaces_the_users_rating() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+        let song = add(&state, "Rated");
+
+        let rated = rate(
+            &state,
+            song.id,
+            "alice",
+            json!({"rating": 2, "review": "meh"}),
+        )
+        .await
+        .unwrap();
+        assert_eq!((rated.average_rating, rated.rating_count), (Some(2.0), 1));
+
+        let rated = rate(&state, song.id, "bob", json!({"rating": 5}))
+            .await
+            .unwrap();
+        assert_eq!((rated.average_rating, rated.rating_count), (Some(3.5), 2));
+
+        // Alice changes her mind; a blank review clears the old one
+        let rated = rate(
+            &state,
+            song.id,
+            "alice",
+            json!({"rating": 4, "review": "  "}),
+        )
+        .await
+        .unwrap();
+        assert_eq!((rated.average_rating, rated.rating_count), (Some(4.5), 2));
+        assert_eq!(state.ratings.read().len(), 2);
+
+        let Negotiated(_, reviews) =
+            handle_songs_reviews(State(state.clone()), Path(song.id), Format::Json)
+                .await
+                .unwrap();
+        let reviews: Vec<(String, Option<u8>, Option<String>)> = reviews
+            .into_iter()
+            .map(|r| (r.user, r.rating, r.review))
+            .collect();
+        assert_eq!(reviews.len(), 2);
+        assert!(reviews.contains(&("alice".to_string(), Some(4), None)));
+
+        // The aggregates are saved with the song
+        let saved: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(state.persistence.path(crate::SONGS_FILE)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved[0]["average_rating"], 4.5);
+        assert_eq!(saved[0]["rating_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_ratings_and_unknown_songs_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+        let song = add(&state, "Rated");
+
+        for rating in [0, 6] {
+            let result = rate(&state, song.id, "alice", json!({ "rating": rating })).await;
+            assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+        }
+        let result = rate(&state, 99, "alice", json!({"rating": 3})).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::NOT_FOUND);
+        assert!(state.ratings.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn favourites_toggle_per_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+        let song = add(&state, "Loved");
+        add(&state, "Other");
+
+        assert_eq!(
+            favourite(&state, song.id, "alice", json!({}))
+                .await
+                .favourite_count,
+            1
+        );
+        assert_eq!(
+            favourite(&state, song.id, "bob", json!({}))
+                .await
+                .favourite_count,
+            2
+        );
+        let unmarked = favourite(&state, song.id, "bob", json!({"favourite": false})).await;
+        assert_eq!(unmarked.favourite_count, 1);
+        // A favourite alone is not a rating
+        assert_eq!((unmarked.average_rating, unmarked.rating_count), (None, 0));
+
+        let list = |user: &str| {
+            let state = state.clone();
+            let user = user.to_string();
+            async move {
+                let Negotiated(_, songs) =
+                    handle_user_favourites(State(state), Path(user), Format::Json).await;
+                songs.into_iter().map(|s| s.id).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(list("alice").await, [song.id]);
+        assert!(list("bob").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_sorts_by_rating_with_unrated_songs_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+        let low = add(&state, "Low");
+        let unrated = add(&state, "Unrated");
+        let high = add(&state, "High");
+        rate(&state, low.id, "alice", json!({"rating": 2}))
+            .await
+            .unwrap();
+        rate(&state, high.id, "alice", json!({"rating": 5}))
+            .await
+            .unwrap();
+
+        let sorted = |order: serde_json::Value| {
+            let mut query: SongSearchQuery =
+                serde_json::from_value(json!({ "order": order })).unwrap();
+            query.sort = Some(SongSort::Rating);
+            search_songs(&state.songs.read(), &query)
+                .unwrap()
+                .into_iter()
+                .map(|s| s.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(json!(null)), [high.id, low.id, unrated.id]);
+        assert_eq!(sorted(json!("asc")), [low.id, high.id, unrated.id]);
+    }
+
+    #[tokio::test]
+    async fn ratings_are_audited_and_can_be_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+        let song = add(&state, "Rated");
+
+        favourite(&state, song.id, "bob", json!({"favourite": true})).await;
+        rate(
+            &state,
+            song.id,
+            "alice",
+            json!({"rating": 3, "review": "ok"}),
+        )
+        .await
+        .unwrap();
+        // Only the review changes, the averages stay as they were
+        rate(
+            &state,
+            song.id,
+            "alice",
+            json!({"rating": 3, "review": "great"}),
+        )
+        .await
+        .unwrap();
+
+        let recorded: Vec<(u64, String, String)> = state
+            .audit
+            .read()
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .map(|e| {
+                let text = |field: &str| e[field].as_str().unwrap().to_string();
+                (e["id"].as_u64().unwrap(), text("action"), text("user"))
+            })
+            .collect();
+        let actions: Vec<(&str, &str)> = recorded
+            .iter()
+            .map(|(_, action, user)| (action.as_str(), user.as_str()))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("create", "alice"),
+                ("favourite", "bob"),
+                ("rate", "alice"),
+                ("rate", "alice"),
+            ]
+        );
+
+        let undo = |id: u64| {
+            audit::handle_audit_undo(State(state.clone()), Path(id), Actor("alice".to_string()))
+        };
+        let review = || {
+            state
+                .ratings
+                .read()
+                .iter()
+                .find(|r| r.user == "alice")
+                .map(|r| (r.rating, r.review.clone()))
+        };
+
+        assert!(undo(recorded[3].0).await.is_ok());
+        assert_eq!(review(), Some((Some(3), Some("ok".to_string()))));
+
+        // Undoing the first rating removes it; Bob's favourite stays
+        assert!(undo(recorded[2].0).await.is_ok());
+        assert_eq!(review(), None);
+        let songs = state.songs.read();
+        let undone = songs.get(song.id).unwrap();
+        assert_eq!(undone.average_rating, None);
+        assert_eq!(undone.favourite_count, 1);
+    }
+}
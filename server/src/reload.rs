@@ -0,0 +1,572 @@
+use crate::audit;
+use crate::library::FileStamp;
+use crate::ratings::refresh_song_stats;
+use crate::{AppState, SONGS_FILE, Song, save_songs};
+use axum::{Json, extract::State};
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+// A song that was changed both in memory and in songs.json since the last
+// sync in ways that could not be combined. The file's values are kept.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReloadConflict {
+    song_id: u64,
+    ours: Option<Song>,
+    theirs: Option<Song>,
+}
+
+// Outcome of the most recent attempt to reload songs.json
+#[derive(Debug, Default, Clone, Serialize, ToSchema)]
+pub struct ReloadStatus {
+    // When songs.json was last reloaded
+    reloaded_at: Option<String>,
+    // Songs added, changed or removed by the last reload
+    changed: usize,
+    // In-memory changes that were merged into the file and saved
+    merged: usize,
+    conflicts: Vec<ReloadConflict>,
+    // Why the current file was rejected, if it was
+    error: Option<String>,
+    #[serde(skip)]
+    rejected: Option<FileStamp>,
+}
+
+// Poll songs.json for edits made outside the server
+pub async fn watch(state: Arc<AppState>) {
+    let secs = state.config.reload_interval;
+    if secs == 0 {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        let _ = tokio::task::spawn_blocking(move || reload(&state, false)).await;
+    }
+}
+
+// Parse and check an edited songs.json
+fn validate(data: &str) -> Result<Vec<Song>, String> {
+    let songs: Vec<Song> =
+        serde_json::from_str(data).map_err(|e| format!("invalid songs.json: {}", e))?;
+
+    let mut ids = HashSet::new();
+    let mut uids = HashSet::new();
+    for song in &songs {
+        if song.id == 0 {
+            return Err("invalid songs.json: song id 0 is not allowed".to_string());
+        }
+        if !ids.insert(song.id) {
+            return Err(format!("invalid songs.json: duplicate song id {}", song.id));
+        }
+        if let Some(uid) = &song.uid
+            && !uids.insert(uid.as_str())
+        {
+            return Err(format!("invalid songs.json: duplicate uid {}", uid));
+        }
+    }
+
+    Ok(songs)
+}
+
+#[derive(Default)]
+struct Merge {
+    songs: Vec<Song>,
+    changed: usize,
+    merged: usize,
+    conflicts: Vec<ReloadConflict>,
+}
+
+// Three-way merge of one value: whichever side changed it wins, and the
+// file wins when both changed it differently
+fn pick<T: Clone + PartialEq>(base: &T, ours: &T, theirs: &T, conflict: &mut bool) -> T {
+    if ours == base || ours == theirs {
+        theirs.clone()
+    } else if theirs == base {
+        ours.clone()
+    } else {
+        *conflict = true;
+        theirs.clone()
+    }
+}
+
+// Merge a song changed both in memory and in the file, e.g. played while its
+// title was being fixed by hand. Returns the song and whether any field was
+// changed differently on both sides. Rating stats are recomputed afterwards.
+fn merge_fields(base: &Song, ours: &Song, theirs: &Song) -> (Song, bool) {
+    let mut conflict = false;
+    let song = Song {
+        uid: pick(&base.uid, &ours.uid, &theirs.uid, &mut conflict),
+        title: pick(&base.title, &ours.title, &theirs.title, &mut conflict),
+        artist: pick(&base.artist, &ours.artist, &theirs.artist, &mut conflict),
+        album: pick(&base.album, &ours.album, &theirs.album, &mut conflict),
+        genre: pick(&base.genre, &ours.genre, &theirs.genre, &mut conflict),
+        play_count: pick(
+            &base.play_count,
+            &ours.play_count,
+            &theirs.play_count,
+            &mut conflict,
+        ),
+        file: pick(&base.file, &ours.file, &theirs.file, &mut conflict),
+        ..theirs.clone()
+    };
+    (song, conflict)
+}
+
+// Three-way merge of the file (`theirs`) and the in-memory songs (`ours`)
+// against the songs as of the last sync. A song changed on only one side
+// takes that side's version; a song changed on both sides is merged field by
+// field. The file's order is kept, with
+// songs created in memory appended.
+fn merge(base: &[Song], ours: &[Song], theirs: Vec<Song>) -> Merge {
+    let base: BTreeMap<u64, &Song> = base.iter().map(|s| (s.id, s)).collect();
+    let ours_by_id: BTreeMap<u64, &Song> = ours.iter().map(|s| (s.id, s)).collect();
+    let theirs_ids: HashSet<u64> = theirs.iter().map(|s| s.id).collect();
+    let mut result = Merge::default();
+
+    for mut song in theirs {
+        let b = base.get(&song.id).copied();
+        let o = ours_by_id.get(&song.id).copied();
+
+        // Hand-edited files may drop generated public ids
+        if song.uid.is_none() {
+            song.uid = o.and_then(|o| o.uid.clone());
+        }
+
+        if o == b {
+            if b != Some(&song) {
+                result.changed += 1;
+            }
+            result.songs.push(song);
+        } else if o == Some(&song) {
+            // Both sides made the same change
+            result.songs.push(song);
+        } else if b == Some(&song) {
+            // Only changed (or removed) in memory
+            result.merged += 1;
+            result.songs.extend(o.cloned());
+        } else if let (Some(b), Some(o)) = (b, o) {
+            // Changed on both sides: combine them field by field
+            result.changed += 1;
+            let (merged, conflict) = merge_fields(b, o, &song);
+            if conflict {
+                result.conflicts.push(ReloadConflict {
+                    song_id: song.id,
+                    ours: Some(o.clone()),
+                    theirs: Some(song.clone()),
+                });
+            }
+            if merged != song {
+                result.merged += 1;
+            }
+            result.songs.push(merged);
+        } else {
+            // Added both in memory and in the file under the same id
+            result.changed += 1;
+            result.conflicts.push(ReloadConflict {
+                song_id: song.id,
+                ours: o.cloned(),
+                theirs: Some(song.clone()),
+            });
+            result.songs.push(song);
+        }
+    }
+
+    for song in ours.iter().filter(|s| !theirs_ids.contains(&s.id)) {
+        match base.get(&song.id) {
+            // Created in memory since the last sync
+            None => {
+                result.merged += 1;
+                result.songs.push(song.clone());
+            }
+            // Removed from the file
+            Some(&b) if b == song => result.changed += 1,
+            // Removed from the file but changed in memory
+            Some(_) => {
+                result.changed += 1;
+                result.conflicts.push(ReloadConflict {
+                    song_id: song.id,
+                    ours: Some(song.clone()),
+                    theirs: None,
+                });
+            }
+        }
+    }
+
+    result
+}
+
+// Reload songs.json if it changed since the server last read or wrote it.
+// An invalid file is reported once (also as a load error in /readyz) and
+// left alone; saves stay deferred until it is fixed. `force` re-checks a file
+// that was already rejected.
+pub fn reload(state: &AppState, force: bool) {
+    let path = state.persistence.path(SONGS_FILE);
+    let stamp = FileStamp::of(&path);
+    if stamp == state.songs.read().stamp() {
+        return;
+    }
+    if !force && stamp.is_some() && stamp == state.reload.lock().rejected {
+        return;
+    }
+
+    // A deleted file is written back from memory rather than emptying the library
+    if stamp.is_none() {
+        warn!("songs.json was removed; writing it back");
+        let mut songs = state.songs.write();
+        songs.mark_synced(None);
+        save_songs(&state.persistence, &mut songs);
+        return;
+    }
+
+    let parsed = fs::read_to_string(&path)
+        .map_err(|e| format!("cannot read songs.json: {}", e))
+        .and_then(|data| validate(&data));
+    let theirs = match parsed {
+        Ok(songs) => {
+            state.persistence.loaded(SONGS_FILE);
+            songs
+        }
+        Err(e) => {
+            error!(error = %e, "not reloading songs.json");
+            state.persistence.load_failed(SONGS_FILE, &e);
+            let mut status = state.reload.lock();
+            status.error = Some(e);
+            status.rejected = stamp;
+            return;
+        }
+    };
+
+    apply(state, &path, stamp, theirs);
+}
+
+// Merge the songs read from the file as of `stamp` into memory. Skipped when
+// the server saved the file while it was being read, since the file then
+// already holds the in-memory songs.
+fn apply(state: &AppState, path: &Path, stamp: Option<FileStamp>, theirs: Vec<Song>) {
+    // Lock order: ratings before songs
+    let ratings = state.ratings.read();
+    let mut songs = state.songs.write();
+
+    // Saved by the server while the file was being read: nothing to reload
+    if FileStamp::of(path) != stamp || songs.stamp() == stamp {
+        return;
+    }
+
+    let before = songs.to_vec();
+    let merge = merge(songs.synced(), &songs.to_vec(), theirs);
+    let sequence = songs.sequence();
+    songs.replace(merge.songs, sequence);
+    for song in songs.iter_mut() {
+        refresh_song_stats(song, &ratings);
+    }
+    songs.mark_synced(stamp);
+
+    // Write back the in-memory changes that were waiting for this reload
+    if merge.merged > 0 {
+        save_songs(&state.persistence, &mut songs);
+    }
+
+    for conflict in &merge.conflicts {
+        warn!(
+            song_id = conflict.song_id,
+            "song was changed both in memory and in songs.json; keeping the file's version"
+        );
+    }
+    info!(
+        changed = merge.changed,
+        merged = merge.merged,
+        conflicts = merge.conflicts.len(),
+        "reloaded songs.json"
+    );
+    audit::record(
+        state,
+        "file",
+        "reload",
+        audit::diff(&before, &songs.to_vec()),
+    );
+
+    *state.reload.lock() = ReloadStatus {
+        reloaded_at: Some(Utc::now().to_rfc3339()),
+        changed: merge.changed,
+        merged: merge.merged,
+        conflicts: merge.conflicts,
+        error: None,
+        rejected: None,
+    };
+}
+
+// Show the outcome of the last reload of songs.json
+#[utoipa::path(
+    get,
+    path = "/admin/reload",
+    tag = "admin",
+    responses((status = 200, description = "Last reload status", body = ReloadStatus))
+)]
+pub async fn handle_reload_status(State(state): State<Arc<AppState>>) -> Json<ReloadStatus> {
+    Json(state.reload.lock().clone())
+}
+
+// Reload songs.json now instead of waiting for the next check
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    tag = "admin",
+    responses((status = 200, description = "Reload status after checking the file", body = ReloadStatus))
+)]
+pub async fn handle_reload(State(state): State<Arc<AppState>>) -> Json<ReloadStatus> {
+    let state = tokio::task::spawn_blocking(move || {
+        reload(&state, true);
+        state
+    })
+    .await
+    .unwrap();
+    Json(state.reload.lock().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::handle_readyz;
+    use crate::{Config, NewSongRequest, add_song, load_state};
+    use axum::http::StatusCode;
+
+    fn song(id: u64, title: &str, plays: u64) -> Song {
+        Song {
+            id,
+            uid: None,
+            title: title.to_string(),
+            artist: "Reloader".to_string(),
+            album: None,
+            genre: "Pop".to_string(),
+            play_count: plays,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        }
+    }
+
+    fn titles(merge: &Merge) -> Vec<(u64, &str)> {
+        merge
+            .songs
+            .iter()
+            .map(|s| (s.id, s.title.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn a_song_changed_on_one_side_takes_that_side() {
+        let base = [song(1, "A", 0), song(2, "B", 0)];
+
+        // Changed in the file only
+        let merge = merge(&base, &base, vec![song(1, "A!", 0), song(2, "B", 0)]);
+        assert_eq!(titles(&merge), [(1, "A!"), (2, "B")]);
+        assert_eq!((merge.changed, merge.merged), (1, 0));
+        assert!(merge.conflicts.is_empty());
+
+        // Changed in memory only: kept and written back
+        let ours = [song(1, "A", 0), song(2, "B", 3)];
+        let merge = super::merge(&base, &ours, base.to_vec());
+        assert_eq!(merge.songs[1].play_count, 3);
+        assert_eq!((merge.changed, merge.merged), (0, 1));
+    }
+
+    #[test]
+    fn a_song_changed_on_both_sides_is_merged_field_by_field() {
+        let base = [song(1, "A", 0)];
+        let ours = [song(1, "A", 2)];
+        let merge = merge(&base, &ours, vec![song(1, "Fixed", 0)]);
+
+        assert_eq!(titles(&merge), [(1, "Fixed")]);
+        assert_eq!(merge.songs[0].play_count, 2);
+        assert_eq!((merge.changed, merge.merged), (1, 1));
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn the_same_change_on_both_sides_is_not_a_conflict() {
+        let base = [song(1, "A", 0)];
+        let ours = [song(1, "Same", 1)];
+        let merge = merge(&base, &ours, ours.to_vec());
+
+        assert_eq!(titles(&merge), [(1, "Same")]);
+        assert_eq!((merge.changed, merge.merged), (0, 0));
+        assert!(merge.conflicts.is_empty());
+
+        // Also field by field: both renamed it alike, only memory played it
+        let ours = [song(1, "Same", 4)];
+        let merge = super::merge(&base, &ours, vec![song(1, "Same", 0)]);
+        assert_eq!(merge.songs[0].play_count, 4);
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn conflicting_changes_keep_the_file() {
+        let base = [song(1, "A", 0)];
+        let ours = [song(1, "Ours", 0)];
+        let merge = merge(&base, &ours, vec![song(1, "Theirs", 0)]);
+
+        assert_eq!(titles(&merge), [(1, "Theirs")]);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].ours.as_ref().unwrap().title, "Ours");
+        assert_eq!(merge.merged, 0);
+    }
+
+    #[test]
+    fn songs_added_on_both_sides() {
+        let base = [song(1, "A", 0)];
+
+        // Added under the same id on both sides: the file wins
+        let ours = [song(1, "A", 0), song(2, "Ours", 0)];
+        let merge = merge(&base, &ours, vec![song(1, "A", 0), song(2, "Theirs", 0)]);
+        assert_eq!(titles(&merge), [(1, "A"), (2, "Theirs")]);
+        assert_eq!(merge.conflicts.len(), 1);
+
+        // Added in memory only: appended after the file's songs
+        let ours = [song(3, "New", 0), song(1, "A", 0)];
+        let merge = super::merge(&base, &ours, vec![song(4, "Typed", 0), song(1, "A", 0)]);
+        assert_eq!(titles(&merge), [(4, "Typed"), (1, "A"), (3, "New")]);
+        assert_eq!((merge.changed, merge.merged), (1, 1));
+    }
+
+    #[test]
+    fn songs_removed_from_the_file() {
+        let base = [song(1, "A", 0), song(2, "B", 0)];
+
+        // Unchanged in memory: removed
+        let merge = merge(&base, &base, vec![song(1, "A", 0)]);
+        assert_eq!(titles(&merge), [(1, "A")]);
+        assert_eq!(merge.changed, 1);
+        assert!(merge.conflicts.is_empty());
+
+        // Changed in memory: still removed, but reported
+        let ours = [song(1, "A", 0), song(2, "B", 5)];
+        let merge = super::merge(&base, &ours, vec![song(1, "A", 0)]);
+        assert_eq!(titles(&merge), [(1, "A")]);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert!(merge.conflicts[0].theirs.is_none());
+
+        // Removed in memory, unchanged in the file: stays removed
+        let merge = super::merge(&base, &base[..1], base.to_vec());
+        assert_eq!(titles(&merge), [(1, "A")]);
+        assert_eq!(merge.merged, 1);
+    }
+
+    #[test]
+    fn public_ids_dropped_from_the_file_are_kept() {
+        let mut with_uid = song(1, "A", 0);
+        with_uid.uid = Some("01HUID".to_string());
+        let base = [with_uid.clone()];
+        let merge = merge(&base, &base, vec![song(1, "A", 0)]);
+        assert_eq!(merge.songs[0].uid.as_deref(), Some("01HUID"));
+        assert_eq!(merge.changed, 0);
+    }
+
+    #[test]
+    fn a_file_saved_by_the_server_while_reading_is_not_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let add = |title: &str| {
+            let song = NewSongRequest {
+                title: title.to_string(),
+                artist: "Reloader".to_string(),
+                genre: "Pop".to_string(),
+                album: None,
+            };
+            add_song(&state, "alice", song)
+        };
+        add("First");
+
+        // The reload reads the file the server just saved...
+        let path = state.persistence.path(SONGS_FILE);
+        let stamp = FileStamp::of(&path);
+        let theirs = validate(&fs::read_to_string(&path).unwrap()).unwrap();
+        // ...and the server saves it again before the reload takes the lock
+        add("Saved meanwhile");
+        apply(&state, &path, stamp, theirs);
+        assert_eq!(state.songs.read().len(), 2);
+
+        // The file as last written by the server is not reloaded either
+        let stamp = FileStamp::of(&path);
+        assert_eq!(state.songs.read().stamp(), stamp);
+        apply(&state, &path, stamp, Vec::new());
+        assert_eq!(state.songs.read().len(), 2);
+
+        // An edit that is still on disk is
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&path, "[]").unwrap();
+        apply(&state, &path, FileStamp::of(&path), Vec::new());
+        assert!(state.songs.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_rejected_file_fails_readiness_and_unsaved_songs_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _) = load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            ..Default::default()
+        });
+        let add = |title: &str| {
+            let song = NewSongRequest {
+                title: title.to_string(),
+                artist: "Reloader".to_string(),
+                genre: "Pop".to_string(),
+                album: None,
+            };
+            add_song(&state, "alice", song)
+        };
+        let readiness = || async {
+            let (status, Json(readiness)) = handle_readyz(State(state.clone())).await;
+            (status, serde_json::to_value(readiness).unwrap())
+        };
+        add("Kept");
+
+        let path = state.persistence.path(SONGS_FILE);
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&path, "not json").unwrap();
+        reload(&state, false);
+        assert!(state.reload.lock().error.is_some());
+        let (status, body) = readiness().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            (
+                body["data_loaded"].as_bool(),
+                body["saves_current"].as_bool()
+            ),
+            (Some(false), Some(true))
+        );
+
+        // Saves wait for the file to be fixed
+        add("Added meanwhile");
+        let (_, body) = readiness().await;
+        assert_eq!(body["saves_current"], false);
+
+        // Stopping now writes the songs next to the rejected file
+        let side = crate::save_on_exit(&state).unwrap();
+        let saved = validate(&fs::read_to_string(side).unwrap()).unwrap();
+        let titles: Vec<&str> = saved.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(titles, ["Kept", "Added meanwhile"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not json");
+
+        // Fixing the file merges the waiting changes and clears both checks
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(&path, "[]").unwrap();
+        reload(&state, false);
+        let (status, body) = readiness().await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(state.songs.read().len(), 1);
+        assert!(crate::save_on_exit(&state).is_none());
+    }
+}
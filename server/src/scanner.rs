@@ -0,0 +1,604 @@
+use crate::library::Library;
+use crate::{AudioFile, Song};
+use id3::TagLike;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+// Tags read from an audio file (missing fields fall back to defaults)
+#[derive(Debug, Clone, Default)]
+struct AudioTags {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+}
+
+// An audio file found on disk, with its tags if they had to be (re)read
+#[derive(Debug)]
+pub struct ScannedFile {
+    file: AudioFile,
+    tags: Option<AudioTags>,
+}
+
+// A file that could not be read during a scan, or a music directory that
+// could not be scanned
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ScanError {
+    path: String,
+    error: String,
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.error)
+    }
+}
+
+// Summary of a scan, returned by the admin endpoint and printed by the CLI
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ScanSummary {
+    added: usize,
+    updated: usize,
+    moved: usize,
+    unchanged: usize,
+    missing: usize,
+    errors: Vec<ScanError>,
+}
+
+// Files found on disk under a music directory
+#[derive(Debug)]
+pub struct ScanResult {
+    root: String,
+    files: Vec<ScannedFile>,
+    errors: Vec<ScanError>,
+}
+
+// Walk `dir` and collect every MP3/FLAC file. Tags are only read for files
+// that are new or whose size/modification time changed since the last scan.
+pub fn walk(dir: &Path, songs: &[Song]) -> Result<ScanResult, ScanError> {
+    let dir_error = |error: String| ScanError {
+        path: dir.display().to_string(),
+        error,
+    };
+    let root = fs::canonicalize(dir).map_err(|e| dir_error(e.to_string()))?;
+    if !root.is_dir() {
+        return Err(dir_error("not a directory".to_string()));
+    }
+
+    let known: HashMap<&str, &AudioFile> = songs
+        .iter()
+        .filter_map(|song| song.file.as_ref())
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+
+    // Symbolic links are not followed: one could point outside MUSIC_DIRS
+    for entry in WalkDir::new(&root) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(ScanError {
+                    path: e
+                        .path()
+                        .map(|p| p.display().to_string())
+                        .unwrap_or_default(),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let path = entry.path();
+        if !entry.file_type().is_file() || audio_format(path).is_none() {
+            continue;
+        }
+
+        let file = match audio_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                errors.push(ScanError {
+                    path: path.display().to_string(),
+                    error: e,
+                });
+                continue;
+            }
+        };
+
+        // Unchanged files keep their existing metadata
+        let unchanged = known
+            .get(file.path.as_str())
+            .is_some_and(|old| old.size == file.size && old.modified == file.modified);
+
+        let tags = if unchanged {
+            None
+        } else {
+            match read_tags(path) {
+                Ok(tags) => Some(tags),
+                Err(e) => {
+                    errors.push(ScanError {
+                        path: file.path.clone(),
+                        error: e,
+                    });
+                    continue;
+                }
+            }
+        };
+
+        files.push(ScannedFile { file, tags });
+    }
+
+    Ok(ScanResult {
+        root: root.display().to_string(),
+        files,
+        errors,
+    })
+}
+
+// Whether `dir` is one of the `roots` or inside one. Symbolic links and `..`
+// are resolved first, so they cannot lead outside the roots; a path that
+// cannot be resolved, e.g. one that does not exist, is never allowed.
+pub fn allowed(roots: &[PathBuf], dir: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    roots
+        .iter()
+        .filter_map(|root| fs::canonicalize(root).ok())
+        .any(|root| dir.starts_with(root))
+}
+
+// Upsert the scanned files into the library. Songs are matched by file path,
+// then by identical size and tags (a moved file), then by title and artist
+// (a song added by hand that now gets linked to its file). Each match is a
+// map lookup, so a scan stays linear in the number of songs and files.
+pub fn apply(songs: &mut Library, result: ScanResult) -> ScanSummary {
+    let mut summary = ScanSummary {
+        errors: result.errors,
+        ..Default::default()
+    };
+
+    let root = Path::new(&result.root);
+    let found: HashSet<&str> = result.files.iter().map(|f| f.file.path.as_str()).collect();
+
+    let mut by_path: HashMap<String, usize> = HashMap::new();
+    // Songs linked to a file under this root that no longer exists, by size,
+    // title and artist
+    let mut vanished: HashMap<(u64, String, String), Vec<usize>> = HashMap::new();
+    // Songs without a file, by case-insensitive title and artist
+    let mut unlinked: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    for (idx, song) in songs.iter().enumerate() {
+        match &song.file {
+            Some(file)
+                if Path::new(&file.path).starts_with(root)
+                    && !found.contains(file.path.as_str()) =>
+            {
+                let key = (file.size, song.title.clone(), song.artist.clone());
+                vanished.entry(key).or_default().push(idx);
+            }
+            Some(file) => {
+                by_path.insert(file.path.clone(), idx);
+            }
+            None => {
+                let key = (
+                    song.title.to_ascii_lowercase(),
+                    song.artist.to_ascii_lowercase(),
+                );
+                unlinked.entry(key).or_default().push(idx);
+            }
+        }
+    }
+
+    for scanned in &result.files {
+        let Some(tags) = &scanned.tags else {
+            summary.unchanged += 1;
+            continue;
+        };
+
+        let title = tags
+            .title
+            .clone()
+            .unwrap_or_else(|| file_stem(&scanned.file.path));
+        let artist = tags
+            .artist
+            .clone()
+            .unwrap_or_else(|| "Unknown Artist".to_string());
+        let album = tags.album.clone();
+        let genre = tags.genre.clone().unwrap_or_else(|| "Unknown".to_string());
+
+        if let Some(&idx) = by_path.get(&scanned.file.path) {
+            let song = &mut songs[idx];
+            song.title = title;
+            song.artist = artist;
+            song.album = album;
+            song.genre = genre;
+            song.file = Some(scanned.file.clone());
+            summary.updated += 1;
+            continue;
+        }
+
+        let moved = vanished
+            .get_mut(&(scanned.file.size, title.clone(), artist.clone()))
+            .and_then(|candidates| candidates.pop());
+        if let Some(idx) = moved {
+            songs[idx].album = album;
+            songs[idx].genre = genre;
+            songs[idx].file = Some(scanned.file.clone());
+            summary.moved += 1;
+            continue;
+        }
+
+        let key = (title.to_ascii_lowercase(), artist.to_ascii_lowercase());
+        let linked = unlinked
+            .get_mut(&key)
+            .filter(|candidates| !candidates.is_empty())
+            .map(|candidates| candidates.remove(0));
+        if let Some(idx) = linked {
+            songs[idx].file = Some(scanned.file.clone());
+            summary.updated += 1;
+            continue;
+        }
+
+        let id = songs.allocate_id();
+        songs.push(Song {
+            id,
+            uid: None,
+            title,
+            artist,
+            album,
+            genre,
+            play_count: 0,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: Some(scanned.file.clone()),
+        });
+        summary.added += 1;
+    }
+
+    // Deleted files: keep the song (and its play count) but drop the link
+    for idx in vanished.into_values().flatten() {
+        songs[idx].file = None;
+        summary.missing += 1;
+    }
+
+    summary
+}
+
+fn audio_format(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "mp3" => Some("mp3"),
+        "flac" => Some("flac"),
+        _ => None,
+    }
+}
+
+fn audio_file(path: &Path) -> Result<AudioFile, String> {
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    Ok(AudioFile {
+        path: path.display().to_string(),
+        size: metadata.len(),
+        modified,
+    })
+}
+
+fn file_stem(path: &str) -> String {
+    PathBuf::from(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_tags(path: &Path) -> Result<AudioTags, String> {
+    match audio_format(path) {
+        Some("mp3") => read_id3(path),
+        Some("flac") => read_vorbis_comments(path),
+        _ => Ok(AudioTags::default()),
+    }
+}
+
+// ID3v2 (falling back to ID3v1) tags of an MP3 file
+fn read_id3(path: &Path) -> Result<AudioTags, String> {
+    let tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => return Ok(AudioTags::default()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    Ok(AudioTags {
+        title: non_empty(tag.title()),
+        artist: non_empty(tag.artist()),
+        album: non_empty(tag.album()),
+        genre: non_empty(tag.genre_parsed().as_deref()),
+    })
+}
+
+// Vorbis comments stored in the VORBIS_COMMENT metadata block of a FLAC file
+fn read_vorbis_comments(path: &Path) -> Result<AudioTags, String> {
+    let mut reader = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    if &magic != b"fLaC" {
+        return Err("not a FLAC file".to_string());
+    }
+
+    let mut tags = AudioTags::default();
+    loop {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+
+        let mut block = vec![0u8; len];
+        reader.read_exact(&mut block).map_err(|e| e.to_string())?;
+
+        if block_type == 4 {
+            for (key, value) in parse_vorbis_comments(&block)? {
+                let slot = match key.to_ascii_uppercase().as_str() {
+                    "TITLE" => &mut tags.title,
+                    "ARTIST" => &mut tags.artist,
+                    "ALBUM" => &mut tags.album,
+                    "GENRE" => &mut tags.genre,
+                    _ => continue,
+                };
+                if slot.is_none() {
+                    *slot = non_empty(Some(&value));
+                }
+            }
+            break;
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    Ok(tags)
+}
+
+fn parse_vorbis_comments(block: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let mut pos = 0;
+    let read_u32 = |pos: &mut usize| -> Result<usize, String> {
+        let bytes = block
+            .get(*pos..*pos + 4)
+            .ok_or("truncated Vorbis comment block")?;
+        *pos += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    };
+
+    let vendor_len = read_u32(&mut pos)?;
+    pos += vendor_len;
+
+    let count = read_u32(&mut pos)?;
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        let len = read_u32(&mut pos)?;
+        let raw = block
+            .get(pos..pos + len)
+            .ok_or("truncated Vorbis comment block")?;
+        pos += len;
+
+        let comment = String::from_utf8_lossy(raw);
+        if let Some((key, value)) = comment.split_once('=') {
+            comments.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    Ok(comments)
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A minimal FLAC file: STREAMINFO followed by a VORBIS_COMMENT block
+    fn flac(comments: &[&str]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&6u32.to_le_bytes());
+        block.extend_from_slice(b"vendor");
+        block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            block.extend_from_slice(comment.as_bytes());
+        }
+
+        let mut bytes = b"fLaC".to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 34]);
+        bytes.extend_from_slice(&[0; 34]);
+        bytes.push(0x80 | 4);
+        bytes.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        bytes.extend_from_slice(&block);
+        bytes
+    }
+
+    fn mp3(path: &Path, title: &str, artist: &str) {
+        let mut tag = id3::Tag::new();
+        tag.set_title(title);
+        tag.set_artist(artist);
+        fs::write(path, b"").unwrap();
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+    }
+
+    fn scan(dir: &Path, songs: &mut Library) -> ScanSummary {
+        let result = walk(dir, &songs.to_vec()).unwrap();
+        apply(songs, result)
+    }
+
+    #[test]
+    fn flac_vorbis_comments_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        fs::write(
+            &path,
+            flac(&[
+                "title=Night Drive",
+                "ARTIST=The Tapes",
+                "ARTIST=Someone Else",
+                "GENRE= ",
+                "NOEQUALS",
+            ]),
+        )
+        .unwrap();
+
+        let tags = read_tags(&path).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Night Drive"));
+        // The first value of a repeated field wins, blank ones are ignored
+        assert_eq!(tags.artist.as_deref(), Some("The Tapes"));
+        assert_eq!(tags.album, None);
+        assert_eq!(tags.genre, None);
+    }
+
+    #[test]
+    fn broken_flac_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+
+        fs::write(&path, b"ID3 not a flac").unwrap();
+        assert_eq!(read_tags(&path).unwrap_err(), "not a FLAC file");
+
+        // The comment claims to be longer than the block
+        let mut bytes = flac(&["TITLE=Cut"]);
+        let len = bytes.len();
+        bytes[len - 13] = 200;
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(
+            read_tags(&path).unwrap_err(),
+            "truncated Vorbis comment block"
+        );
+
+        // The file ends inside a metadata block
+        fs::write(&path, &flac(&["TITLE=Cut"])[..20]).unwrap();
+        assert!(read_tags(&path).is_err());
+    }
+
+    #[test]
+    fn rescans_add_skip_move_and_drop_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        mp3(&root.join("one.mp3"), "One", "Band");
+        fs::write(root.join("two.flac"), flac(&["TITLE=Two", "ARTIST=Band"])).unwrap();
+        fs::write(root.join("notes.txt"), b"not music").unwrap();
+
+        let mut songs = Library::default();
+        let summary = scan(root, &mut songs);
+        assert_eq!(summary.added, 2);
+        assert!(summary.errors.is_empty());
+
+        let summary = scan(root, &mut songs);
+        assert_eq!((summary.added, summary.unchanged), (0, 2));
+
+        fs::create_dir(root.join("moved")).unwrap();
+        fs::rename(root.join("one.mp3"), root.join("moved/one.mp3")).unwrap();
+        fs::remove_file(root.join("two.flac")).unwrap();
+        let summary = scan(root, &mut songs);
+        assert_eq!((summary.added, summary.moved, summary.missing), (0, 1, 1));
+
+        let one = songs.iter().find(|s| s.title == "One").unwrap();
+        assert!(one.file.as_ref().unwrap().path.ends_with("moved/one.mp3"));
+        let two = songs.iter().find(|s| s.title == "Two").unwrap();
+        assert!(two.file.is_none());
+        assert_eq!(songs.len(), 2);
+    }
+
+    #[test]
+    fn scanned_files_link_to_songs_added_by_hand() {
+        let dir = tempfile::tempdir().unwrap();
+        mp3(&dir.path().join("intro.mp3"), "Intro", "Band");
+
+        let mut songs = Library::default();
+        songs.push(Song {
+            id: 1,
+            uid: None,
+            title: "intro".to_string(),
+            artist: "BAND".to_string(),
+            album: None,
+            genre: "Rock".to_string(),
+            play_count: 3,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        });
+
+        let summary = scan(dir.path(), &mut songs);
+        assert_eq!((summary.added, summary.updated), (0, 1));
+        assert_eq!(songs.len(), 1);
+        assert!(songs[0].file.is_some());
+    }
+
+    #[test]
+    fn walk_reports_the_directory_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let error = walk(&missing, &[]).unwrap_err();
+        assert_eq!(error.path, missing.display().to_string());
+        assert!(error.error.contains("No such file"), "{}", error.error);
+
+        let file = dir.path().join("file.mp3");
+        fs::write(&file, b"").unwrap();
+        assert_eq!(walk(&file, &[]).unwrap_err().error, "not a directory");
+    }
+
+    #[test]
+    fn only_folders_under_the_roots_are_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("music");
+        fs::create_dir_all(music.join("rock")).unwrap();
+        let roots = vec![music.clone()];
+
+        assert!(allowed(&roots, &music));
+        assert!(allowed(&roots, &music.join("rock")));
+        assert!(!allowed(&roots, dir.path()));
+        assert!(!allowed(&roots, &music.join("rock/../..")));
+        assert!(!allowed(&[], &music));
+        // Unresolvable paths are refused rather than compared as given
+        assert!(!allowed(&roots, &music.join("missing")));
+        assert!(!allowed(&[music.join("missing")], &music.join("missing")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_do_not_lead_outside_the_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let music = dir.path().join("music");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&music).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        mp3(&music.join("inside.mp3"), "Inside", "Band");
+        mp3(&outside.join("secret.mp3"), "Secret", "Band");
+        std::os::unix::fs::symlink(&outside, music.join("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.mp3"), music.join("file.mp3")).unwrap();
+
+        let mut songs = Library::default();
+        let summary = scan(&music, &mut songs);
+        assert_eq!(summary.added, 1);
+        assert_eq!(songs[0].title, "Inside");
+
+        let roots = vec![music.clone()];
+        assert!(!allowed(&roots, &music.join("linked")));
+    }
+}
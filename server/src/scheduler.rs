@@ -0,0 +1,330 @@
+use crate::config::Config;
+use crate::{AppState, ErrorMessage, audit, plays, queue, snapshot, trending};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+// Periodic maintenance jobs, run in-process. Each job has its own interval
+// (zero disables the timer) and can also be run on demand through
+// POST /admin/jobs/NAME/run. A job never runs twice at the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Job {
+    // Drop audit entries past the retention period
+    CompactHistory,
+    // Delete snapshots beyond the retention count
+    PruneSnapshots,
+    // Recompute the trending songs
+    Trending,
+    // Save play counts counted in memory
+    CheckpointPlays,
+    // Save webhook dead letters collected in memory
+    SaveDeadLetters,
+    // Drop play queues that have not been used for a while
+    ExpireQueues,
+}
+
+impl Job {
+    const ALL: [Job; 6] = [
+        Job::CompactHistory,
+        Job::PruneSnapshots,
+        Job::Trending,
+        Job::CheckpointPlays,
+        Job::SaveDeadLetters,
+        Job::ExpireQueues,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Job::CompactHistory => "compact-history",
+            Job::PruneSnapshots => "prune-snapshots",
+            Job::Trending => "trending",
+            Job::CheckpointPlays => "checkpoint-plays",
+            Job::SaveDeadLetters => "save-dead-letters",
+            Job::ExpireQueues => "expire-queues",
+        }
+    }
+
+    fn interval(self, config: &Config) -> Duration {
+        match self {
+            Job::CompactHistory => config.history_compact_interval,
+            Job::PruneSnapshots => config.snapshot_prune_interval,
+            Job::Trending => config.trending_interval,
+            Job::CheckpointPlays => config.play_checkpoint_interval,
+            Job::SaveDeadLetters => config.dead_letter_save_interval,
+            Job::ExpireQueues => config.queue_expire_interval,
+        }
+    }
+
+    // Do the work, returning a short summary of what was done
+    fn run(self, state: &AppState) -> Result<String, String> {
+        match self {
+            Job::CompactHistory => {
+                let days = state.config.history_retention_days;
+                let removed = audit::compact(state, chrono::Duration::days(days as i64));
+                Ok(format!("{} audit entries removed", removed))
+            }
+            Job::PruneSnapshots => {
+                let dir = state.config.snapshot_path();
+                let removed = snapshot::prune(&dir, state.config.snapshot_retention);
+                Ok(format!("{} snapshots removed", removed.len()))
+            }
+            Job::Trending => Ok(trending::recompute(state)),
+            Job::CheckpointPlays => {
+                if plays::checkpoint(state) {
+                    Ok("play counts saved".to_string())
+                } else {
+                    Err("some play counts could not be saved yet".to_string())
+                }
+            }
+            Job::SaveDeadLetters => {
+                if state.webhooks.save_dead_letters(&state.persistence) {
+                    Ok("dead letters saved".to_string())
+                } else {
+                    Err("dead letters could not be saved".to_string())
+                }
+            }
+            Job::ExpireQueues => Ok(format!("{} queues expired", queue::expire(state))),
+        }
+    }
+}
+
+// Schedule and outcome of a job
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    name: &'static str,
+    // Seconds between scheduled runs (0 when only run on demand)
+    interval_secs: u64,
+    running: bool,
+    runs: u64,
+    last_run_at: Option<String>,
+    last_duration_ms: Option<f64>,
+    // Summary of the last successful run
+    last_result: Option<String>,
+    // Why the last run failed, if it did
+    last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    jobs: Mutex<Vec<JobStatus>>,
+}
+
+impl Scheduler {
+    pub fn new(config: &Config) -> Scheduler {
+        let jobs = Job::ALL
+            .iter()
+            .map(|job| JobStatus {
+                name: job.name(),
+                interval_secs: job.interval(config).as_secs(),
+                running: false,
+                runs: 0,
+                last_run_at: None,
+                last_duration_ms: None,
+                last_result: None,
+                last_error: None,
+            })
+            .collect();
+        Scheduler {
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    // Mark a job as running; false if it already is
+    fn start(&self, job: Job) -> bool {
+        let mut jobs = self.jobs.lock();
+        let status = jobs.iter_mut().find(|s| s.name == job.name()).unwrap();
+        !std::mem::replace(&mut status.running, true)
+    }
+
+    fn finish(&self, job: Job, started: Instant, result: Result<String, String>) -> JobStatus {
+        let mut jobs = self.jobs.lock();
+        let status = jobs.iter_mut().find(|s| s.name == job.name()).unwrap();
+        status.running = false;
+        status.runs += 1;
+        status.last_run_at = Some(Utc::now().to_rfc3339());
+        status.last_duration_ms = Some(started.elapsed().as_secs_f64() * 1000.0);
+        match result {
+            Ok(summary) => {
+                status.last_result = Some(summary);
+                status.last_error = None;
+            }
+            Err(e) => status.last_error = Some(e),
+        }
+        status.clone()
+    }
+}
+
+// Run a job on a blocking thread unless it is already running. Returns the
+// updated status, or `None` if the job was busy.
+async fn run_job(state: Arc<AppState>, job: Job) -> Option<JobStatus> {
+    if !state.jobs.start(job) {
+        return None;
+    }
+
+    let started = Instant::now();
+    let task_state = state.clone();
+    let result = tokio::task::spawn_blocking(move || job.run(&task_state))
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()));
+
+    match &result {
+        Ok(summary) => info!(job = job.name(), summary, "job finished"),
+        Err(e) => warn!(job = job.name(), error = %e, "job failed"),
+    }
+    Some(state.jobs.finish(job, started, result))
+}
+
+// Start the timer of every job with a non-zero interval
+pub fn start(state: &Arc<AppState>) {
+    for job in Job::ALL {
+        let interval = job.interval(&state.config);
+        if interval.is_zero() {
+            continue;
+        }
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            // The first run is at startup, which also records the baseline
+            // for the trending songs
+            let mut timer = tokio::time::interval(interval);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                timer.tick().await;
+                run_job(state.clone(), job).await;
+            }
+        });
+    }
+}
+
+// List the scheduled jobs with their last run
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "admin",
+    responses((status = 200, description = "Every job", body = [JobStatus]))
+)]
+pub async fn handle_jobs_list(State(state): State<Arc<AppState>>) -> Json<Vec<JobStatus>> {
+    Json(state.jobs.jobs.lock().clone())
+}
+
+// Run a job now and wait for it to finish
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{name}/run",
+    tag = "admin",
+    params(("name" = String, Path, description = "Job name, e.g. `trending`")),
+    responses(
+        (status = 200, description = "The job's status after the run", body = JobStatus),
+        (status = 404, description = "No job with this name", body = ErrorMessage),
+        (status = 409, description = "The job is already running", body = ErrorMessage),
+    )
+)]
+pub async fn handle_jobs_run(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<Json<JobStatus>, (StatusCode, Json<ErrorMessage>)> {
+    let Some(job) = Job::ALL.into_iter().find(|job| job.name() == name) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorMessage {
+                error: "Job not found",
+            }),
+        ));
+    };
+
+    run_job(state, job).await.map(Json).ok_or((
+        StatusCode::CONFLICT,
+        Json(ErrorMessage {
+            error: "Job is already running",
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_state;
+
+    fn setup(dir: &std::path::Path) -> Arc<AppState> {
+        let (state, _receiver) = load_state(Config {
+            data_dir: dir.to_path_buf(),
+            ..Default::default()
+        });
+        state
+    }
+
+    async fn run(state: &Arc<AppState>, name: &str) -> Result<JobStatus, StatusCode> {
+        handle_jobs_run(State(state.clone()), Path(name.to_string()))
+            .await
+            .map(|Json(status)| status)
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn a_running_job_is_not_started_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+
+        assert!(state.jobs.start(Job::ExpireQueues));
+        assert!(!state.jobs.start(Job::ExpireQueues));
+        assert_eq!(
+            run(&state, "expire-queues").await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        // Other jobs are not blocked
+        assert!(run(&state, "trending").await.is_ok());
+
+        state
+            .jobs
+            .finish(Job::ExpireQueues, Instant::now(), Ok("done".to_string()));
+        let status = run(&state, "expire-queues").await.unwrap();
+        assert_eq!((status.running, status.runs), (false, 2));
+        assert_eq!(status.last_result.as_deref(), Some("0 queues expired"));
+    }
+
+    #[test]
+    fn a_failed_run_keeps_the_last_successful_result() {
+        let scheduler = Scheduler::new(&Config::default());
+        let job = Job::CheckpointPlays;
+
+        assert!(scheduler.start(job));
+        scheduler.finish(job, Instant::now(), Ok("play counts saved".to_string()));
+        assert!(scheduler.start(job));
+        let status = scheduler.finish(job, Instant::now(), Err("disk full".to_string()));
+        assert_eq!(status.runs, 2);
+        assert!(!status.running);
+        assert!(status.last_run_at.is_some());
+        assert_eq!(status.last_result.as_deref(), Some("play counts saved"));
+        assert_eq!(status.last_error.as_deref(), Some("disk full"));
+
+        // The next success clears the error
+        assert!(scheduler.start(job));
+        let status = scheduler.finish(job, Instant::now(), Ok("play counts saved".to_string()));
+        assert_eq!(status.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn unknown_jobs_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = setup(dir.path());
+
+        assert_eq!(
+            run(&state, "defragment").await.unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        let Json(jobs) = handle_jobs_list(State(state)).await;
+        let names: Vec<&str> = jobs.iter().map(|j| j.name).collect();
+        assert_eq!(names, Job::ALL.map(Job::name));
+        assert!(jobs.iter().all(|j| j.runs == 0));
+    }
+}
@@ -0,0 +1,622 @@
+use crate::audit::{self, Actor, SongChange};
+use crate::config::Config;
+use crate::health::Persistence;
+use crate::libraries;
+use crate::library::{self, Library, LibraryFiles, Sequence};
+use crate::playlists::{self, Playlist};
+use crate::ratings::{RATINGS_FILE, UserRating};
+use crate::{AppState, ErrorMessage, Song, load_library, save_json, save_songs};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+// Everything needed to bring `AppState` back to a point in time. Webhooks
+// are left out on purpose: they are configuration, hold signing secrets, and
+// restoring data should not change where its events are delivered.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    created_at: String,
+    visit_count: usize,
+    #[serde(default)]
+    sequence: Sequence,
+    #[serde(serialize_with = "library::serialize_stored")]
+    songs: Vec<Song>,
+    ratings: Vec<UserRating>,
+    // Missing from snapshots taken before they were included; restoring one
+    // of those leaves the playlists and named libraries as they are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    playlists: Option<Vec<Playlist>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    libraries: Option<BTreeMap<String, LibrarySnapshot>>,
+}
+
+// The songs of a named library
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LibrarySnapshot {
+    #[serde(default)]
+    sequence: Sequence,
+    #[serde(serialize_with = "library::serialize_stored")]
+    songs: Vec<Song>,
+}
+
+impl LibrarySnapshot {
+    fn capture(library: &Library) -> LibrarySnapshot {
+        LibrarySnapshot {
+            sequence: library.sequence(),
+            songs: library.to_vec(),
+        }
+    }
+}
+
+// A snapshot file as listed by GET /admin/snapshots
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SnapshotInfo {
+    name: String,
+    size: u64,
+}
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+// Snapshot names embed a UTC timestamp, so they sort chronologically
+fn is_snapshot_name(name: &str) -> bool {
+    name.starts_with("snapshot-")
+        && name.ends_with(".json")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+// Write a snapshot to a new timestamped file. The file is written under a
+// temporary name first so a crash never leaves a half-written snapshot.
+pub fn write(dir: &FsPath, snapshot: &Snapshot) -> Result<SnapshotInfo, String> {
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let name = format!("snapshot-{}.json", Utc::now().format("%Y%m%dT%H%M%S%3fZ"));
+    let path = dir.join(&name);
+    let tmp = dir.join(format!(".{}.tmp", name));
+
+    let json = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+    fs::write(&tmp, &json).map_err(|e| e.to_string())?;
+    fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+
+    Ok(SnapshotInfo {
+        name,
+        size: json.len() as u64,
+    })
+}
+
+// List snapshots, newest first
+pub fn list(dir: &FsPath) -> Vec<SnapshotInfo> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut snapshots: Vec<SnapshotInfo> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !is_snapshot_name(&name) {
+                return None;
+            }
+            let size = entry.metadata().ok()?.len();
+            Some(SnapshotInfo { name, size })
+        })
+        .collect();
+
+    snapshots.sort_by(|a, b| b.name.cmp(&a.name));
+    snapshots
+}
+
+// Read and validate a snapshot by name
+pub fn read(dir: &FsPath, name: &str) -> Result<Snapshot, String> {
+    if !is_snapshot_name(name) {
+        return Err(format!("invalid snapshot name: {}", name));
+    }
+
+    let data = fs::read_to_string(dir.join(name)).map_err(|e| e.to_string())?;
+    serde_json::from_str(&data).map_err(|e| e.to_string())
+}
+
+// Delete all but the `keep` newest snapshots
+pub fn prune(dir: &FsPath, keep: usize) -> Vec<String> {
+    let mut removed = Vec::new();
+
+    for old in list(dir).into_iter().skip(keep) {
+        match fs::remove_file(dir.join(&old.name)) {
+            Ok(()) => removed.push(old.name),
+            Err(e) => warn!(name = %old.name, error = %e, "failed to prune snapshot"),
+        }
+    }
+
+    removed
+}
+
+// Capture the current state. The locks are held together so the songs,
+// ratings and playlists in the snapshot are consistent with each other.
+fn capture(state: &AppState) -> Snapshot {
+    // Lock order: ratings, songs, then playlists
+    let ratings = state.ratings.read();
+    let songs = state.songs.read();
+    let playlists = state.playlists.read();
+    let libraries = state
+        .libraries
+        .read()
+        .iter()
+        .map(|(name, library)| (name.clone(), LibrarySnapshot::capture(&library.read())))
+        .collect();
+
+    Snapshot {
+        created_at: Utc::now().to_rfc3339(),
+        visit_count: state.visit_count.load(Ordering::SeqCst),
+        sequence: songs.sequence(),
+        songs: songs.to_vec(),
+        ratings: ratings.clone(),
+        playlists: Some(playlists.to_vec()),
+        libraries: Some(libraries),
+    }
+}
+
+// Bring the named libraries back to a snapshot, creating those that have
+// been lost since. Libraries cannot be removed, so one created after the
+// snapshot is emptied instead. `record` is called with each library's
+// changes.
+fn restore_libraries(
+    persistence: &Persistence,
+    config: &Config,
+    libraries: &mut BTreeMap<String, Arc<RwLock<Library>>>,
+    mut snapshot: BTreeMap<String, LibrarySnapshot>,
+    mut record: impl FnMut(&str, Vec<SongChange>),
+) {
+    for name in libraries.keys() {
+        snapshot.entry(name.clone()).or_default();
+    }
+
+    for (name, restored) in snapshot {
+        if !libraries.contains_key(&name) {
+            match libraries::create(persistence, config, &name) {
+                Ok(library) => {
+                    libraries.insert(name.clone(), Arc::new(RwLock::new(library)));
+                }
+                Err(e) => {
+                    warn!(%name, error = %e, "failed to recreate library");
+                    continue;
+                }
+            }
+        }
+
+        let mut library = libraries[&name].write();
+        let before = library.to_vec();
+        library.replace(restored.songs, restored.sequence);
+        save_songs(persistence, &mut library);
+        record(&name, audit::diff(&before, &library.to_vec()));
+    }
+}
+
+// Swap a snapshot into the running state and persist it. The songs that
+// changed are recorded in the audit log like any other catalogue change, which
+// also notifies webhooks of the songs added and removed.
+fn apply(state: &AppState, user: &str, snapshot: Snapshot) {
+    // Lock order: ratings, songs, playlists, then the audit log
+    let mut ratings = state.ratings.write();
+    let mut songs = state.songs.write();
+
+    let before = songs.to_vec();
+    *ratings = snapshot.ratings;
+    songs.replace(snapshot.songs, snapshot.sequence);
+    state
+        .visit_count
+        .store(snapshot.visit_count, Ordering::SeqCst);
+
+    save_json(&state.persistence, RATINGS_FILE, &*ratings);
+    save_songs(&state.persistence, &mut songs);
+    if let Some(restored) = snapshot.playlists {
+        state
+            .playlists
+            .write()
+            .restore(restored, &state.persistence);
+    }
+    audit::record(
+        state,
+        user,
+        "restore",
+        audit::diff(&before, &songs.to_vec()),
+    );
+
+    if let Some(restored) = snapshot.libraries {
+        let mut libraries = state.libraries.write();
+        restore_libraries(
+            &state.persistence,
+            &state.config,
+            &mut libraries,
+            restored,
+            |name, changes| audit::record_in(state, Some(name), user, "restore", changes),
+        );
+    }
+}
+
+fn snapshot_dir(state: &AppState) -> PathBuf {
+    state.config.snapshot_path()
+}
+
+// Take a snapshot of the running state, then apply the retention policy
+#[utoipa::path(
+    post,
+    path = "/admin/snapshots",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The snapshot that was written", body = SnapshotInfo),
+        (status = 500, description = "The snapshot could not be written", body = ErrorMessage),
+    )
+)]
+pub async fn handle_snapshot_create(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SnapshotInfo>, ApiError> {
+    let dir = snapshot_dir(&state);
+    let snapshot = capture(&state);
+
+    let info = write(&dir, &snapshot).map_err(|e| {
+        warn!(error = %e, "failed to write snapshot");
+        error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to write snapshot",
+        )
+    })?;
+    prune(&dir, state.config.snapshot_retention);
+
+    info!(name = %info.name, "snapshot created");
+    Ok(Json(info))
+}
+
+// List the available snapshots, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/snapshots",
+    tag = "admin",
+    responses((status = 200, description = "Snapshots, newest first", body = [SnapshotInfo]))
+)]
+pub async fn handle_snapshot_list(State(state): State<Arc<AppState>>) -> Json<Vec<SnapshotInfo>> {
+    Json(list(&snapshot_dir(&state)))
+}
+
+// Restore a snapshot into the running server
+#[utoipa::path(
+    post,
+    path = "/admin/snapshots/{name}/restore",
+    tag = "admin",
+    params(("name" = String, Path, description = "Snapshot file name")),
+    responses(
+        (status = 200, description = "The snapshot that was restored", body = SnapshotInfo),
+        (status = 404, description = "No snapshot with this name", body = ErrorMessage),
+        (status = 422, description = "The snapshot file is not valid", body = ErrorMessage),
+    )
+)]
+pub async fn handle_snapshot_restore(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Actor(user): Actor,
+) -> Result<Json<SnapshotInfo>, ApiError> {
+    let dir = snapshot_dir(&state);
+    if !list(&dir).iter().any(|s| s.name == name) {
+        return Err(error(StatusCode::NOT_FOUND, "Snapshot not found"));
+    }
+
+    let snapshot = read(&dir, &name).map_err(|e| {
+        warn!(%name, error = %e, "failed to read snapshot");
+        error(StatusCode::UNPROCESSABLE_ENTITY, "Snapshot is not valid")
+    })?;
+    apply(&state, &user, snapshot);
+
+    info!(%name, "snapshot restored");
+    Ok(Json(SnapshotInfo {
+        size: fs::metadata(dir.join(&name)).map_or(0, |m| m.len()),
+        name,
+    }))
+}
+
+// `server snapshot`: snapshot the data files on disk and exit
+pub fn run_create(config: &Config, dir: &FsPath) -> Result<(), String> {
+    let persistence = Persistence::new(&config.data_dir);
+    let songs = load_library(&persistence, config, LibraryFiles::default());
+    let libraries = libraries::load_all(&persistence, config)
+        .into_iter()
+        .map(|(name, library)| (name, LibrarySnapshot::capture(&library.read())))
+        .collect();
+    let snapshot = Snapshot {
+        created_at: Utc::now().to_rfc3339(),
+        visit_count: 0,
+        sequence: songs.sequence(),
+        songs: songs.to_vec(),
+        ratings: crate::load_json(&persistence, RATINGS_FILE),
+        playlists: Some(playlists::load(&persistence).to_vec()),
+        libraries: Some(libraries),
+    };
+
+    let info = write(dir, &snapshot)?;
+    println!("Created {}", info.name);
+    for name in prune(dir, config.snapshot_retention) {
+        println!("Pruned {}", name);
+    }
+    Ok(())
+}
+
+// `server snapshots`: list the snapshots on disk
+pub fn run_list(dir: &FsPath) {
+    for snapshot in list(dir) {
+        println!("{}\t{} bytes", snapshot.name, snapshot.size);
+    }
+}
+
+// `server restore <name>`: overwrite the data files with a snapshot
+pub fn run_restore(config: &Config, dir: &FsPath, name: &str) -> Result<(), String> {
+    let snapshot = read(dir, name)?;
+
+    let persistence = Persistence::new(&config.data_dir);
+    let mut songs = load_library(&persistence, config, LibraryFiles::default());
+    let before = songs.to_vec();
+    songs.replace(snapshot.songs, snapshot.sequence);
+
+    save_json(&persistence, RATINGS_FILE, &snapshot.ratings);
+    save_songs(&persistence, &mut songs);
+    audit::record_to_file(
+        &persistence,
+        None,
+        "cli",
+        "restore",
+        audit::diff(&before, &songs.to_vec()),
+    );
+
+    if let Some(restored) = snapshot.playlists {
+        playlists::load(&persistence).restore(restored, &persistence);
+    }
+    if let Some(restored) = snapshot.libraries {
+        let mut libraries = libraries::load_all(&persistence, config);
+        restore_libraries(
+            &persistence,
+            config,
+            &mut libraries,
+            restored,
+            |name, changes| {
+                audit::record_to_file(&persistence, Some(name), "cli", "restore", changes)
+            },
+        );
+    }
+
+    println!("Restored {} ({} songs)", name, songs.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, NewSongRequest, add_song, load_state};
+    use std::time::Duration;
+
+    fn state(dir: &FsPath) -> Arc<AppState> {
+        load_state(Config {
+            data_dir: dir.to_path_buf(),
+            snapshot_retention: 2,
+            ..Default::default()
+        })
+        .0
+    }
+
+    fn add(state: &AppState, title: &str) -> Song {
+        let song = NewSongRequest {
+            title: title.to_string(),
+            artist: "Snapper".to_string(),
+            genre: "Pop".to_string(),
+            album: None,
+        };
+        add_song(state, "alice", song)
+    }
+
+    async fn create(state: &Arc<AppState>) -> String {
+        // Snapshot names have millisecond resolution
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        handle_snapshot_create(State(state.clone()))
+            .await
+            .unwrap()
+            .0
+            .name
+    }
+
+    #[test]
+    fn write_list_read_and_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let names: Vec<String> = (0..3)
+            .map(|visits| {
+                std::thread::sleep(Duration::from_millis(2));
+                let snapshot = Snapshot {
+                    created_at: Utc::now().to_rfc3339(),
+                    visit_count: visits,
+                    sequence: Sequence::default(),
+                    songs: Vec::new(),
+                    ratings: Vec::new(),
+                    playlists: None,
+                    libraries: None,
+                };
+                write(dir.path(), &snapshot).unwrap().name
+            })
+            .collect();
+        fs::write(dir.path().join("notes.txt"), "not a snapshot").unwrap();
+
+        let listed: Vec<String> = list(dir.path()).into_iter().map(|s| s.name).collect();
+        assert_eq!(listed, names.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!(read(dir.path(), &names[1]).unwrap().visit_count, 1);
+        assert!(read(dir.path(), "../songs.json").is_err());
+
+        // The newest are kept
+        assert_eq!(
+            prune(dir.path(), 1),
+            vec![names[1].clone(), names[0].clone()]
+        );
+        assert_eq!(list(dir.path()).len(), 1);
+        assert!(dir.path().join("notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn restore_brings_back_songs_and_records_the_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let kept = add(&state, "Before");
+        let name = create(&state).await;
+        let added = add(&state, "After");
+
+        let restored = handle_snapshot_restore(
+            State(state.clone()),
+            Path(name.clone()),
+            Actor("bob".to_string()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(restored.0.name, name);
+
+        let songs = state.songs.read().to_vec();
+        assert_eq!(songs, vec![kept]);
+        // Ids stay monotonic across the restore
+        assert!(state.songs.write().allocate_id() > added.id);
+
+        let entry = serde_json::to_value(state.audit.read().last().unwrap()).unwrap();
+        assert_eq!(entry["user"], "bob");
+        assert_eq!(entry["action"], "restore");
+        assert_eq!(entry["changes"][0]["song_id"], added.id);
+
+        // Persisted: a restart sees the restored songs
+        let reloaded = load_state(state.config.clone()).0;
+        assert_eq!(reloaded.songs.read().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn restore_covers_playlists_and_named_libraries_but_not_webhooks() {
+        use crate::libraries::{handle_libraries_create, handle_library_songs_new};
+        use crate::playlists::handle_playlists_create;
+        use crate::webhooks::{handle_webhooks_create, handle_webhooks_list};
+        use serde_json::json;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        let song = add(&state, "Listed");
+        let new_library = |name: &str| {
+            handle_libraries_create(
+                State(state.clone()),
+                Json(serde_json::from_value(json!({"name": name})).unwrap()),
+            )
+        };
+        let new_song = |lib: &str, title: &str| {
+            handle_library_songs_new(
+                State(state.clone()),
+                Path(lib.to_string()),
+                Actor("alice".to_string()),
+                Json(NewSongRequest {
+                    title: title.to_string(),
+                    artist: "Snapper".to_string(),
+                    genre: "Pop".to_string(),
+                    album: None,
+                }),
+            )
+        };
+        let new_playlist = |name: &str| {
+            handle_playlists_create(
+                State(state.clone()),
+                Actor("alice".to_string()),
+                Json(serde_json::from_value(json!({"name": name, "song_ids": [song.id]})).unwrap()),
+            )
+        };
+
+        assert!(new_library("live").await.is_ok());
+        assert!(new_song("live", "Kept").await.is_ok());
+        new_playlist("Before").await;
+        let name = create(&state).await;
+
+        assert!(new_song("live", "Dropped").await.is_ok());
+        assert!(new_library("later").await.is_ok());
+        assert!(new_song("later", "Emptied").await.is_ok());
+        new_playlist("After").await;
+        let hook = handle_webhooks_create(
+            State(state.clone()),
+            Json(serde_json::from_value(json!({"url": "http://localhost:1/hook"})).unwrap()),
+        )
+        .await;
+        assert!(hook.is_ok());
+
+        let restored =
+            handle_snapshot_restore(State(state.clone()), Path(name), Actor("bob".to_string()))
+                .await;
+        assert!(restored.is_ok());
+
+        let playlists = serde_json::to_value(&*state.playlists.read().clone()).unwrap();
+        assert_eq!(playlists[0]["name"], "Before");
+        assert_eq!(playlists.as_array().unwrap().len(), 1);
+        let titles = |lib: &str| -> Vec<String> {
+            let library = state.libraries.read()[lib].clone();
+            library.read().iter().map(|s| s.title.clone()).collect()
+        };
+        assert_eq!(titles("live"), vec!["Kept"]);
+        // A library created after the snapshot is emptied, not removed
+        assert!(titles("later").is_empty());
+        let restores: Vec<_> = state
+            .audit
+            .read()
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .filter(|e| e["action"] == "restore")
+            .map(|e| e["library"].clone())
+            .collect();
+        // The default library did not change, so only the named ones are
+        // recorded
+        assert_eq!(restores, vec![json!("later"), json!("live")]);
+
+        // Webhooks are configuration and are not part of snapshots
+        assert_eq!(handle_webhooks_list(State(state.clone())).await.0.len(), 1);
+
+        // A playlist created after the restore does not reuse the id of the
+        // one the restore dropped
+        new_playlist("Again").await;
+        let playlists = serde_json::to_value(&*state.playlists.read().clone()).unwrap();
+        assert_eq!(playlists[1]["id"], playlists[0]["id"].as_u64().unwrap() + 2);
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_pruned_and_bad_names_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path());
+        for _ in 0..3 {
+            create(&state).await;
+        }
+        let Json(listed) = handle_snapshot_list(State(state.clone())).await;
+        assert_eq!(listed.len(), 2);
+
+        let missing = handle_snapshot_restore(
+            State(state.clone()),
+            Path("snapshot-19700101T000000000Z.json".to_string()),
+            Actor("bob".to_string()),
+        )
+        .await;
+        assert_eq!(missing.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        fs::write(state.config.snapshot_path().join("snapshot-x.json"), "{").unwrap();
+        let invalid = handle_snapshot_restore(
+            State(state.clone()),
+            Path("snapshot-x.json".to_string()),
+            Actor("bob".to_string()),
+        )
+        .await;
+        assert_eq!(invalid.unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -0,0 +1,309 @@
+use crate::libraries::{self, DEFAULT_LIBRARY};
+use crate::{AppState, ErrorMessage};
+use axum::{
+    Json,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use std::io::SeekFrom;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// Result of parsing a `Range` request header against a file size
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    // No (usable) range: serve the whole file
+    Full,
+    // Inclusive byte range to serve with 206 Partial Content
+    Partial(u64, u64),
+    // Range outside the file: 416 Range Not Satisfiable
+    Unsatisfiable,
+}
+
+// Parse a single `bytes=start-end`, `bytes=start-` or `bytes=-suffix` range.
+// Malformed headers and multi-range requests fall back to the whole file.
+fn parse_range(header: &str, size: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", "") => return ByteRange::Full,
+        ("", suffix) => {
+            let Ok(suffix) = suffix.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            if suffix == 0 || size == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = if end.is_empty() {
+                size.saturating_sub(1)
+            } else {
+                match end.parse::<u64>() {
+                    Ok(end) if end >= start => end.min(size.saturating_sub(1)),
+                    _ => return ByteRange::Full,
+                }
+            };
+            if start >= size {
+                return ByteRange::Unsatisfiable;
+            }
+            (start, end)
+        }
+    };
+
+    ByteRange::Partial(start, end)
+}
+
+// Counts the bytes a stream has actually sent, to decide when it makes a
+// play. Only bytes sent in the same response add up: a 1-byte range at the
+// play mark, or a seek past it, is not a play.
+#[derive(Debug)]
+struct PlayMeter {
+    // Bytes to send before a play is counted
+    threshold: u64,
+    sent: u64,
+    counted: bool,
+}
+
+impl PlayMeter {
+    fn new(size: u64, portion: f64) -> PlayMeter {
+        let threshold = ((size as f64 * portion).ceil() as u64).max(1);
+        PlayMeter {
+            threshold,
+            sent: 0,
+            // An empty file is never played
+            counted: size == 0,
+        }
+    }
+
+    // Record `bytes` more sent; true exactly once, when the play is reached
+    fn add(&mut self, bytes: u64) -> bool {
+        self.sent += bytes;
+        if self.counted || self.sent < self.threshold {
+            return false;
+        }
+        self.counted = true;
+        true
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    let lower = path.to_ascii_lowercase();
+    if lower.ends_with(".mp3") {
+        "audio/mpeg"
+    } else if lower.ends_with(".flac") {
+        "audio/flac"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorMessage {
+            error: "Song not found",
+        }),
+    )
+        .into_response()
+}
+
+// Stream a song's audio file, honouring byte-range requests so players can
+// seek. A play is counted once one response has sent the configured portion
+// of the file (STREAM_PLAY_PORTION) to the client.
+#[utoipa::path(
+    get,
+    path = "/songs/{id}/stream",
+    tag = "songs",
+    params(
+        ("id" = u64, Path, description = "Song id"),
+        ("Range" = Option<String>, Header, description = "Single byte range, e.g. `bytes=0-1023`"),
+    ),
+    responses(
+        (status = 200, description = "The whole audio file"),
+        (status = 206, description = "The requested byte range"),
+        (status = 404, description = "No song with this id, or it has no audio file", body = ErrorMessage),
+        (status = 416, description = "Range outside the file"),
+    )
+)]
+pub async fn handle_songs_stream(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+    headers: HeaderMap,
+) -> Response {
+    stream(state, DEFAULT_LIBRARY.to_string(), id, headers).await
+}
+
+// Stream a song of a library; plays are counted in that library
+pub async fn stream(state: Arc<AppState>, lib: String, id: u64, headers: HeaderMap) -> Response {
+    let library = match libraries::library(&state, &lib) {
+        Ok(library) => library,
+        Err(e) => return e.into_response(),
+    };
+    let path = {
+        let songs = library.read();
+        match songs.get(id).and_then(|s| s.file.as_ref()) {
+            Some(file) => file.path.clone(),
+            None => return not_found(),
+        }
+    };
+
+    let Ok(mut file) = File::open(&path).await else {
+        return not_found();
+    };
+    let Ok(metadata) = file.metadata().await else {
+        return not_found();
+    };
+    let size = metadata.len();
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(ByteRange::Full, |v| parse_range(v, size));
+
+    let (status, start, end) = match range {
+        ByteRange::Full => (StatusCode::OK, 0, size.saturating_sub(1)),
+        ByteRange::Partial(start, end) => (StatusCode::PARTIAL_CONTENT, start, end),
+        ByteRange::Unsatisfiable => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [
+                    (header::CONTENT_RANGE, format!("bytes */{}", size)),
+                    (header::ACCEPT_RANGES, "bytes".to_string()),
+                ],
+            )
+                .into_response();
+        }
+    };
+    let len = if size == 0 { 0 } else { end - start + 1 };
+
+    if start > 0 && file.seek(SeekFrom::Start(start)).await.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let mut meter = PlayMeter::new(size, state.config.play_portion);
+    let body = ReaderStream::new(file.take(len)).map(move |chunk| {
+        if let Ok(bytes) = &chunk
+            && meter.add(bytes.len() as u64)
+        {
+            let _ = libraries::record_play_in(&state, &lib, id);
+        }
+        chunk
+    });
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::CONTENT_LENGTH, len)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, size),
+        );
+    }
+
+    response.body(Body::from_stream(body)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(
+            parse_range(" bytes=500-500 ", 1000),
+            ByteRange::Partial(500, 500)
+        );
+        // The end is clamped to the last byte
+        assert_eq!(
+            parse_range("bytes=900-5000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(parse_range("bytes=0-", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(
+            parse_range("bytes=999-", 1000),
+            ByteRange::Partial(999, 999)
+        );
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        // A suffix longer than the file is the whole file
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=2000-3000", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn unusable_ranges_serve_the_whole_file() {
+        for header in [
+            "items=0-10",
+            "bytes=",
+            "bytes=-",
+            "bytes=abc-10",
+            "bytes=0-abc",
+            "bytes=10-5",
+            "bytes=0-10,20-30",
+            "bytes=5",
+        ] {
+            assert_eq!(parse_range(header, 1000), ByteRange::Full, "{}", header);
+        }
+    }
+
+    #[test]
+    fn play_needs_the_portion_sent() {
+        let mut meter = PlayMeter::new(1000, 0.5);
+        assert!(!meter.add(499));
+        assert!(meter.add(1));
+        // Only counted once per stream
+        assert!(!meter.add(500));
+
+        // A single byte at the play mark is not a play
+        let mut meter = PlayMeter::new(1000, 0.5);
+        assert!(!meter.add(1));
+    }
+
+    #[test]
+    fn empty_file_is_never_played() {
+        let mut meter = PlayMeter::new(0, 0.5);
+        assert!(!meter.add(0));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::config::Config;
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use rustls::ServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+// Where the dev mode certificate is kept, so it survives restarts and
+// browsers only need to trust it once
+const DEV_CERT_FILE: &str = "tls/dev-cert.pem";
+const DEV_KEY_FILE: &str = "tls/dev-key.pem";
+
+// Build the rustls configuration from TLS_CERT/TLS_KEY, or from the
+// self-signed dev certificate when TLS_DEV is set
+pub fn server_config(config: &Config) -> Result<Arc<ServerConfig>, String> {
+    let (cert_path, key_path) = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => (cert.as_str(), key.as_str()),
+        (None, None) if config.tls_dev => {
+            ensure_dev_certificate(config)?;
+            (DEV_CERT_FILE, DEV_KEY_FILE)
+        }
+        _ => return Err("TLS_CERT and TLS_KEY must be set together".to_string()),
+    };
+
+    let certs = read_certs(cert_path)?;
+    let key = read_key(key_path)?;
+
+    let mut server_config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("cannot read {}: {}", path, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot read {}: {}", path, e))?
+        .ok_or_else(|| format!("no private key found in {}", path))
+}
+
+// Generate a self-signed certificate for localhost (and the bind address)
+// unless one was generated before
+fn ensure_dev_certificate(config: &Config) -> Result<(), String> {
+    if Path::new(DEV_CERT_FILE).exists() && Path::new(DEV_KEY_FILE).exists() {
+        return Ok(());
+    }
+
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    if !matches!(config.bind_address.as_str(), "0.0.0.0" | "::")
+        && !names.contains(&config.bind_address)
+    {
+        names.push(config.bind_address.clone());
+    }
+
+    let generated = rcgen::generate_simple_self_signed(names.clone())
+        .map_err(|e| format!("cannot generate certificate: {}", e))?;
+
+    fs::create_dir_all("tls").map_err(|e| format!("cannot create tls directory: {}", e))?;
+    fs::write(DEV_CERT_FILE, generated.cert.pem())
+        .map_err(|e| format!("cannot write {}: {}", DEV_CERT_FILE, e))?;
+    write_private(DEV_KEY_FILE, &generated.key_pair.serialize_pem())
+        .map_err(|e| format!("cannot write {}: {}", DEV_KEY_FILE, e))?;
+
+    info!(
+        cert = DEV_CERT_FILE,
+        ?names,
+        "generated self-signed certificate"
+    );
+    Ok(())
+}
+
+// Write a private key readable by the owner only
+fn write_private(path: &str, contents: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // The mode only applies to new files; tighten an existing one too
+        if Path::new(path).exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+// Accept TLS connections and serve the app on each of them until `shutdown`
+// resolves, then wait for the open connections to finish their requests.
+// Clients that do not complete the handshake within `handshake_timeout` are
+// dropped, so they cannot hold connections open.
+pub async fn serve(
+    listener: TcpListener,
+    tls: Arc<ServerConfig>,
+    app: Router,
+    handshake_timeout: Duration,
+    shutdown: impl Future<Output = ()>,
+) {
+    let acceptor = TlsAcceptor::from(tls);
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok(connection) => connection,
+                Err(e) => {
+                    // Usually out of file descriptors; back off instead of spinning
+                    warn!(error = %e, "failed to accept connection");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(app.clone());
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        debug!(%peer, error = %e, "TLS handshake failed");
+                        return;
+                    }
+                    Err(_) => {
+                        debug!(%peer, "TLS handshake timed out");
+                        return;
+                    }
+                };
+
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection(TokioIo::new(stream), service);
+            if let Err(e) = watcher.watch(connection).await {
+                debug!(%peer, error = %e, "connection closed with an error");
+            }
+        });
+    }
+
+    graceful.shutdown().await;
+}
+
+// Router for the plain HTTP listener when HTTPS is enabled: every request
+// is redirected to the same path on the HTTPS port
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new()
+        .fallback(handle_https_redirect)
+        .with_state(https_port)
+}
+
+async fn handle_https_redirect(
+    State(https_port): State<u16>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let Some(host) = headers.get(header::HOST).and_then(|v| v.to_str().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+
+    // Drop any port from the host, keeping bracketed IPv6 addresses intact
+    let host = match host.rfind(':') {
+        Some(idx) if !host[idx..].contains(']') => &host[..idx],
+        _ => host,
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+    let location = if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    };
+    Redirect::permanent(&location).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tokio::io::AsyncReadExt;
+    use tower::ServiceExt;
+
+    async fn redirect(https_port: u16, host: Option<&str>, uri: &str) -> Response {
+        let mut request = Request::get(uri);
+        if let Some(host) = host {
+            request = request.header(header::HOST, host);
+        }
+        redirect_router(https_port)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    fn location(response: &Response) -> &str {
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()[header::LOCATION].to_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirects_keep_the_host_and_path_on_the_https_port() {
+        let response = redirect(8443, Some("music.local:8080"), "/songs/search?q=a").await;
+        assert_eq!(
+            location(&response),
+            "https://music.local:8443/songs/search?q=a"
+        );
+
+        let response = redirect(8443, Some("music.local"), "/").await;
+        assert_eq!(location(&response), "https://music.local:8443/");
+
+        let response = redirect(8443, Some("[::1]:8080"), "/ui").await;
+        assert_eq!(location(&response), "https://[::1]:8443/ui");
+
+        let response = redirect(8443, Some("[::1]"), "/ui").await;
+        assert_eq!(location(&response), "https://[::1]:8443/ui");
+
+        // The default port is left out
+        let response = redirect(443, Some("music.local:8080"), "/count").await;
+        assert_eq!(location(&response), "https://music.local/count");
+    }
+
+    #[tokio::test]
+    async fn redirects_need_a_host() {
+        let response = redirect(8443, None, "/").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_keys_are_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.pem");
+        let path = path.to_str().unwrap();
+
+        write_private(path, "new").unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(path, "again").unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_to_string(path).unwrap(), "again");
+    }
+
+    #[tokio::test]
+    async fn stalled_handshakes_are_dropped() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivateKeyDer::try_from(generated.key_pair.serialize_der()).unwrap();
+        let tls =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![generated.cert.der().clone()], key)
+                .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(
+            listener,
+            Arc::new(tls),
+            Router::new(),
+            Duration::from_millis(100),
+            std::future::pending(),
+        ));
+
+        // Connect but never start the handshake: the server hangs up
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf))
+            .await
+            .expect("the server kept the stalled connection open");
+        assert!(matches!(read, Ok(0) | Err(_)));
+
+        server.abort();
+    }
+}
@@ -0,0 +1,100 @@
+use crate::negotiate::{Format, Negotiated};
+use crate::{AppState, Song};
+use axum::extract::{Query, State};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+// Songs kept in the trending list
+const TRENDING_SIZE: usize = 100;
+
+// Songs that gained the most plays between the last two runs of the
+// `trending` job (see `scheduler.rs`)
+#[derive(Debug, Default)]
+pub struct Trending {
+    // Play counts as of the last run
+    previous: HashMap<u64, u64>,
+    baseline: bool,
+    // Song ids with the plays gained, most first
+    songs: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrendingSong {
+    // Plays during the last trending window
+    recent_plays: u64,
+    song: Song,
+}
+
+// Structure for receiving trending list parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TrendingQuery {
+    /// Maximum number of songs (default 20)
+    limit: Option<usize>,
+}
+
+// Compare play counts with the previous run and keep the songs that gained
+// the most. The first run only records the counts.
+pub fn recompute(state: &AppState) -> String {
+    let current: HashMap<u64, u64> = state
+        .songs
+        .read()
+        .iter()
+        .map(|s| (s.id, s.plays()))
+        .collect();
+
+    let mut trending = state.trending.lock();
+    let mut songs: Vec<(u64, u64)> = current
+        .iter()
+        .map(|(&id, &plays)| {
+            let before = trending.previous.get(&id).copied().unwrap_or(0);
+            (id, plays.saturating_sub(before))
+        })
+        .filter(|&(_, gained)| gained > 0)
+        .collect();
+    songs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    songs.truncate(TRENDING_SIZE);
+
+    if !trending.baseline {
+        songs.clear();
+    }
+    let summary = format!("{} songs trending", songs.len());
+
+    trending.previous = current;
+    trending.baseline = true;
+    trending.songs = songs;
+    summary
+}
+
+// Songs played the most during the last trending window
+#[utoipa::path(
+    get,
+    path = "/songs/trending",
+    tag = "songs",
+    params(TrendingQuery),
+    responses((status = 200, description = "Trending songs, most recent plays first", body = [TrendingSong], content_type = ["application/json", "text/csv", "application/msgpack"]))
+)]
+pub async fn handle_songs_trending(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TrendingQuery>,
+    format: Format,
+) -> Negotiated<Vec<TrendingSong>> {
+    let limit = query.limit.unwrap_or(20);
+    let ids = state.trending.lock().songs.clone();
+
+    let songs = state.songs.read();
+    let trending = ids
+        .into_iter()
+        .filter_map(|(id, recent_plays)| {
+            Some(TrendingSong {
+                recent_plays,
+                song: songs.get(id)?.song(),
+            })
+        })
+        .take(limit)
+        .collect();
+
+    Negotiated(format, trending)
+}
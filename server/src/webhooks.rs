@@ -0,0 +1,782 @@
+use crate::health::Persistence;
+use crate::library::Sequence;
+use crate::plays::Unsaved;
+use crate::{AppState, ErrorMessage, Song, load_json, now_secs, save_json};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use parking_lot::{Mutex, RwLock};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, mpsc};
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+
+pub const WEBHOOKS_FILE: &str = "webhooks.json";
+pub const WEBHOOK_SEQUENCE_FILE: &str = "webhook_sequence.json";
+pub const DEAD_LETTERS_FILE: &str = "webhook_dead_letters.json";
+
+// Deliveries sent at the same time, across all webhooks
+const MAX_CONCURRENT_DELIVERIES: usize = 8;
+// Deliveries being attempted or waiting to retry, across all webhooks
+const MAX_PENDING_DELIVERIES: usize = 256;
+// Deliveries queued behind the pending ones; further events are moved
+// straight to the dead letters
+const QUEUE_CAPACITY: usize = 1024;
+// Dead letters kept; the oldest are dropped beyond this
+const MAX_DEAD_LETTERS: usize = 1000;
+// Longest wait between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// Library events a webhook can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub enum Event {
+    #[serde(rename = "song.added")]
+    Added,
+    #[serde(rename = "song.played")]
+    Played,
+    #[serde(rename = "song.deleted")]
+    Deleted,
+}
+
+const ALL_EVENTS: [Event; 3] = [Event::Added, Event::Played, Event::Deleted];
+
+// A registered webhook. Every delivery is signed with its secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    id: u64,
+    url: String,
+    secret: String,
+    events: Vec<Event>,
+    created_at: String,
+}
+
+// A webhook as listed by the admin endpoints (the secret is only shown once,
+// when the webhook is registered)
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookInfo {
+    id: u64,
+    url: String,
+    events: Vec<Event>,
+    created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+// Structure for receiving a webhook registration from POST JSON
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewWebhookRequest {
+    url: String,
+    // Events to receive (all when omitted)
+    events: Option<Vec<Event>>,
+    // Signing secret (generated when omitted)
+    secret: Option<String>,
+}
+
+// Body POSTed to a webhook URL
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct EventPayload {
+    // Unique per event; repeated on retries so receivers can deduplicate
+    id: String,
+    event: Event,
+    at: String,
+    // Named library the song belongs to (absent for the default library)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    library: Option<String>,
+    song: Song,
+}
+
+// One event on its way to one webhook
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Delivery {
+    id: String,
+    webhook_id: u64,
+    url: String,
+    payload: EventPayload,
+    attempts: u32,
+    // Error from the last attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    // When the delivery was given up on (seconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failed_at: Option<u64>,
+}
+
+// Registered webhooks, the delivery queue and deliveries that ran out of
+// retries
+#[derive(Debug)]
+pub struct Webhooks {
+    hooks: RwLock<Vec<Webhook>>,
+    // Next webhook id. Ids are never reused, so a delivery queued for a
+    // removed webhook cannot be sent with another webhook's secret.
+    next_id: Mutex<u64>,
+    dead_letters: Mutex<Vec<Delivery>>,
+    // Set when dead letters change; they are saved by the
+    // `save-dead-letters` job rather than on the request that added them.
+    // Saves hold `saving`, not the dead letters, so they never block emits.
+    unsaved: Unsaved,
+    saving: Mutex<()>,
+    queue: mpsc::Sender<Delivery>,
+}
+
+pub type Receiver = mpsc::Receiver<Delivery>;
+
+type ApiError = (StatusCode, Json<ErrorMessage>);
+
+fn error(status: StatusCode, error: &'static str) -> ApiError {
+    (status, Json(ErrorMessage { error }))
+}
+
+impl Webhook {
+    fn info(&self, with_secret: bool) -> WebhookInfo {
+        WebhookInfo {
+            id: self.id,
+            url: self.url.clone(),
+            events: self.events.clone(),
+            created_at: self.created_at.clone(),
+            secret: with_secret.then(|| self.secret.clone()),
+        }
+    }
+}
+
+impl Webhooks {
+    // Load registered webhooks, their id sequence and dead letters from
+    // disk. The receiver is handed to `run`, which performs the deliveries.
+    pub fn load(persistence: &Persistence) -> (Webhooks, Receiver) {
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let hooks: Vec<Webhook> = load_json(persistence, WEBHOOKS_FILE);
+        let sequence: Sequence = load_json(persistence, WEBHOOK_SEQUENCE_FILE);
+        // Never below the highest id in use, in case the sequence file is missing
+        let next_id = hooks
+            .iter()
+            .map(|h| h.id + 1)
+            .fold(sequence.next_id.max(1), u64::max);
+
+        let webhooks = Webhooks {
+            hooks: RwLock::new(hooks),
+            next_id: Mutex::new(next_id),
+            dead_letters: Mutex::new(load_json(persistence, DEAD_LETTERS_FILE)),
+            unsaved: Unsaved::default(),
+            saving: Mutex::new(()),
+            queue,
+        };
+        (webhooks, receiver)
+    }
+
+    // Queue an event about a song of the default library
+    pub fn emit(&self, event: Event, song: &Song) {
+        self.emit_in(None, event, song);
+    }
+
+    // Queue an event for every webhook subscribed to it. Never blocks; when
+    // the queue is full the delivery goes to the dead letters, where it can be
+    // retried later.
+    pub fn emit_in(&self, library: Option<&str>, event: Event, song: &Song) {
+        let hooks = self.hooks.read();
+        let mut subscribed = hooks
+            .iter()
+            .filter(|h| h.events.contains(&event))
+            .peekable();
+        if subscribed.peek().is_none() {
+            return;
+        }
+
+        let payload = EventPayload {
+            id: uuid::Uuid::new_v4().to_string(),
+            event,
+            at: Utc::now().to_rfc3339(),
+            library: library.map(str::to_string),
+            song: song.clone(),
+        };
+        for hook in subscribed {
+            let delivery = Delivery {
+                id: uuid::Uuid::new_v4().to_string(),
+                webhook_id: hook.id,
+                url: hook.url.clone(),
+                payload: payload.clone(),
+                attempts: 0,
+                last_error: None,
+                failed_at: None,
+            };
+            if let Err(mpsc::error::TrySendError::Full(mut delivery)) =
+                self.queue.try_send(delivery)
+            {
+                warn!(
+                    webhook_id = hook.id,
+                    "webhook queue is full; moved delivery to dead letters"
+                );
+                delivery.last_error = Some("delivery queue full".to_string());
+                delivery.failed_at = Some(now_secs());
+                self.dead_letter(delivery);
+            }
+        }
+    }
+
+    // Secret of the webhook a delivery was queued for, unless the webhook was
+    // removed or now points elsewhere
+    fn secret(&self, delivery: &Delivery) -> Option<String> {
+        let hooks = self.hooks.read();
+        hooks
+            .iter()
+            .find(|h| h.id == delivery.webhook_id && h.url == delivery.url)
+            .map(|h| h.secret.clone())
+    }
+
+    fn dead_letter(&self, delivery: Delivery) {
+        let mut dead_letters = self.dead_letters.lock();
+        dead_letters.push(delivery);
+        if dead_letters.len() > MAX_DEAD_LETTERS {
+            let excess = dead_letters.len() - MAX_DEAD_LETTERS;
+            dead_letters.drain(..excess);
+            error!(excess, "too many webhook dead letters; dropped the oldest");
+        }
+        self.unsaved.mark();
+    }
+
+    // Write the dead letters to disk if they changed since the last save,
+    // returning whether they are saved. A failed save is retried by the next
+    // call.
+    pub fn save_dead_letters(&self, persistence: &Persistence) -> bool {
+        let _saving = self.saving.lock();
+        if !self.unsaved.is_set() {
+            return true;
+        }
+        // Cleared before the copy, so dead letters added meanwhile are saved
+        // by the next call
+        self.unsaved.clear();
+        let dead_letters = self.dead_letters.lock().clone();
+        if !save_json(persistence, DEAD_LETTERS_FILE, &dead_letters) {
+            self.unsaved.mark();
+            return false;
+        }
+        true
+    }
+}
+
+// `X-Webhook-Signature` value: HMAC-SHA256 of "<timestamp>.<body>" keyed with
+// the webhook's secret, hex encoded
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Wait before the retry that follows attempt number `attempts`: the base
+// backoff doubled for every earlier retry, at most `MAX_BACKOFF`
+fn backoff(base: Duration, attempts: u32) -> Duration {
+    2u32.checked_pow(attempts.saturating_sub(1))
+        .and_then(|factor| base.checked_mul(factor))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+// Deliver queued events in the background until the server stops. A delivery
+// is only taken off the queue once there is room for it among the pending
+// deliveries, so a webhook that is down cannot pile up tasks.
+pub async fn run(state: Arc<AppState>, mut receiver: Receiver) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!(
+            "music-library-webhooks/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("HTTP client");
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+    let pending = Arc::new(Semaphore::new(MAX_PENDING_DELIVERIES));
+
+    loop {
+        let slot = pending.clone().acquire_owned().await.unwrap();
+        let Some(delivery) = receiver.recv().await else {
+            return;
+        };
+        let state = state.clone();
+        let client = client.clone();
+        let permits = permits.clone();
+        tokio::spawn(async move {
+            deliver(&state, &client, &permits, delivery).await;
+            drop(slot);
+        });
+    }
+}
+
+// Try a delivery until it succeeds, backing off exponentially between
+// attempts, and move it to the dead letters once it runs out of attempts
+async fn deliver(
+    state: &AppState,
+    client: &reqwest::Client,
+    permits: &Semaphore,
+    mut delivery: Delivery,
+) {
+    let max_attempts = state.config.webhook_max_attempts;
+    let body = serde_json::to_vec(&delivery.payload).unwrap();
+
+    loop {
+        // The webhook may have been removed while the delivery waited
+        let Some(secret) = state.webhooks.secret(&delivery) else {
+            debug!(
+                webhook_id = delivery.webhook_id,
+                "dropping delivery for removed webhook"
+            );
+            return;
+        };
+
+        delivery.attempts += 1;
+        let result = {
+            let _permit = permits.acquire().await.unwrap();
+            let timestamp = now_secs();
+            client
+                .post(&delivery.url)
+                .header("content-type", "application/json")
+                .header("x-webhook-event", event_name(delivery.payload.event))
+                .header("x-webhook-delivery", &delivery.id)
+                .header("x-webhook-timestamp", timestamp)
+                .header("x-webhook-signature", sign(&secret, timestamp, &body))
+                .body(body.clone())
+                .send()
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|response| match response.status() {
+                    status if status.is_success() => Ok(()),
+                    status => Err(format!("HTTP {}", status)),
+                })
+        };
+
+        match result {
+            Ok(()) => {
+                debug!(
+                    webhook_id = delivery.webhook_id,
+                    attempts = delivery.attempts,
+                    "webhook delivered"
+                );
+                return;
+            }
+            Err(e) if delivery.attempts >= max_attempts => {
+                warn!(webhook_id = delivery.webhook_id, error = %e, attempts = delivery.attempts, "webhook delivery failed; moved to dead letters");
+                delivery.last_error = Some(e);
+                delivery.failed_at = Some(now_secs());
+                state.webhooks.dead_letter(delivery);
+                return;
+            }
+            Err(e) => {
+                let backoff = backoff(state.config.webhook_backoff, delivery.attempts);
+                debug!(webhook_id = delivery.webhook_id, error = %e, ?backoff, "webhook delivery failed; retrying");
+                delivery.last_error = Some(e);
+                tokio::time::sleep(backoff).await;
+            }
+        }
+    }
+}
+
+fn event_name(event: Event) -> &'static str {
+    match event {
+        Event::Added => "song.added",
+        Event::Played => "song.played",
+        Event::Deleted => "song.deleted",
+    }
+}
+
+// List registered webhooks
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    responses((status = 200, description = "Registered webhooks", body = [WebhookInfo]))
+)]
+pub async fn handle_webhooks_list(State(state): State<Arc<AppState>>) -> Json<Vec<WebhookInfo>> {
+    let hooks = state.webhooks.hooks.read();
+    Json(hooks.iter().map(|h| h.info(false)).collect())
+}
+
+// Register a webhook URL. The response includes the signing secret.
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    request_body = NewWebhookRequest,
+    responses(
+        (status = 201, description = "The registered webhook, with its secret", body = WebhookInfo),
+        (status = 400, description = "Invalid URL or event list", body = ErrorMessage),
+    )
+)]
+pub async fn handle_webhooks_create(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<NewWebhookRequest>,
+) -> Result<(StatusCode, Json<WebhookInfo>), ApiError> {
+    let url = payload.url.trim();
+    if reqwest::Url::parse(url).map_or(true, |u| !matches!(u.scheme(), "http" | "https")) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "Webhook URL must be an http(s) URL",
+        ));
+    }
+
+    let events = payload.events.unwrap_or_else(|| ALL_EVENTS.to_vec());
+    if events.is_empty() {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "A webhook needs at least one event",
+        ));
+    }
+
+    let secret = match payload.secret.filter(|s| !s.is_empty()) {
+        Some(secret) => secret,
+        None => {
+            let mut bytes = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            hex::encode(bytes)
+        }
+    };
+
+    let mut hooks = state.webhooks.hooks.write();
+    let id = {
+        let mut next_id = state.webhooks.next_id.lock();
+        let id = *next_id;
+        *next_id += 1;
+        let sequence = Sequence {
+            next_id: *next_id,
+            ..Default::default()
+        };
+        save_json(&state.persistence, WEBHOOK_SEQUENCE_FILE, &sequence);
+        id
+    };
+    let webhook = Webhook {
+        id,
+        url: url.to_string(),
+        secret,
+        events,
+        created_at: Utc::now().to_rfc3339(),
+    };
+    hooks.push(webhook.clone());
+    save_json(&state.persistence, WEBHOOKS_FILE, &*hooks);
+
+    info!(id = webhook.id, url = %webhook.url, "webhook registered");
+    Ok((StatusCode::CREATED, Json(webhook.info(true))))
+}
+
+// Remove a webhook. Deliveries still queued for it are dropped.
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{id}",
+    tag = "admin",
+    params(("id" = u64, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "The webhook was removed"),
+        (status = 404, description = "No webhook with this id", body = ErrorMessage),
+    )
+)]
+pub async fn handle_webhooks_delete(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    let mut hooks = state.webhooks.hooks.write();
+    let Some(idx) = hooks.iter().position(|h| h.id == id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Webhook not found"));
+    };
+    hooks.remove(idx);
+    save_json(&state.persistence, WEBHOOKS_FILE, &*hooks);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// List deliveries that failed every attempt
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/dead-letters",
+    tag = "admin",
+    responses((status = 200, description = "Failed deliveries, oldest first", body = [Delivery]))
+)]
+pub async fn handle_dead_letters_list(State(state): State<Arc<AppState>>) -> Json<Vec<Delivery>> {
+    Json(state.webhooks.dead_letters.lock().clone())
+}
+
+// Queue a failed delivery again, with a fresh set of attempts
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/dead-letters/{id}/retry",
+    tag = "admin",
+    params(("id" = String, Path, description = "Delivery id")),
+    responses(
+        (status = 202, description = "The delivery was queued again"),
+        (status = 404, description = "No failed delivery with this id", body = ErrorMessage),
+        (status = 503, description = "The delivery queue is full", body = ErrorMessage),
+    )
+)]
+pub async fn handle_dead_letters_retry(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut dead_letters = state.webhooks.dead_letters.lock();
+    let Some(idx) = dead_letters.iter().position(|d| d.id == id) else {
+        return Err(error(StatusCode::NOT_FOUND, "Delivery not found"));
+    };
+
+    let mut delivery = dead_letters[idx].clone();
+    delivery.attempts = 0;
+    delivery.failed_at = None;
+    if state.webhooks.queue.try_send(delivery).is_err() {
+        return Err(error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Webhook delivery queue is full",
+        ));
+    }
+
+    dead_letters.remove(idx);
+    state.webhooks.unsaved.mark();
+    drop(dead_letters);
+    state.webhooks.save_dead_letters(&state.persistence);
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use axum::{Router, body::Bytes, http::HeaderMap, routing::post};
+    use std::collections::VecDeque;
+    use tempfile::TempDir;
+
+    // A local HTTP receiver that answers with the given statuses in turn
+    // (200 once they run out) and records every request
+    #[derive(Default)]
+    struct StandIn {
+        statuses: Mutex<VecDeque<u16>>,
+        received: Mutex<Vec<(HeaderMap, Bytes)>>,
+    }
+
+    async fn stand_in(statuses: &[u16]) -> (String, Arc<StandIn>) {
+        let stand_in = Arc::new(StandIn {
+            statuses: Mutex::new(statuses.iter().copied().collect()),
+            ..Default::default()
+        });
+        let recorder = stand_in.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                recorder.received.lock().push((headers, body));
+                let status = recorder.statuses.lock().pop_front().unwrap_or(200);
+                StatusCode::from_u16(status).unwrap()
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, stand_in)
+    }
+
+    fn state(dir: &TempDir, max_attempts: u32) -> (Arc<AppState>, Receiver) {
+        crate::load_state(Config {
+            data_dir: dir.path().to_path_buf(),
+            webhook_max_attempts: max_attempts,
+            webhook_backoff: Duration::from_millis(1),
+            ..Default::default()
+        })
+    }
+
+    async fn register(state: &Arc<AppState>, url: &str, events: Option<Vec<Event>>) -> u64 {
+        let request = NewWebhookRequest {
+            url: url.to_string(),
+            events,
+            secret: Some("topsecret".to_string()),
+        };
+        let (status, Json(info)) = handle_webhooks_create(State(state.clone()), Json(request))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        info.id
+    }
+
+    fn song() -> Song {
+        Song {
+            id: 7,
+            uid: None,
+            title: "Hook Line".to_string(),
+            artist: "The Senders".to_string(),
+            album: None,
+            genre: "Pop".to_string(),
+            play_count: 3,
+            average_rating: None,
+            rating_count: 0,
+            favourite_count: 0,
+            file: None,
+        }
+    }
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+        headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    async fn deliver_one(state: &AppState, delivery: Delivery) {
+        let client = reqwest::Client::new();
+        deliver(state, &client, &Semaphore::new(1), delivery).await;
+    }
+
+    #[test]
+    fn signature_is_hmac_of_timestamp_and_body() {
+        assert_eq!(
+            sign("topsecret", 1_700_000_000, br#"{"a":1}"#),
+            "sha256=6a939b0c71853d606167625a15168ee9188c6a511c773ef4f42d307f3849e50f"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_and_is_capped() {
+        let base = Duration::from_secs(1);
+        assert_eq!(backoff(base, 1), base);
+        assert_eq!(backoff(base, 3), Duration::from_secs(4));
+        assert_eq!(backoff(base, 40), MAX_BACKOFF);
+        assert_eq!(backoff(Duration::MAX, 2), MAX_BACKOFF);
+        assert_eq!(backoff(Duration::ZERO, 5), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn events_are_filtered_by_subscription() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = state(&dir, 1);
+        let id = register(&state, "http://127.0.0.1:9/hook", Some(vec![Event::Played])).await;
+
+        state.webhooks.emit(Event::Added, &song());
+        assert!(receiver.try_recv().is_err());
+
+        state.webhooks.emit(Event::Played, &song());
+        let delivery = receiver.try_recv().unwrap();
+        assert_eq!(delivery.webhook_id, id);
+        assert_eq!(delivery.payload.event, Event::Played);
+        assert_eq!(delivery.payload.song.id, 7);
+    }
+
+    #[tokio::test]
+    async fn retries_until_delivered_with_valid_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = state(&dir, 5);
+        let (url, stand_in) = stand_in(&[500, 503]).await;
+        register(&state, &url, None).await;
+
+        state.webhooks.emit(Event::Added, &song());
+        let delivery = receiver.try_recv().unwrap();
+        let delivery_id = delivery.id.clone();
+        deliver_one(&state, delivery).await;
+
+        let received = stand_in.received.lock();
+        assert_eq!(received.len(), 3);
+        for (headers, body) in received.iter() {
+            assert_eq!(header(headers, "x-webhook-event"), "song.added");
+            assert_eq!(header(headers, "x-webhook-delivery"), delivery_id);
+            let timestamp = header(headers, "x-webhook-timestamp").parse().unwrap();
+            assert_eq!(
+                header(headers, "x-webhook-signature"),
+                sign("topsecret", timestamp, body)
+            );
+        }
+        let payload: EventPayload = serde_json::from_slice(&received[0].1).unwrap();
+        assert_eq!(payload.song.title, "Hook Line");
+        assert!(state.webhooks.dead_letters.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn exhausted_deliveries_are_dead_lettered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = state(&dir, 2);
+        let (url, stand_in) = stand_in(&[500, 500, 500]).await;
+        register(&state, &url, None).await;
+
+        state.webhooks.emit(Event::Deleted, &song());
+        deliver_one(&state, receiver.try_recv().unwrap()).await;
+
+        assert_eq!(stand_in.received.lock().len(), 2);
+        let dead_letters = state.webhooks.dead_letters.lock().clone();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert_eq!(
+            dead_letters[0].last_error.as_deref(),
+            Some("HTTP 500 Internal Server Error")
+        );
+        assert!(dead_letters[0].failed_at.is_some());
+
+        // Persisted by the next save, and queued again with a fresh set of
+        // attempts on retry
+        let on_disk = |state: &AppState| {
+            let (reloaded, _) = crate::load_state(state.config.clone());
+            reloaded.webhooks.dead_letters.lock().len()
+        };
+        assert_eq!(on_disk(&state), 0);
+        assert!(state.webhooks.save_dead_letters(&state.persistence));
+        assert_eq!(on_disk(&state), 1);
+        let status =
+            handle_dead_letters_retry(State(state.clone()), Path(dead_letters[0].id.clone()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(receiver.try_recv().unwrap().attempts, 0);
+        assert!(state.webhooks.dead_letters.lock().is_empty());
+        assert_eq!(on_disk(&state), 0);
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_dead_letters_without_writing_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _receiver) = state(&dir, 1);
+        let (url, _) = stand_in(&[]).await;
+        register(&state, &url, None).await;
+
+        for _ in 0..QUEUE_CAPACITY + 3 {
+            state.webhooks.emit(Event::Played, &song());
+        }
+        assert_eq!(state.webhooks.dead_letters.lock().len(), 3);
+        let file = state.persistence.path(DEAD_LETTERS_FILE);
+        assert!(!file.exists());
+
+        // Written by the `save-dead-letters` job, once per change
+        assert!(state.webhooks.save_dead_letters(&state.persistence));
+        assert!(file.exists());
+        std::fs::remove_file(&file).unwrap();
+        assert!(state.webhooks.save_dead_letters(&state.persistence));
+        assert!(!file.exists());
+    }
+
+    #[tokio::test]
+    async fn ids_are_not_reused_and_stale_deliveries_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, mut receiver) = state(&dir, 1);
+        let (old_url, old_receiver) = stand_in(&[]).await;
+        let (new_url, new_receiver) = stand_in(&[]).await;
+
+        let old_id = register(&state, &old_url, None).await;
+        state.webhooks.emit(Event::Played, &song());
+        let stale = receiver.try_recv().unwrap();
+
+        handle_webhooks_delete(State(state.clone()), Path(old_id))
+            .await
+            .unwrap();
+        let new_id = register(&state, &new_url, None).await;
+        assert_ne!(new_id, old_id);
+
+        // Neither sent to the old URL with the new secret, nor to the new URL
+        deliver_one(&state, stale).await;
+        assert!(old_receiver.received.lock().is_empty());
+        assert!(new_receiver.received.lock().is_empty());
+
+        // A delivery whose URL no longer matches its webhook is dropped too
+        state.webhooks.emit(Event::Played, &song());
+        let mut moved = receiver.try_recv().unwrap();
+        assert_eq!(moved.webhook_id, new_id);
+        moved.url = old_url;
+        deliver_one(&state, moved).await;
+        assert!(old_receiver.received.lock().is_empty());
+
+        // The sequence survives a restart, even with every webhook removed
+        handle_webhooks_delete(State(state.clone()), Path(new_id))
+            .await
+            .unwrap();
+        let (reloaded, _) = crate::load_state(state.config.clone());
+        let next_id = register(&reloaded, &new_url, None).await;
+        assert!(next_id > new_id);
+    }
+}
@@ -0,0 +1,90 @@
+// Request and response types of the music library API, shared by the server
+// and the client crate. With the `schema` feature they also describe
+// themselves for the server's OpenAPI document.
+
+use serde::{Deserialize, Serialize};
+
+// Represents a song in the personal music library
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct Song {
+    pub id: u64,
+    // Opaque public id, only assigned when PUBLIC_IDS is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    pub title: String,
+    pub artist: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    pub genre: String,
+    pub play_count: u64,
+    #[serde(default)]
+    pub average_rating: Option<f64>,
+    #[serde(default)]
+    pub rating_count: u64,
+    #[serde(default)]
+    pub favourite_count: u64,
+    // Where the song was scanned from. This is a path on the server, so it is
+    // never sent to clients; the server keeps it in its own data files.
+    #[serde(default, skip_serializing)]
+    pub file: Option<AudioFile>,
+}
+
+// Audio file on disk that a song was scanned from. Only stored by the
+// server, so it is not part of the OpenAPI document.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AudioFile {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+}
+
+// Structure for receiving a new song request from POST JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct NewSongRequest {
+    pub title: String,
+    pub artist: String,
+    pub genre: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+}
+
+// Structure for receiving search query parameters
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::IntoParams, utoipa::ToSchema))]
+#[cfg_attr(feature = "schema", into_params(parameter_in = Query))]
+pub struct SongSearchQuery {
+    /// Search expression, e.g. `artist:"Taylor Swift" AND NOT genre:pop AND plays>10`
+    pub query: Option<String>,
+    /// Case-insensitive substring of the title
+    pub title: Option<String>,
+    /// Case-insensitive substring of the artist
+    pub artist: Option<String>,
+    /// Case-insensitive substring of the genre
+    pub genre: Option<String>,
+    /// Field to sort the results by
+    pub sort: Option<SongSort>,
+    /// Sort direction (numeric fields default to descending)
+    pub order: Option<SortOrder>,
+}
+
+// Fields that search results can be sorted by
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SongSort {
+    Id,
+    Title,
+    Plays,
+    Rating,
+    Favourites,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}